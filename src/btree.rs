@@ -55,19 +55,21 @@ A 4-byte big-endian page number which is the left child pointer.
 A varint which is the integer key
 
 */
-
-use anyhow::{bail, Result};
 use std::cell::RefCell;
-use std::io::{Seek, SeekFrom};
-use std::path::Iter;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::ops::{Bound, Range};
 use std::rc::Rc;
-use std::{convert::TryInto, fs::File, io::Read};
 
 use crate::cell::{
-    DataCell, IndexInteriorCell, IndexLeafCell, InteriorCell, TableInteriorCell, TableLeafCell,
+    first_overflow_page, local_payload_size, DataCell, IndexInteriorCell, IndexLeafCell,
+    InteriorCell, TableInteriorCell, TableLeafCell,
 };
-use crate::page::{BtreePage, PageHeader, PageType};
-use crate::record::ReadableRecord;
+use crate::error::{Result, StorageError};
+use crate::page::{read_u32, BtreePage, PageType};
+use crate::pager::Pager;
+use crate::record::{ReadableRecord, Record};
+use crate::sql_data_types::{compare_records, SerialData};
 use log::debug;
 
 /*
@@ -96,244 +98,2400 @@ use log::debug;
 *
 */
 
+// the pages from the root down to some page, each with the index of the child taken on the way down
+type BtreePath = Vec<(BtreePage, usize)>;
+
 #[derive(Debug, Clone)]
 enum BtreeType {
     Table,
     Index,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct Btree {
     btree_type: BtreeType,
-    db_file_name: String,
-    db_file_handle: Rc<RefCell<File>>,
-    page_size: usize,
-    root_page: BtreePage,
+    pager: Rc<RefCell<Pager>>,
+    root_page_num: u32,
 }
 
 impl Btree {
-    // schema table is special because it has an extra 100 bytes of database header
-    pub fn read_schema_table(
-        db_file_name: &str,
-        page_size: usize,
-        reserved_bytes_per_page: u8,
-    ) -> Result<Self> {
-        Btree::read_page_to_tree(db_file_name, page_size, 0, 100, reserved_bytes_per_page)
-    }
-
-    pub fn read_table(
-        db_file_name: &str,
-        page_size: usize,
-        page_offset: usize,
-        reserved_bytes_per_page: u8,
-    ) -> Result<Self> {
-        debug!("Reading Btree Root Page at Offset {}", page_offset);
-        Btree::read_page_to_tree(
-            db_file_name,
-            page_size,
-            page_offset,
-            0,
-            reserved_bytes_per_page,
-        )
+    // schema table is special because it has an extra 100 bytes of database header, it is always rooted on page 1
+    pub(crate) fn read_schema_table(pager: Rc<RefCell<Pager>>) -> Result<Self> {
+        Btree::open(pager, 1)
     }
 
-    fn read_page_to_tree(
-        db_file_name: &str,
-        page_size: usize,
-        page_offset: usize,
-        header_offset: usize,
-        reserved_bytes_per_page: u8,
-    ) -> Result<Self> {
-        let mut db_file_handle = File::open(db_file_name)?;
-        let mut buffer: Vec<u8> = vec![0; page_size];
-
-        // seek to offset page
-        if page_offset as u64
-            != db_file_handle.seek(std::io::SeekFrom::Start(page_offset.try_into()?))?
-        {
-            bail!("failed to seek to page offset");
-        }
-
-        if page_size != db_file_handle.read(&mut buffer)? {
-            bail!("failed to read expected bytes for table page");
-        }
-
-        let root_page = BtreePage::new(buffer, header_offset, reserved_bytes_per_page)?;
+    pub(crate) fn open(pager: Rc<RefCell<Pager>>, root_page_num: u32) -> Result<Self> {
+        debug!("Reading Btree Root Page {}", root_page_num);
+        let root_page = pager.borrow_mut().read_page(root_page_num)?;
 
         Ok(Btree {
             btree_type: match root_page.page_type {
                 PageType::LeafTable | PageType::InteriorTable => BtreeType::Table,
                 PageType::LeafIndex | PageType::InteriorIndex => BtreeType::Index,
             },
-            db_file_name: db_file_name.to_string(),
-            db_file_handle: Rc::new(RefCell::new(db_file_handle)),
-            page_size,
-            root_page,
+            pager,
+            root_page_num,
         })
     }
 
-    // As table btree this struct is responsible for knowing how to parse the cell_content from page and be able to parse it
-    // sepcifically as the Table B-Tree Cell type for interior or leaf
-    pub fn get_rows(&self, is_root_db_page: bool) -> Result<Vec<ReadableRecord>> {
-        let mut all_cells = Vec::new();
+    pub fn root_page_num(&self) -> u32 {
+        self.root_page_num
+    }
 
-        self.traverse_table_btree(&self.root_page, &mut all_cells, is_root_db_page)?;
+    pub fn iter(&self) -> BtreeIterator {
+        BtreeIterator::new(self.clone())
+    }
 
-        Ok(all_cells
-            .iter()
-            .map(|cell| cell.get_readable_record())
-            .collect())
+    // Convenience for small trees and exploration, everything else should stream through iter()
+    pub fn get_rows(&self) -> Result<Vec<ReadableRecord>> {
+        self.iter().collect()
     }
 
-    fn traverse_table_btree(
-        &self,
-        curr_page: &BtreePage,
-        cells: &mut Vec<DataCell>,
-        is_root_db_page: bool,
-    ) -> Result<()> {
-        let page_byte_buffer = curr_page.get_raw_bytes_buffer();
-        match &curr_page.page_header {
-            PageHeader::Interior(interior_header) => {
-                let num_cells = interior_header.common_header.num_cells;
-                // skip the page by 32+offset bytes to skip metadata block
-                let start_cell_pointer_region = if is_root_db_page { 100 + 12 } else { 12 };
-                let end_cell_pointer_region = start_cell_pointer_region + (num_cells as usize * 2);
-                let cell_pointers =
-                    &page_byte_buffer[start_cell_pointer_region..end_cell_pointer_region];
-
-                debug!("Interior Page with {} cells", num_cells);
-
-                for i in 0..num_cells {
-                    let cell_offset = u16::from_be_bytes(
-                        cell_pointers[(i * 2) as usize..(i * 2 + 2) as usize].try_into()?,
-                    );
-                    let tent: &[u8] = &page_byte_buffer[cell_offset.try_into()?..];
-                    let interior_cell = match self.btree_type {
-                        BtreeType::Table => {
-                            let (cell, _) = TableInteriorCell::from_be_bytes(tent)?;
-                            InteriorCell::Table(cell)
-                        }
-                        BtreeType::Index => {
-                            let (cell, _) = IndexInteriorCell::from_be_bytes(tent)?;
-                            let index_interior_cell = InteriorCell::Index(cell.clone());
-                            cells.push(DataCell::IndexInterior(cell));
-                            index_interior_cell
-                        }
-                    };
-
-                    // use the db handle to read the said page number
-                    let mut new_page_byte_buffer = vec![0; self.page_size];
-                    // explicit block to drop the mutable borrow of db_file_handle before we move on to the next call
-                    // that uses db_file_handle mutably. Since recursive calls are made one at a time, we do not hold
-                    // a mutable access to db_file_handle Rc<RefCell<File>> when someone else is using it.
-                    {
-                        let mut db_file_handle = self.db_file_handle.borrow_mut();
-                        self.read_page_into_buffer(
-                            &mut db_file_handle,
-                            &mut new_page_byte_buffer,
-                            interior_cell.get_left_child_page_number(),
-                        )?;
-                    }
+    // O(log N) lookup of a single row by its integer key
+    pub fn get_by_rowid(&self, rowid: i64) -> Result<Option<ReadableRecord>> {
+        let mut cursor = self.iter();
+        if !cursor.seek_rowid(rowid)? {
+            return Ok(None);
+        }
 
-                    let new_page =
-                        BtreePage::new(new_page_byte_buffer, 0, curr_page.reserved_bytes_per_page)?;
+        Ok(Some(cursor.current()?))
+    }
 
-                    // use the cell to read the new page directed by the cell, and recursively traverse the tree left to right
-                    self.traverse_table_btree(&new_page, cells, false)?;
+    /*
+     * Lazily scans the rows whose rowid falls within the given bounds in rowid order. The cursor seeks straight to the
+     * leaf holding the lower bound and the scan stops as soon as a rowid passes the upper bound, so only the pages
+     * overlapping the range are ever read.
+     */
+    pub fn scan_rowid_range(
+        &self,
+        lower_bound: Bound<i64>,
+        upper_bound: Bound<i64>,
+    ) -> Result<RowidRangeIterator> {
+        let mut cursor = self.iter();
+        let positioned = match lower_bound {
+            Bound::Included(rowid) => {
+                cursor.seek_rowid(rowid)?;
+                cursor.is_on_entry()
+            }
+            Bound::Excluded(rowid) => match rowid.checked_add(1) {
+                Some(rowid) => {
+                    cursor.seek_rowid(rowid)?;
+                    cursor.is_on_entry()
                 }
+                // nothing is greater than i64::MAX
+                None => false,
+            },
+            Bound::Unbounded => match cursor.seek_first() {
+                Some(Ok(_)) => true,
+                Some(Err(err)) => return Err(err),
+                None => false,
+            },
+        };
+
+        Ok(RowidRangeIterator {
+            cursor,
+            upper_bound,
+            pending_first: positioned,
+            exhausted: !positioned,
+        })
+    }
+
+    /*
+     * Finds every entry of an index btree whose leading columns equal the given key, comparing values the way SQLite
+     * does. Each entry is handed back as its key columns and the rowid of the table row it points at, which sits in
+     * the last column of every index record.
+     */
+    pub fn index_seek(&self, key: &[SerialData]) -> Result<Vec<(Vec<SerialData>, i64)>> {
+        let mut cursor = self.iter();
+        let mut entries = Vec::new();
+        if !cursor.seek_key(key)? {
+            return Ok(entries);
+        }
+
+        let mut record = cursor.current()?;
+        loop {
+            let mut columns = record.read_record()?;
+            if compare_key_prefix(&columns, key) != Ordering::Equal {
+                break;
+            }
 
-                // read the right most child separately
-                let mut right_page_byte_buffer = vec![0; self.page_size];
-                let right_most_pointer_page_number = interior_header.right_most_pointer;
-                {
-                    let mut db_file_handle = self.db_file_handle.borrow_mut();
-                    self.read_page_into_buffer(
-                        &mut db_file_handle,
-                        &mut right_page_byte_buffer,
-                        right_most_pointer_page_number,
-                    )?;
+            let rowid = match columns.pop().as_ref().and_then(SerialData::as_integer) {
+                Some(rowid) => rowid,
+                None => {
+                    return Err(StorageError::corrupt(
+                        "index entry does not end with an integer rowid",
+                    ))
                 }
+            };
+            entries.push((columns, rowid));
+
+            record = match cursor.next().transpose()? {
+                Some(record) => record,
+                None => break,
+            };
+        }
+
+        Ok(entries)
+    }
+
+    // the largest rowid in a table btree, None when the table is empty
+    pub fn max_rowid(&self) -> Result<Option<i64>> {
+        let mut cursor = self.iter();
+        match cursor.seek_last().transpose()? {
+            Some(_) => cursor.current_rowid(),
+            None => Ok(None),
+        }
+    }
+
+    /*
+     * Inserts a row into a table btree under the given rowid, failing if the rowid is taken.
+     * The cell goes onto the leaf the rowid belongs on, into its free space if there is enough of it. A full leaf is
+     * split and the divider keys of the new pages are pushed into the parent, which may split in turn all the way up
+     * to the root. The root keeps its page number when it splits, its cells move to new pages and it becomes the
+     * interior page above them, so the root page recorded in sqlite_schema stays valid.
+     * A record too large for the leaf keeps its first bytes on the leaf and spills the rest onto overflow pages.
+     */
+    pub fn insert(&self, rowid: i64, record: &[SerialData]) -> Result<()> {
+        if let BtreeType::Index = self.btree_type {
+            return Err(StorageError::InvalidOperation(
+                "rows can only be inserted into table btrees".to_string(),
+            ));
+        }
+
+        let (page, idx, path) = self.find_leaf(rowid)?;
+        if idx < page.num_cells() && self.integer_key_at(&page, idx)? == rowid {
+            return Err(StorageError::Constraint(format!(
+                "rowid {rowid} already exists"
+            )));
+        }
+        let appending = is_appending(&page, idx, &path);
+
+        // only spilled once the rowid is known to be free, and handed back when the cell cannot be placed
+        let cell = self.table_leaf_cell(rowid, record)?;
+        let overflow_page = self.new_cell_overflow_page(&PageType::LeafTable, &cell)?;
+        if let Err(err) = self.insert_cells(page, idx, vec![cell], path, appending) {
+            self.free_unused_chains(&[overflow_page], self.row_overflow_page(rowid))?;
+            return Err(err);
+        }
+        self.pager.borrow_mut().commit()
+    }
+
+    /*
+     * Deletes the row with the given rowid from a table btree, returns whether there was such a row.
+     * The cell is removed from its leaf and any overflow pages it spilled onto go to the freelist. A leaf left mostly
+     * empty is merged with or rebalanced against a sibling, see rebalance().
+     */
+    pub fn delete(&self, rowid: i64) -> Result<bool> {
+        if let BtreeType::Index = self.btree_type {
+            return Err(StorageError::InvalidOperation(
+                "rows can only be deleted from table btrees".to_string(),
+            ));
+        }
+
+        let (mut page, idx, path) = self.find_leaf(rowid)?;
+        if idx == page.num_cells() || self.integer_key_at(&page, idx)? != rowid {
+            return Ok(false);
+        }
+
+        self.free_overflow_pages_of(&page, idx)?;
+        page.drop_cell(idx)?;
+
+        self.rebalance(page, path)?;
+        self.pager.borrow_mut().commit()?;
+        Ok(true)
+    }
+
+    /*
+     * Replaces the record of the row with the given rowid in a table btree, returns whether there was such a row.
+     * A new cell no larger than the old one is written over it, a larger one takes the place of the old cell like a
+     * newly inserted row would, splitting the leaf if it has to. The overflow pages of the old cell only go to the
+     * freelist once the new cell is written, until then the cell on disk still points at them.
+     */
+    pub fn update(&self, rowid: i64, record: &[SerialData]) -> Result<bool> {
+        if let BtreeType::Index = self.btree_type {
+            return Err(StorageError::InvalidOperation(
+                "rows can only be updated in table btrees".to_string(),
+            ));
+        }
+
+        let (mut page, idx, path) = self.find_leaf(rowid)?;
+        if idx == page.num_cells() || self.integer_key_at(&page, idx)? != rowid {
+            return Ok(false);
+        }
+
+        let old_overflow_page = self.overflow_page_at(&page, idx)?;
+        let cell = self.table_leaf_cell(rowid, record)?;
+        let new_overflow_page = self.new_cell_overflow_page(&PageType::LeafTable, &cell)?;
+        let written = if page.overwrite_cell(idx, &cell)? {
+            self.pager.borrow_mut().write_page(&page)
+        } else {
+            page.drop_cell(idx)?;
+            self.insert_cells(page, idx, vec![cell], path, false)
+        };
+        if let Err(err) = written {
+            self.free_unused_chains(
+                &[old_overflow_page, new_overflow_page],
+                self.row_overflow_page(rowid),
+            )?;
+            return Err(err);
+        }
+
+        if let Some(overflow_page) = old_overflow_page {
+            self.free_overflow_pages(overflow_page)?;
+        }
+        self.pager.borrow_mut().commit()?;
+        Ok(true)
+    }
+
+    /*
+     * Adds an entry to an index btree, key holds the indexed columns followed by the rowid of the table row they
+     * belong to, which makes every entry unique. The entry goes onto the leaf it sorts into and a full leaf is split
+     * like a table leaf, except that the entry between two runs moves up into the parent whole, since the cells of
+     * interior index pages are entries themselves.
+     */
+    pub fn insert_index_entry(&self, key: &[SerialData]) -> Result<()> {
+        if let BtreeType::Table = self.btree_type {
+            return Err(StorageError::InvalidOperation(
+                "index entries can only be inserted into index btrees".to_string(),
+            ));
+        }
 
-                let right_page =
-                    BtreePage::new(right_page_byte_buffer, 0, curr_page.reserved_bytes_per_page)?;
-
-                self.traverse_table_btree(&right_page, cells, false)?;
-
-                Ok(())
-            }
-            PageHeader::Leaf(leaf_header) => {
-                // get access to the content area in here
-                let num_cells = leaf_header.common_header.num_cells;
-                // read cell pointer array
-                let start_cell_pointer = if is_root_db_page { 100 + 8 } else { 8 }; // 100 from DB Header if is root db page which has extra header on page
-                let end_cell_pointer = start_cell_pointer + (num_cells as usize * 2);
-                let cell_pointers = &page_byte_buffer[start_cell_pointer..end_cell_pointer];
-
-                debug!("Leaf Page with {} cells", num_cells);
-
-                for i in 0..num_cells {
-                    let cell_offset = u16::from_be_bytes(
-                        cell_pointers[i as usize * 2..i as usize * 2 + 2].try_into()?,
-                    );
-                    let cell_content = &page_byte_buffer[cell_offset as usize..];
-
-                    // Based on btree type use the appropriate type for cell parsing
-                    let cell = match self.btree_type {
-                        BtreeType::Table => {
-                            let (cell, _) = TableLeafCell::from_be_bytes(
-                                self.db_file_name.clone(),
-                                cell_content,
-                                self.page_size.try_into()?,
-                                curr_page.reserved_bytes_per_page,
-                            )?;
-                            DataCell::Table(cell)
-                        }
-                        BtreeType::Index => {
-                            let (cell, _) = IndexLeafCell::from_be_bytes(
-                                self.db_file_name.clone(),
-                                cell_content,
-                                self.page_size.try_into()?,
-                                curr_page.reserved_bytes_per_page,
-                            )?;
-                            DataCell::IndexLeaf(cell)
-                        }
-                    };
-
-                    cells.push(cell);
+        let (page, idx, path) = self.find_index_entry(key, true)?;
+        if idx < page.num_cells()
+            && compare_records(&self.index_key_at(&page, idx)?, key) == Ordering::Equal
+        {
+            return Err(StorageError::Constraint(format!(
+                "index entry {key:?} already exists"
+            )));
+        }
+
+        let appending = is_appending(&page, idx, &path);
+        let cell = self.index_leaf_cell(key)?;
+        let overflow_page = self.new_cell_overflow_page(&PageType::LeafIndex, &cell)?;
+        if let Err(err) = self.insert_cells(page, idx, vec![cell], path, appending) {
+            self.free_unused_chains(&[overflow_page], self.index_entry_overflow_page(key))?;
+            return Err(err);
+        }
+        self.pager.borrow_mut().commit()
+    }
+
+    /*
+     * Removes an entry from an index btree, returns whether there was such an entry. An entry on a leaf simply goes
+     * and the leaf is rebalanced. An entry on an interior page is replaced by the entry before it, the last one on the
+     * right most leaf of its left subtree, which is then removed from that leaf. The moved entry takes its overflow
+     * pages along, so only the pages of the deleted entry go to the freelist.
+     */
+    pub fn delete_index_entry(&self, key: &[SerialData]) -> Result<bool> {
+        if let BtreeType::Table = self.btree_type {
+            return Err(StorageError::InvalidOperation(
+                "index entries can only be deleted from index btrees".to_string(),
+            ));
+        }
+
+        let (mut page, idx, path) = self.find_index_entry(key, true)?;
+        if idx == page.num_cells()
+            || compare_records(&self.index_key_at(&page, idx)?, key) != Ordering::Equal
+        {
+            return Ok(false);
+        }
+        self.free_overflow_pages_of(&page, idx)?;
+
+        if page.is_leaf() {
+            page.drop_cell(idx)?;
+            self.rebalance(page, path)?;
+            return self.pager.borrow_mut().commit().map(|_| true);
+        }
+
+        let left_child = self.child_page_number(&page, idx)?;
+        let mut leaf = self.read_page(left_child)?;
+        while !leaf.is_leaf() {
+            leaf = self.read_page(self.child_page_number(&leaf, leaf.num_cells())?)?;
+        }
+        if leaf.num_cells() == 0 {
+            return Err(StorageError::Corrupt {
+                page: leaf.page_num,
+                offset: 0,
+                reason: "index leaf page without entries".to_string(),
+            });
+        }
+        let predecessor_idx = leaf.num_cells() - 1;
+        let predecessor_key = self.index_key_at(&leaf, predecessor_idx)?;
+        let divider = [
+            &left_child.to_be_bytes()[..],
+            leaf.cell_bytes(predecessor_idx)?,
+        ]
+        .concat();
+        page.drop_cell(idx)?;
+        self.insert_cells(page, idx, vec![divider], path, false)?;
+
+        // the predecessor is in the tree twice now, descending without stopping at the interior copy finds the leaf one
+        let (mut leaf, idx, path) = self.find_index_entry(&predecessor_key, false)?;
+        if idx == leaf.num_cells()
+            || compare_records(&self.index_key_at(&leaf, idx)?, &predecessor_key) != Ordering::Equal
+        {
+            return Err(StorageError::corrupt(
+                "lost track of the entry moved up in place of a deleted one",
+            ));
+        }
+        leaf.drop_cell(idx)?;
+        self.rebalance(leaf, path)?;
+        self.pager.borrow_mut().commit()?;
+        Ok(true)
+    }
+
+    /*
+     * Fills an empty index btree from entries that arrive in key order, far faster than inserting them one at a time.
+     * The tree is built bottom-up with every page packed as full as it gets: entries go onto a leaf until the next one
+     * does not fit, which then moves up into the level above as the divider to the next leaf, and a full interior page
+     * passes its dividers up the same way. The root is written last. A build that fails part way hands every page it
+     * wrote back to the freelist and leaves the index empty.
+     */
+    pub fn build_index(
+        &self,
+        entries: impl Iterator<Item = Result<Vec<SerialData>>>,
+    ) -> Result<()> {
+        let root = self.read_page(self.root_page_num)?;
+        if !matches!(self.btree_type, BtreeType::Index) || !root.is_leaf() || root.num_cells() > 0 {
+            return Err(StorageError::InvalidOperation(format!(
+                "btree rooted on page {} is not an empty index",
+                self.root_page_num
+            )));
+        }
+
+        let mut builder = IndexBuilder {
+            btree: self,
+            levels: Vec::new(),
+            written_pages: Vec::new(),
+            overflow_chains: Vec::new(),
+        };
+        if let Err(err) = builder.build(entries) {
+            builder.discard()?;
+            return Err(err);
+        }
+        self.pager.borrow_mut().commit()
+    }
+
+    /*
+     * Hands every page of the btree back to the freelist, the root included, along with the overflow pages of every
+     * cell. Nothing of the btree is left afterwards, so it is consumed. The pages are gathered by walking down from
+     * the root, a page reached twice means the btree is corrupt and nothing more is freed.
+     */
+    pub(crate) fn free_all_pages(self) -> Result<()> {
+        if self.root_page_num == 1 {
+            return Err(StorageError::InvalidOperation(
+                "the sqlite_schema btree cannot be freed".to_string(),
+            ));
+        }
+
+        let mut visited = HashSet::new();
+        let mut pending = vec![self.root_page_num];
+        while let Some(page_num) = pending.pop() {
+            if !visited.insert(page_num) {
+                return Err(StorageError::Corrupt {
+                    page: page_num,
+                    offset: 0,
+                    reason: format!(
+                        "page is reached twice in the btree rooted on page {}",
+                        self.root_page_num
+                    ),
+                });
+            }
+
+            let page = self.read_page(page_num)?;
+            for idx in 0..page.num_cells() {
+                self.free_overflow_pages_of(&page, idx)?;
+                if !page.is_leaf() {
+                    pending.push(self.child_page_number(&page, idx)?);
                 }
+            }
+            pending.extend(page.right_most_pointer());
+            self.pager.borrow_mut().free_page(page_num)?;
+        }
+        self.pager.borrow_mut().commit()
+    }
+
+    /*
+     * Descends an index btree towards key, returning the page reached, the index of the first entry on it that does not
+     * sort before key and the path from the root. With stop_at_match the descent ends early on an interior page holding
+     * an entry equal to key, otherwise it goes on into the child before that entry, where equal entries would sit.
+     */
+    fn find_index_entry(
+        &self,
+        key: &[SerialData],
+        stop_at_match: bool,
+    ) -> Result<(BtreePage, usize, BtreePath)> {
+        let key_ordering = |page: &BtreePage, idx: usize| -> Result<Ordering> {
+            Ok(compare_records(&self.index_key_at(page, idx)?, key))
+        };
+
+        let mut path = Vec::new();
+        let mut page = self.read_page(self.root_page_num)?;
+        loop {
+            let idx = lower_bound(page.num_cells(), |idx| key_ordering(&page, idx))?;
+            let is_match = idx < page.num_cells() && key_ordering(&page, idx)? == Ordering::Equal;
+            if page.is_leaf() || (stop_at_match && is_match) {
+                return Ok((page, idx, path));
+            }
+
+            let child_page_num = self.child_page_number(&page, idx)?;
+            path.push((page, idx));
+            page = self.read_page(child_page_num)?;
+        }
+    }
+
+    /*
+     * Descends a table btree to the leaf a rowid belongs on, returning the leaf, the index of the first cell with a
+     * rowid at least as large, and the path from the root down to the leaf with the child taken at every interior page.
+     */
+    fn find_leaf(&self, rowid: i64) -> Result<(BtreePage, usize, BtreePath)> {
+        let key_ordering = |page: &BtreePage, idx: usize| -> Result<Ordering> {
+            Ok(self.integer_key_at(page, idx)?.cmp(&rowid))
+        };
+
+        let mut path = Vec::new();
+        let mut page = self.read_page(self.root_page_num)?;
+        while !page.is_leaf() {
+            let child_idx = lower_bound(page.num_cells(), |idx| key_ordering(&page, idx))?;
+            let child_page_num = self.child_page_number(&page, child_idx)?;
+            path.push((page, child_idx));
+            page = self.read_page(child_page_num)?;
+        }
+
+        let idx = lower_bound(page.num_cells(), |idx| key_ordering(&page, idx))?;
+        Ok((page, idx, path))
+    }
+
+    fn table_leaf_cell(&self, rowid: i64, record: &[SerialData]) -> Result<Vec<u8>> {
+        let payload = self.encode_record(record);
+        let (local_size, first_overflow_page) = self.spill(&PageType::LeafTable, &payload)?;
+        Ok(TableLeafCell::to_be_bytes(
+            rowid,
+            payload.len(),
+            &payload[..local_size],
+            first_overflow_page,
+        ))
+    }
+
+    fn index_leaf_cell(&self, key: &[SerialData]) -> Result<Vec<u8>> {
+        let payload = self.encode_record(key);
+        let (local_size, first_overflow_page) = self.spill(&PageType::LeafIndex, &payload)?;
+        Ok(IndexLeafCell::to_be_bytes(
+            payload.len(),
+            &payload[..local_size],
+            first_overflow_page,
+        ))
+    }
+
+    fn encode_record(&self, record: &[SerialData]) -> Vec<u8> {
+        let pager = self.pager.borrow();
+        Record::new(record.to_vec()).to_be_bytes(&pager.metadata().schema_format_number)
+    }
+
+    // how much of a payload stays on a page of page_type, and the overflow chain the rest was written to if it spills
+    fn spill(&self, page_type: &PageType, payload: &[u8]) -> Result<(usize, Option<u32>)> {
+        let usable_size = self.pager.borrow().usable_size();
+        let local_size = local_payload_size(page_type, payload.len(), usable_size);
+        if local_size == payload.len() {
+            return Ok((local_size, None));
+        }
+
+        let first_overflow_page = self.write_overflow_pages(&payload[local_size..])?;
+        Ok((local_size, Some(first_overflow_page)))
+    }
+
+    /*
+     * Writes the part of a payload that does not fit on its btree page to a chain of new overflow pages, returning
+     * the first page of the chain. Each overflow page starts with the number of the next page, 0 on the last one,
+     * followed by as much of the payload as the usable space holds. A chain that cannot be written in full is of no use
+     * to anyone, the pages allocated for it go straight back to the freelist.
+     */
+    fn write_overflow_pages(&self, overflow: &[u8]) -> Result<u32> {
+        let mut pager = self.pager.borrow_mut();
+        let chunks = overflow.chunks(pager.usable_size() - 4).collect::<Vec<_>>();
+        let mut page_nums = Vec::with_capacity(chunks.len());
+        if let Err(err) = write_overflow_chain(&mut pager, &chunks, &mut page_nums) {
+            for page_num in page_nums {
+                pager.free_page(page_num)?;
+            }
+            pager.commit()?;
+            return Err(err);
+        }
+        Ok(page_nums[0])
+    }
+
+    // returns the overflow pages the idx'th cell of a page spilled onto to the freelist, if it spilled at all
+    fn free_overflow_pages_of(&self, page: &BtreePage, idx: usize) -> Result<()> {
+        match self.overflow_page_at(page, idx)? {
+            Some(overflow_page) => self.free_overflow_pages(overflow_page),
+            None => Ok(()),
+        }
+    }
+
+    // the first overflow page of the idx'th cell of a page, None when it did not spill
+    fn overflow_page_at(&self, page: &BtreePage, idx: usize) -> Result<Option<u32>> {
+        let usable_size = self.pager.borrow().usable_size();
+        first_overflow_page(&page.page_type, page.cell_content(idx)?, usable_size)
+            .map_err(|err| err.at(page.page_num, page.cell_offset(idx).unwrap_or(0)))
+    }
+
+    // the first overflow page of a cell not placed on a page yet, for a page of page_type
+    fn new_cell_overflow_page(&self, page_type: &PageType, cell: &[u8]) -> Result<Option<u32>> {
+        let usable_size = self.pager.borrow().usable_size();
+        first_overflow_page(page_type, cell, usable_size)
+    }
+
+    // the first overflow page of the row with the given rowid, None when there is no such row or it did not spill
+    fn row_overflow_page(&self, rowid: i64) -> Result<Option<u32>> {
+        let (page, idx, _) = self.find_leaf(rowid)?;
+        if idx == page.num_cells() || self.integer_key_at(&page, idx)? != rowid {
+            return Ok(None);
+        }
+        self.overflow_page_at(&page, idx)
+    }
+
+    // the first overflow page of the index entry equal to key, None when there is no such entry or it did not spill
+    fn index_entry_overflow_page(&self, key: &[SerialData]) -> Result<Option<u32>> {
+        let (page, idx, _) = self.find_index_entry(key, true)?;
+        if idx == page.num_cells()
+            || compare_records(&self.index_key_at(&page, idx)?, key) != Ordering::Equal
+        {
+            return Ok(None);
+        }
+        self.overflow_page_at(&page, idx)
+    }
+
+    /*
+     * Cleans up after a write that failed while placing a cell, chains being the overflow chains of the cells the write
+     * was dealing with. A split that fails part way can already have written the new cell out, so only the chains the
+     * entry in the tree does not point at, in_use, go to the freelist. When the entry could not even be looked up
+     * nothing is freed, leaking pages is better than freeing ones still in use.
+     */
+    fn free_unused_chains(
+        &self,
+        chains: &[Option<u32>],
+        in_use: Result<Option<u32>>,
+    ) -> Result<()> {
+        let Ok(in_use) = in_use else {
+            return Ok(());
+        };
+        for chain in chains.iter().flatten() {
+            if Some(*chain) != in_use {
+                self.free_overflow_pages(*chain)?;
+            }
+        }
+        self.pager.borrow_mut().commit()
+    }
 
-                Ok(())
+    // returns every page of an overflow chain to the freelist
+    fn free_overflow_pages(&self, first_page_num: u32) -> Result<()> {
+        let mut pager = self.pager.borrow_mut();
+        let mut page_num = first_page_num;
+        // a chain can not be longer than the file, a longer one loops back on itself
+        for _ in 0..pager.page_count() {
+            let next_page_num = read_u32(&pager.read_raw_page(page_num)?, 0)?;
+            pager.free_page(page_num)?;
+            if next_page_num == 0 {
+                return Ok(());
             }
+            page_num = next_page_num;
         }
+
+        Err(StorageError::Corrupt {
+            page: first_page_num,
+            offset: 0,
+            reason: "overflow chain loops back on itself".to_string(),
+        })
+    }
+
+    // places cells on a page starting at idx, splitting the page when they do not all fit
+    fn insert_cells(
+        &self,
+        mut page: BtreePage,
+        idx: usize,
+        new_cells: Vec<Vec<u8>>,
+        path: BtreePath,
+        appending: bool,
+    ) -> Result<()> {
+        for (n, cell) in new_cells.iter().enumerate() {
+            if !page.insert_cell(idx + n, cell)? {
+                let mut cells = page.cells()?;
+                cells.splice(idx + n..idx + n, new_cells[n..].iter().cloned());
+                let page_type = page.page_type.clone();
+                let right_most_pointer = page.right_most_pointer();
+                return self.split(page, page_type, cells, right_most_pointer, path, appending);
+            }
+        }
+
+        self.pager.borrow_mut().write_page(&page)
     }
 
-    fn read_page_into_buffer(
+    /*
+     * Spreads cells that no longer fit on one page over as many pages as it takes, the last run of cells stays on the
+     * page and every run before it moves to a new page. Each new page gets a divider cell in the parent pointing at it:
+     * a table leaf run is divided by its largest rowid, while on every other page the cell between two runs moves up
+     * into the parent as the divider and hands its left child to the run before it as the right most pointer.
+     * The pointer the parent already has to the page keeps pointing at the last run, so the dividers just go in front
+     * of it. A root has no parent, so all its runs move to new pages and it becomes the interior page above them.
+     */
+    fn split(
         &self,
-        db_file_handle: &mut File,
-        buf: &mut Vec<u8>,
-        page_num: u32,
+        mut page: BtreePage,
+        page_type: PageType,
+        cells: Vec<Vec<u8>>,
+        right_most_pointer: Option<u32>,
+        mut path: BtreePath,
+        appending: bool,
     ) -> Result<()> {
-        let offset_page_number: u64 = (page_num - 1).try_into()?;
-        let next_page_addr: u64 = offset_page_number * self.page_size as u64;
-        db_file_handle.seek(SeekFrom::Start(next_page_addr))?;
-        db_file_handle.read(buf)?;
+        let (page_size, usable_size, reserved_bytes_per_page) = {
+            let pager = self.pager.borrow();
+            (
+                pager.page_size(),
+                pager.usable_size(),
+                pager.reserved_bytes_per_page(),
+            )
+        };
+        let is_root = path.is_empty();
+        let is_leaf = matches!(page_type, PageType::LeafTable | PageType::LeafIndex);
+        let is_table_leaf = matches!(page_type, PageType::LeafTable);
+        let sizes = cells.iter().map(|cell| cell.len() + 2).collect::<Vec<_>>();
+        let capacity = BtreePage::cell_capacity(&page_type, usable_size, 0);
+        let runs = distribute(&sizes, capacity, !is_table_leaf, appending)?;
+
+        let mut dividers = Vec::new();
+        let mut last_page_num = page.page_num;
+        for (n, run) in runs.iter().enumerate() {
+            let is_last = n == runs.len() - 1;
+            let run_right_most_pointer = match (is_last, is_leaf) {
+                (true, _) => right_most_pointer,
+                (false, true) => None,
+                (false, false) => Some(read_u32(&cells[run.end], 0)?),
+            };
+
+            if is_last && !is_root {
+                page.rebuild(
+                    page_type.clone(),
+                    &cells[run.clone()],
+                    run_right_most_pointer,
+                )?;
+                self.pager.borrow_mut().write_page(&page)?;
+                break;
+            }
+
+            let page_num = self.pager.borrow_mut().allocate_page()?;
+            let mut new_page = BtreePage::new_empty(
+                page_num,
+                page_type.clone(),
+                page_size,
+                0,
+                reserved_bytes_per_page,
+            )?;
+            new_page.rebuild(
+                page_type.clone(),
+                &cells[run.clone()],
+                run_right_most_pointer,
+            )?;
+            self.pager.borrow_mut().write_page(&new_page)?;
+            last_page_num = page_num;
+
+            if !is_last {
+                let divider = match page_type {
+                    PageType::LeafTable => TableInteriorCell::to_be_bytes(
+                        page_num,
+                        TableLeafCell::integer_key_from_be_bytes(&cells[run.end - 1])?,
+                    ),
+                    // index leaf cells are interior cells without the left child pointer
+                    PageType::LeafIndex => [&page_num.to_be_bytes()[..], &cells[run.end]].concat(),
+                    PageType::InteriorTable | PageType::InteriorIndex => {
+                        let mut divider = cells[run.end].clone();
+                        divider[..4].copy_from_slice(&page_num.to_be_bytes());
+                        divider
+                    }
+                };
+                dividers.push(divider);
+            }
+        }
+
+        match path.pop() {
+            Some((parent, child_idx)) => {
+                self.insert_cells(parent, child_idx, dividers, path, appending)
+            }
+            None => {
+                let interior_type = match page_type {
+                    PageType::LeafTable | PageType::InteriorTable => PageType::InteriorTable,
+                    PageType::LeafIndex | PageType::InteriorIndex => PageType::InteriorIndex,
+                };
+                let needed: usize = dividers.iter().map(|divider| divider.len() + 2).sum();
+                let root_capacity =
+                    BtreePage::cell_capacity(&interior_type, usable_size, page.header_offset());
+                if needed <= root_capacity {
+                    page.rebuild(interior_type, &dividers, Some(last_page_num))?;
+                    self.pager.borrow_mut().write_page(&page)
+                } else {
+                    self.split(
+                        page,
+                        interior_type,
+                        dividers,
+                        Some(last_page_num),
+                        path,
+                        appending,
+                    )
+                }
+            }
+        }
+    }
+
+    /*
+     * Brings a page that lost cells back in balance. Like SQLite a page is left alone until more than two thirds of it
+     * is free, then it is combined with its right sibling, or its left one for the right most child. When the cells of
+     * both fit on one page the right page takes them all and the left page is freed, so the parent loses the divider
+     * between them and may need rebalancing in turn. Otherwise the cells are split over the two pages afresh.
+     * On every page but a table leaf the divider comes down from the parent to sit between the cells of the two pages.
+     */
+    fn rebalance(&self, page: BtreePage, mut path: BtreePath) -> Result<()> {
+        let usable_size = self.pager.borrow().usable_size();
+        let (mut parent, child_idx) = match path.pop() {
+            Some(parent) => parent,
+            None => return self.collapse_root(page),
+        };
+        if page.free_space()? * 3 <= usable_size * 2 {
+            return self.pager.borrow_mut().write_page(&page);
+        }
+        // only a root left without dividers by collapse_root() has no sibling to offer, its child may fit in it now
+        if parent.num_cells() == 0 {
+            self.pager.borrow_mut().write_page(&page)?;
+            return self.collapse_root(parent);
+        }
+
+        let (left, mut right, divider_idx) = if child_idx < parent.num_cells() {
+            let sibling = self.read_page(self.child_page_number(&parent, child_idx + 1)?)?;
+            (page, sibling, child_idx)
+        } else {
+            let sibling = self.read_page(self.child_page_number(&parent, child_idx - 1)?)?;
+            (sibling, page, child_idx - 1)
+        };
+        if left.page_type != right.page_type {
+            return Err(StorageError::Corrupt {
+                page: parent.page_num,
+                offset: 0,
+                reason: "sibling pages of different types".to_string(),
+            });
+        }
+
+        let page_type = left.page_type.clone();
+        let mut cells = left.cells()?;
+        let divider = parent.cell_bytes(divider_idx)?.to_vec();
+        match page_type {
+            PageType::LeafTable => {}
+            // index leaf cells are interior cells without the left child pointer
+            PageType::LeafIndex => cells.push(divider[4..].to_vec()),
+            PageType::InteriorTable | PageType::InteriorIndex => {
+                let mut divider = divider;
+                let left_child = left.right_most_pointer().unwrap_or(0);
+                divider[..4].copy_from_slice(&left_child.to_be_bytes());
+                cells.push(divider);
+            }
+        }
+        cells.extend(right.cells()?);
+        let right_most_pointer = right.right_most_pointer();
+
+        parent.drop_cell(divider_idx)?;
+        self.pager.borrow_mut().free_page(left.page_num)?;
+
+        let needed: usize = cells.iter().map(|cell| cell.len() + 2).sum();
+        if needed <= BtreePage::cell_capacity(&page_type, usable_size, right.header_offset()) {
+            right.rebuild(page_type, &cells, right_most_pointer)?;
+            self.pager.borrow_mut().write_page(&right)?;
+            return self.rebalance(parent, path);
+        }
+
+        // the right page now sits where the left one was in the parent, split() puts the divider back in front of it
+        path.push((parent, divider_idx));
+        self.split(right, page_type, cells, right_most_pointer, path, false)
+    }
+
+    /*
+     * A root interior page left without dividers has a single child, which is moved up into the root so the tree
+     * loses a level while the root keeps its page number. Page 1 has 100 bytes less room than its child, when the
+     * cells of the child do not fit there the root stays as it is, like it does in SQLite.
+     */
+    fn collapse_root(&self, mut root: BtreePage) -> Result<()> {
+        if let (0, Some(child_page_num)) = (root.num_cells(), root.right_most_pointer()) {
+            let child = self.read_page(child_page_num)?;
+            let cells = child.cells()?;
+            let needed: usize = cells.iter().map(|cell| cell.len() + 2).sum();
+            let usable_size = self.pager.borrow().usable_size();
+            if needed
+                <= BtreePage::cell_capacity(&child.page_type, usable_size, root.header_offset())
+            {
+                root.rebuild(child.page_type.clone(), &cells, child.right_most_pointer())?;
+                self.pager.borrow_mut().free_page(child_page_num)?;
+            }
+        }
+
+        self.pager.borrow_mut().write_page(&root)
+    }
+
+    fn read_page(&self, page_num: u32) -> Result<BtreePage> {
+        self.pager.borrow_mut().read_page(page_num)
+    }
+
+    // the integer key of a table btree cell, interior cells carry the largest rowid of their left child
+    fn integer_key_at(&self, page: &BtreePage, idx: usize) -> Result<i64> {
+        let cell_content = page.cell_content(idx)?;
+        let integer_key = match page.page_type {
+            PageType::LeafTable => TableLeafCell::integer_key_from_be_bytes(cell_content),
+            PageType::InteriorTable => {
+                TableInteriorCell::from_be_bytes(cell_content).map(|(cell, _)| cell.integer_key.0)
+            }
+            PageType::LeafIndex | PageType::InteriorIndex => {
+                return Err(StorageError::InvalidOperation(
+                    "index btree cells are not keyed by rowid".to_string(),
+                ))
+            }
+        };
+
+        integer_key.map_err(|err| err.at(page.page_num, page.cell_offset(idx).unwrap_or(0)))
+    }
+
+    // the full key of an index btree cell, the key columns followed by the rowid of the table row
+    fn index_key_at(&self, page: &BtreePage, idx: usize) -> Result<Vec<SerialData>> {
+        self.read_cell(page, idx)?
+            .get_readable_record()
+            .read_record()
+    }
+
+    // page number of the child an interior page points to at idx, where idx == num_cells is the right most pointer
+    fn child_page_number(&self, page: &BtreePage, idx: usize) -> Result<u32> {
+        if idx == page.num_cells() {
+            return match page.right_most_pointer() {
+                Some(page_num) => Ok(page_num),
+                None => Err(StorageError::InvalidOperation(
+                    "leaf pages do not have child pages".to_string(),
+                )),
+            };
+        }
+
+        let cell_content = page.cell_content(idx)?;
+        let interior_cell = match self.btree_type {
+            BtreeType::Table => TableInteriorCell::from_be_bytes(cell_content)
+                .map(|(cell, _)| InteriorCell::Table(cell)),
+            BtreeType::Index => {
+                let (db_file_name, page_size) = self.file_name_and_page_size();
+                IndexInteriorCell::from_be_bytes(
+                    db_file_name,
+                    cell_content,
+                    page_size,
+                    page.reserved_bytes_per_page,
+                )
+                .map(|(cell, _)| InteriorCell::Index(cell))
+            }
+        }
+        .map_err(|err| err.at(page.page_num, page.cell_offset(idx).unwrap_or(0)))?;
+
+        Ok(interior_cell.get_left_child_page_number())
+    }
+
+    // As table btree this struct is responsible for knowing how to parse the cell_content from page and be able to parse it
+    // sepcifically as the Table B-Tree Cell type for interior or leaf
+    fn read_cell(&self, page: &BtreePage, idx: usize) -> Result<DataCell> {
+        let cell_offset = page.cell_offset(idx)?;
+        let cell_content = page.cell_content(idx)?;
+        let (db_file_name, page_size) = self.file_name_and_page_size();
+        let cell = match page.page_type {
+            PageType::LeafTable => TableLeafCell::from_be_bytes(
+                db_file_name,
+                cell_content,
+                page_size,
+                page.reserved_bytes_per_page,
+            )
+            .map(|(cell, _)| DataCell::Table(cell)),
+            PageType::LeafIndex => IndexLeafCell::from_be_bytes(
+                db_file_name,
+                cell_content,
+                page_size,
+                page.reserved_bytes_per_page,
+            )
+            .map(|(cell, _)| DataCell::IndexLeaf(cell)),
+            PageType::InteriorIndex => IndexInteriorCell::from_be_bytes(
+                db_file_name,
+                cell_content,
+                page_size,
+                page.reserved_bytes_per_page,
+            )
+            .map(|(cell, _)| DataCell::IndexInterior(cell)),
+            PageType::InteriorTable => {
+                return Err(StorageError::InvalidOperation(
+                    "interior table cells do not hold any data".to_string(),
+                ))
+            }
+        };
+
+        cell.map_err(|err| err.at(page.page_num, cell_offset))
+    }
+
+    // cells that spill read the rest of their payload from the file themselves, so they need to know where it is
+    fn file_name_and_page_size(&self) -> (String, u16) {
+        let pager = self.pager.borrow();
+        (pager.db_file_name().to_string(), pager.page_size() as u16)
+    }
+}
+
+// the page being filled on one level of an index under construction, leaves being level 0
+#[derive(Default)]
+struct BuildLevel {
+    cells: Vec<Vec<u8>>,
+    // the bytes the cells take up along with their cell pointers
+    used: usize,
+    // a cell that did not fit on the full page, held back until it is known whether more cells follow it
+    held_back: Option<Vec<u8>>,
+}
+
+struct IndexBuilder<'a> {
+    btree: &'a Btree,
+    levels: Vec<BuildLevel>,
+    // everything written so far, so a failed build can hand it back
+    written_pages: Vec<u32>,
+    overflow_chains: Vec<u32>,
+}
+
+impl IndexBuilder<'_> {
+    fn build(&mut self, entries: impl Iterator<Item = Result<Vec<SerialData>>>) -> Result<()> {
+        let usable_size = self.btree.pager.borrow().usable_size();
+        for entry in entries {
+            let cell = self.btree.index_leaf_cell(&entry?)?;
+            if let Some(overflow_page) =
+                first_overflow_page(&PageType::LeafIndex, &cell, usable_size)?
+            {
+                self.overflow_chains.push(overflow_page);
+            }
+            self.add(0, cell)?;
+        }
+        self.finish()
+    }
+
+    /*
+     * Adds a cell to the page being filled on a level, interior cells carrying their left child. A full page is only
+     * finished once the cell after the one that did not fit arrives, so the held back cell can move up as the divider
+     * between the finished page and the next one which starts with the newer cell.
+     */
+    fn add(&mut self, level: usize, cell: Vec<u8>) -> Result<()> {
+        if level == self.levels.len() {
+            self.levels.push(BuildLevel::default());
+        }
+
+        if let Some(held_back) = self.levels[level].held_back.take() {
+            let cells = std::mem::take(&mut self.levels[level].cells);
+            self.levels[level].used = 0;
+            let divider = self.finish_page(level, cells, held_back)?;
+            self.add(level + 1, divider)?;
+        }
+
+        let page_type = build_page_type(level);
+        let usable_size = self.btree.pager.borrow().usable_size();
+        let build_level = &mut self.levels[level];
+        if build_level.used + cell.len() + 2 <= BtreePage::cell_capacity(&page_type, usable_size, 0)
+        {
+            build_level.used += cell.len() + 2;
+            build_level.cells.push(cell);
+        } else {
+            build_level.held_back = Some(cell);
+        }
+        Ok(())
+    }
+
+    /*
+     * Writes out the last page of every level, leaves first, each one becoming the right most child of the page above
+     * it. When the entries ran out with a cell held back on a level, the full page gives up its last cell to divide it
+     * from a last page holding just the held back cell. The top level is down to one page, which becomes the root.
+     */
+    fn finish(&mut self) -> Result<()> {
+        let mut right_most_child = None;
+        let mut level = 0;
+        while level < self.levels.len() {
+            if let Some(held_back) = self.levels[level].held_back.take() {
+                let mut cells = std::mem::replace(&mut self.levels[level].cells, vec![held_back]);
+                let last = cells.pop().ok_or_else(|| {
+                    StorageError::InvalidOperation(
+                        "an index cell does not fit on a page".to_string(),
+                    )
+                })?;
+                let divider = self.finish_page(level, cells, last)?;
+                self.add(level + 1, divider)?;
+            }
+
+            let cells = std::mem::take(&mut self.levels[level].cells);
+            let page_type = build_page_type(level);
+            if level + 1 == self.levels.len() {
+                let mut root = self.btree.read_page(self.btree.root_page_num)?;
+                root.rebuild(page_type, &cells, right_most_child)?;
+                self.btree.pager.borrow_mut().write_page(&root)?;
+            } else {
+                right_most_child = Some(self.write_page(page_type, &cells, right_most_child)?);
+            }
+            level += 1;
+        }
         Ok(())
     }
+
+    // writes a full page of a level and returns the divider that goes up in front of it, made from the cell after it
+    fn finish_page(&mut self, level: usize, cells: Vec<Vec<u8>>, next: Vec<u8>) -> Result<Vec<u8>> {
+        // an interior cell hands its left child to the page before it as the right most child
+        let (right_most_child, entry) = if level == 0 {
+            (None, &next[..])
+        } else {
+            (Some(read_u32(&next, 0)?), &next[4..])
+        };
+        let page_num = self.write_page(build_page_type(level), &cells, right_most_child)?;
+        Ok([&page_num.to_be_bytes()[..], entry].concat())
+    }
+
+    fn write_page(
+        &mut self,
+        page_type: PageType,
+        cells: &[Vec<u8>],
+        right_most_child: Option<u32>,
+    ) -> Result<u32> {
+        let mut pager = self.btree.pager.borrow_mut();
+        let page_num = pager.allocate_page()?;
+        self.written_pages.push(page_num);
+        let mut page = BtreePage::new_empty(
+            page_num,
+            page_type.clone(),
+            pager.page_size(),
+            0,
+            pager.reserved_bytes_per_page(),
+        )?;
+        page.rebuild(page_type, cells, right_most_child)?;
+        pager.write_page(&page)?;
+        Ok(page_num)
+    }
+
+    fn discard(&mut self) -> Result<()> {
+        for overflow_page in std::mem::take(&mut self.overflow_chains) {
+            self.btree.free_overflow_pages(overflow_page)?;
+        }
+        let mut pager = self.btree.pager.borrow_mut();
+        for page_num in std::mem::take(&mut self.written_pages) {
+            pager.free_page(page_num)?;
+        }
+        pager.commit()
+    }
+}
+
+fn build_page_type(level: usize) -> PageType {
+    if level == 0 {
+        PageType::LeafIndex
+    } else {
+        PageType::InteriorIndex
+    }
+}
+
+/*
+ * A cursor over a btree. The cursor holds the path from the root page down to the page it is currently positioned on,
+ * so memory use is bounded by the depth of the tree rather than the number of entries in it.
+ *
+ * Table btrees only hold entries on their leaves, interior pages just route to children.
+ * Index btrees hold entries on interior pages as well, and the interior entry at cell i sorts after every entry
+ * in the subtree of child i and before every entry in the subtree of child i + 1.
+ */
+struct CursorFrame {
+    page: BtreePage,
+    // for the page the cursor is positioned on this is the cell of the current entry,
+    // for every page above it this is the child we descended into (num_cells meaning the right most pointer)
+    cell_idx: usize,
+}
+
+enum CursorPosition {
+    Unpositioned,
+    Entry,
+    BeforeFirst,
+    AfterLast,
 }
 
-// TODO: An abstraction for a next() method for the Btree struct that returns the next node. This introduces state, but lets us do stuff in a more controlled manner
 pub struct BtreeIterator {
     btree: Btree,
-    curr_page: BtreePage,
-    curr_cell: usize,
+    stack: Vec<CursorFrame>,
+    position: CursorPosition,
 }
 
 impl BtreeIterator {
     pub fn new(btree: Btree) -> Self {
         BtreeIterator {
-            btree: btree.clone(),
-            curr_page: btree.root_page.clone(),
-            curr_cell: 0,
+            btree,
+            stack: Vec::new(),
+            position: CursorPosition::Unpositioned,
+        }
+    }
+
+    // moves the cursor onto the smallest entry of the btree and returns it
+    pub fn seek_first(&mut self) -> Option<Result<ReadableRecord>> {
+        let moved = self.move_to_first();
+        self.settle(moved, CursorPosition::AfterLast)
+    }
+
+    // moves the cursor onto the largest entry of the btree and returns it. Named apart from Iterator::last which
+    // would consume the cursor, so the cursor can keep walking backwards with prev() afterwards
+    pub fn seek_last(&mut self) -> Option<Result<ReadableRecord>> {
+        let moved = self.move_to_last();
+        self.settle(moved, CursorPosition::BeforeFirst)
+    }
+
+    // moves the cursor one entry back, an unpositioned cursor or one that ran off the end starts from the last entry
+    pub fn prev(&mut self) -> Option<Result<ReadableRecord>> {
+        match self.position {
+            CursorPosition::Unpositioned | CursorPosition::AfterLast => self.seek_last(),
+            CursorPosition::BeforeFirst => None,
+            CursorPosition::Entry => {
+                let moved = self.move_to_prev();
+                self.settle(moved, CursorPosition::BeforeFirst)
+            }
+        }
+    }
+
+    // returns the entry the cursor is currently positioned on
+    pub fn current(&self) -> Result<ReadableRecord> {
+        let frame = self.current_frame()?;
+        let cell = self.btree.read_cell(&frame.page, frame.cell_idx)?;
+        Ok(cell.get_readable_record())
+    }
+
+    // returns the rowid of the entry the cursor is positioned on, index btrees have no rowid key
+    pub fn current_rowid(&self) -> Result<Option<i64>> {
+        let frame = self.current_frame()?;
+        match self.btree.btree_type {
+            BtreeType::Table => Ok(Some(
+                self.btree.integer_key_at(&frame.page, frame.cell_idx)?,
+            )),
+            BtreeType::Index => Ok(None),
+        }
+    }
+
+    /*
+     * Positions the cursor on the first entry of a table btree whose rowid is >= the given rowid and reports whether
+     * that entry is an exact match. Interior table cells hold the largest rowid found in their left child, so the
+     * descent follows the first cell whose key is >= rowid, or the right most pointer when every key is smaller.
+     * This only touches one page per level of the tree.
+     */
+    pub fn seek_rowid(&mut self, rowid: i64) -> Result<bool> {
+        if let BtreeType::Index = self.btree.btree_type {
+            return Err(StorageError::InvalidOperation(
+                "seek by rowid is only supported on table btrees".to_string(),
+            ));
+        }
+
+        self.seek_by(|btree, page, idx| Ok(btree.integer_key_at(page, idx)?.cmp(&rowid)))
+    }
+
+    /*
+     * Positions the cursor on the first entry of an index btree whose leading columns are >= the given key and reports
+     * whether that entry's leading columns are equal to it. The key may name fewer columns than the index holds,
+     * entries sharing that prefix are contiguous so the caller can keep calling next() while they still match.
+     * Interior index cells are entries themselves, and a cell equal to the key can have equal entries in its
+     * left child too, so the descent follows the first cell that is >= the key just like the table descent.
+     */
+    pub fn seek_key(&mut self, key: &[SerialData]) -> Result<bool> {
+        if let BtreeType::Table = self.btree.btree_type {
+            return Err(StorageError::InvalidOperation(
+                "seek by key is only supported on index btrees".to_string(),
+            ));
+        }
+
+        self.seek_by(|btree, page, idx| {
+            let cell_key = btree.index_key_at(page, idx)?;
+            Ok(compare_key_prefix(&cell_key, key))
+        })
+    }
+
+    // descends to the first entry that does not sort before the target, compare_to_target orders a cell against it
+    fn seek_by<F>(&mut self, compare_to_target: F) -> Result<bool>
+    where
+        F: Fn(&Btree, &BtreePage, usize) -> Result<Ordering>,
+    {
+        self.stack.clear();
+        self.position = CursorPosition::Unpositioned;
+
+        let mut page = self.btree.read_page(self.btree.root_page_num)?;
+        while !page.is_leaf() {
+            let child_idx = lower_bound(page.num_cells(), |idx| {
+                compare_to_target(&self.btree, &page, idx)
+            })?;
+            let child_page_num = self.btree.child_page_number(&page, child_idx)?;
+            self.stack.push(CursorFrame {
+                page,
+                cell_idx: child_idx,
+            });
+            page = self.btree.read_page(child_page_num)?;
+        }
+
+        let cell_idx = lower_bound(page.num_cells(), |idx| {
+            compare_to_target(&self.btree, &page, idx)
+        })?;
+        let num_cells = page.num_cells();
+        self.stack.push(CursorFrame { page, cell_idx });
+
+        if cell_idx == num_cells {
+            // every entry on this leaf sorts before the target, so the first one that does not (if any) comes after it
+            let moved = match self.stack.last_mut() {
+                Some(frame) if num_cells > 0 => {
+                    frame.cell_idx = num_cells - 1;
+                    self.move_to_next()?
+                }
+                _ => false,
+            };
+            if !moved {
+                self.stack.clear();
+                self.position = CursorPosition::AfterLast;
+                return Ok(false);
+            }
+        }
+
+        self.position = CursorPosition::Entry;
+        let frame = self.current_frame()?;
+        Ok(compare_to_target(&self.btree, &frame.page, frame.cell_idx)? == Ordering::Equal)
+    }
+
+    pub fn is_on_entry(&self) -> bool {
+        matches!(self.position, CursorPosition::Entry)
+    }
+
+    fn current_frame(&self) -> Result<&CursorFrame> {
+        match (&self.position, self.stack.last()) {
+            (CursorPosition::Entry, Some(frame)) => Ok(frame),
+            _ => Err(StorageError::InvalidOperation(
+                "cursor is not positioned on an entry".to_string(),
+            )),
+        }
+    }
+
+    // turns the result of a cursor movement into what the public api hands back
+    fn settle(
+        &mut self,
+        moved: Result<bool>,
+        when_stopped: CursorPosition,
+    ) -> Option<Result<ReadableRecord>> {
+        match self.settle_position(moved, when_stopped) {
+            Ok(true) => Some(self.current()),
+            Ok(false) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
+
+    /*
+     * Records where a cursor movement left the cursor. A movement that fails stops the cursor just like one that runs
+     * off the end, so a caller skipping the error does not start over from the other end and run into the same error
+     * forever.
+     */
+    fn settle_position(
+        &mut self,
+        moved: Result<bool>,
+        when_stopped: CursorPosition,
+    ) -> Result<bool> {
+        if let Ok(true) = moved {
+            self.position = CursorPosition::Entry;
+        } else {
+            self.stack.clear();
+            self.position = when_stopped;
+        }
+        moved
+    }
+
+    // moves the cursor one entry forward like next() without reading the entry, returns whether it is on one
+    fn move_forward(&mut self) -> Result<bool> {
+        let moved = match self.position {
+            CursorPosition::Unpositioned | CursorPosition::BeforeFirst => self.move_to_first(),
+            CursorPosition::AfterLast => return Ok(false),
+            CursorPosition::Entry => self.move_to_next(),
+        };
+        self.settle_position(moved, CursorPosition::AfterLast)
+    }
+
+    fn move_to_first(&mut self) -> Result<bool> {
+        self.stack.clear();
+        let root_page_num = self.btree.root_page_num;
+        self.descend_left_most(root_page_num)
+    }
+
+    fn move_to_last(&mut self) -> Result<bool> {
+        self.stack.clear();
+        let root_page_num = self.btree.root_page_num;
+        self.descend_right_most(root_page_num)
+    }
+
+    // pushes the path to the smallest entry in the subtree rooted at page_num
+    fn descend_left_most(&mut self, page_num: u32) -> Result<bool> {
+        let mut page = self.btree.read_page(page_num)?;
+        while !page.is_leaf() {
+            let child_page_num = self.btree.child_page_number(&page, 0)?;
+            self.stack.push(CursorFrame { page, cell_idx: 0 });
+            page = self.btree.read_page(child_page_num)?;
+        }
+
+        // only an empty root page can be a leaf without cells
+        let has_entries = page.num_cells() > 0;
+        self.stack.push(CursorFrame { page, cell_idx: 0 });
+        Ok(has_entries)
+    }
+
+    // pushes the path to the largest entry in the subtree rooted at page_num
+    fn descend_right_most(&mut self, page_num: u32) -> Result<bool> {
+        let mut page = self.btree.read_page(page_num)?;
+        while !page.is_leaf() {
+            let num_cells = page.num_cells();
+            let child_page_num = self.btree.child_page_number(&page, num_cells)?;
+            self.stack.push(CursorFrame {
+                page,
+                cell_idx: num_cells,
+            });
+            page = self.btree.read_page(child_page_num)?;
+        }
+
+        let num_cells = page.num_cells();
+        self.stack.push(CursorFrame {
+            page,
+            cell_idx: num_cells.saturating_sub(1),
+        });
+        Ok(num_cells > 0)
+    }
+
+    fn move_to_next(&mut self) -> Result<bool> {
+        let Some(frame) = self.stack.last_mut() else {
+            return Ok(false);
+        };
+
+        if !frame.page.is_leaf() {
+            // positioned on an index interior entry, the next entry is the smallest one in the following child
+            frame.cell_idx += 1;
+            let child_page_num = self.btree.child_page_number(&frame.page, frame.cell_idx)?;
+            return self.descend_left_most(child_page_num);
+        }
+
+        if frame.cell_idx + 1 < frame.page.num_cells() {
+            frame.cell_idx += 1;
+            return Ok(true);
+        }
+
+        // leaf is exhausted, climb until an ancestor has something to the right of the child we came from
+        self.stack.pop();
+        while let Some(frame) = self.stack.last_mut() {
+            if frame.cell_idx < frame.page.num_cells() {
+                match self.btree.btree_type {
+                    // the interior cell we descended through is itself the next entry
+                    BtreeType::Index => return Ok(true),
+                    BtreeType::Table => {
+                        frame.cell_idx += 1;
+                        let child_page_num =
+                            self.btree.child_page_number(&frame.page, frame.cell_idx)?;
+                        return self.descend_left_most(child_page_num);
+                    }
+                }
+            }
+            self.stack.pop();
+        }
+
+        Ok(false)
+    }
+
+    fn move_to_prev(&mut self) -> Result<bool> {
+        let Some(frame) = self.stack.last_mut() else {
+            return Ok(false);
+        };
+
+        if !frame.page.is_leaf() {
+            // positioned on an index interior entry, the previous entry is the largest one in its left child
+            let child_page_num = self.btree.child_page_number(&frame.page, frame.cell_idx)?;
+            return self.descend_right_most(child_page_num);
+        }
+
+        if frame.cell_idx > 0 {
+            frame.cell_idx -= 1;
+            return Ok(true);
+        }
+
+        // leaf is exhausted, climb until an ancestor has something to the left of the child we came from
+        self.stack.pop();
+        while let Some(frame) = self.stack.last_mut() {
+            if frame.cell_idx > 0 {
+                frame.cell_idx -= 1;
+                match self.btree.btree_type {
+                    // the interior cell separating us from our left sibling is the previous entry
+                    BtreeType::Index => return Ok(true),
+                    BtreeType::Table => {
+                        let child_page_num =
+                            self.btree.child_page_number(&frame.page, frame.cell_idx)?;
+                        return self.descend_right_most(child_page_num);
+                    }
+                }
+            }
+            self.stack.pop();
+        }
+
+        Ok(false)
+    }
+}
+
+impl Iterator for BtreeIterator {
+    type Item = Result<ReadableRecord>;
+
+    // moves the cursor one entry forward, an unpositioned cursor or one that ran off the start begins at the first entry
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.move_forward() {
+            Ok(true) => Some(self.current()),
+            Ok(false) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
+
+    // jumps straight down the right most path instead of walking every entry
+    fn last(mut self) -> Option<Self::Item> {
+        self.seek_last()
+    }
+}
+
+// allocates a page for every chunk and writes the chunks out as a chain, page_nums keeps what was allocated so far
+fn write_overflow_chain(
+    pager: &mut Pager,
+    chunks: &[&[u8]],
+    page_nums: &mut Vec<u32>,
+) -> Result<()> {
+    for _ in chunks {
+        page_nums.push(pager.allocate_page()?);
+    }
+    for (n, chunk) in chunks.iter().enumerate() {
+        let next_page_num = page_nums.get(n + 1).copied().unwrap_or(0);
+        let mut buffer = vec![0; pager.page_size()];
+        buffer[..4].copy_from_slice(&next_page_num.to_be_bytes());
+        buffer[4..4 + chunk.len()].copy_from_slice(chunk);
+        pager.write_raw_page(page_nums[n], &buffer)?;
+    }
+    Ok(())
+}
+
+// whether a cell placed at idx on a leaf lands after every entry in the tree, i.e. at the very end of every page
+fn is_appending(page: &BtreePage, idx: usize, path: &BtreePath) -> bool {
+    idx == page.num_cells()
+        && path
+            .iter()
+            .all(|(parent, child_idx)| *child_idx == parent.num_cells())
+}
+
+/*
+ * Cuts cells (given by the size they take on a page, pointer included) into runs that each fit in capacity bytes.
+ * When consumes_divider is set the cell after every run but the last moves up into the parent instead of onto a page,
+ * and the last cell is never picked for that so the last run is not left empty.
+ * Runs come out about the same size so every page has room to grow, except when appending where each run is filled
+ * up completely. Rows inserted in rowid order then leave full pages behind them instead of half empty ones.
+ */
+fn distribute(
+    sizes: &[usize],
+    capacity: usize,
+    consumes_divider: bool,
+    appending: bool,
+) -> Result<Vec<Range<usize>>> {
+    let total: usize = sizes.iter().sum();
+    let num_pages = total.div_ceil(capacity).max(2);
+    let target = total.div_ceil(num_pages);
+
+    let mut runs = Vec::new();
+    let (mut start, mut used, mut idx) = (0, 0, 0);
+    while idx < sizes.len() {
+        let size = sizes[idx];
+        if size > capacity {
+            return Err(StorageError::InvalidOperation(format!(
+                "a cell of {size} bytes does not fit on a page"
+            )));
+        }
+
+        let must_close = used + size > capacity;
+        let may_close = !appending && used >= target;
+        if idx > start && (must_close || may_close) {
+            let mut end = idx;
+            if consumes_divider && idx + 1 == sizes.len() {
+                if !must_close {
+                    used += size;
+                    idx += 1;
+                    continue;
+                }
+                // hand the divider role to the cell before so the last one still has a run to go to
+                end -= 1;
+                if end == start {
+                    return Err(StorageError::InvalidOperation(
+                        "cells are too large to be split across pages".to_string(),
+                    ));
+                }
+            }
+
+            runs.push(start..end);
+            start = if consumes_divider { end + 1 } else { end };
+            idx = start;
+            used = 0;
+            continue;
+        }
+
+        used += size;
+        idx += 1;
+    }
+    runs.push(start..sizes.len());
+
+    Ok(runs)
+}
+
+// binary search for the first of num_cells cells that does not sort before the target, num_cells if there is none
+fn lower_bound<F>(num_cells: usize, compare_to_target: F) -> Result<usize>
+where
+    F: Fn(usize) -> Result<Ordering>,
+{
+    let (mut lo, mut hi) = (0, num_cells);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if compare_to_target(mid)? == Ordering::Less {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+
+    Ok(lo)
+}
+
+// orders an index entry against a key that may only cover its leading columns
+fn compare_key_prefix(entry: &[SerialData], key: &[SerialData]) -> Ordering {
+    compare_records(&entry[..key.len().min(entry.len())], key)
+}
+
+// Iterator over the (rowid, record) pairs of a table btree within a rowid range, see Btree::scan_rowid_range
+pub struct RowidRangeIterator {
+    cursor: BtreeIterator,
+    upper_bound: Bound<i64>,
+    // the seek already left the cursor on the first row in range, so the first call must not advance it
+    pending_first: bool,
+    exhausted: bool,
+}
+
+impl RowidRangeIterator {
+    fn within_upper_bound(&self, rowid: i64) -> bool {
+        match self.upper_bound {
+            Bound::Included(hi) => rowid <= hi,
+            Bound::Excluded(hi) => rowid < hi,
+            Bound::Unbounded => true,
+        }
+    }
+
+    // the record of a row is only read once its rowid is known to be in range
+    fn advance(&mut self) -> Result<Option<(i64, ReadableRecord)>> {
+        if self.pending_first {
+            self.pending_first = false;
+        } else if !self.cursor.move_forward()? {
+            return Ok(None);
+        }
+
+        let rowid = match self.cursor.current_rowid()? {
+            Some(rowid) => rowid,
+            None => {
+                return Err(StorageError::InvalidOperation(
+                    "rowid range scans are only supported on table btrees".to_string(),
+                ))
+            }
+        };
+        if !self.within_upper_bound(rowid) {
+            return Ok(None);
+        }
+
+        Ok(Some((rowid, self.cursor.current()?)))
+    }
+}
+
+impl Iterator for RowidRangeIterator {
+    type Item = Result<(i64, ReadableRecord)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.exhausted {
+            return None;
         }
+
+        let advanced = self.advance().transpose();
+        if !matches!(advanced, Some(Ok(_))) {
+            self.exhausted = true;
+        }
+        advanced
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::database::{CreateOptions, Database, SchemaFormatNumber};
+    use crate::sql_data_types::SerialData;
+    use std::fs::File;
+    use std::io::{Seek, SeekFrom, Write};
+    use std::ops::RangeBounds;
+
+    const PAGE_SIZE: usize = 4096;
+    // the test btrees spread their entries over leaves of this many, so even small ones have several levels
+    const ENTRIES_PER_LEAF: usize = 16;
+
+    // the varint encoding of a value below 2^56, which is all these tests need
+    fn varint(value: u64) -> Vec<u8> {
+        let mut bytes = vec![(value & 0x7f) as u8];
+        let mut rest = value >> 7;
+        while rest != 0 {
+            bytes.insert(0, (rest & 0x7f) as u8 | 0x80);
+            rest >>= 7;
+        }
+        bytes
+    }
+
+    // the record of text and integer values, integers always take the full 8 bytes
+    fn record(values: &[SerialData]) -> Vec<u8> {
+        let mut header = Vec::new();
+        let mut body = Vec::new();
+        for value in values {
+            match value {
+                SerialData::I64(n) => {
+                    header.extend(varint(6));
+                    body.extend(n.to_be_bytes());
+                }
+                SerialData::Text(text) => {
+                    header.extend(varint(13 + 2 * text.len() as u64));
+                    body.extend(text.as_bytes());
+                }
+                other => panic!("test records hold no {other:?}"),
+            }
+        }
+        // the header size counts itself, which takes one byte while the header stays short
+        assert!(header.len() < 127);
+        [varint(header.len() as u64 + 1), header, body].concat()
+    }
+
+    // a btree page holding the cells in order, their content packed against the end of the page
+    fn btree_page(
+        page_type: u8,
+        cells: &[Vec<u8>],
+        right_child: Option<u32>,
+        header_offset: usize,
+    ) -> Vec<u8> {
+        let mut page = vec![0; PAGE_SIZE];
+        let mut pointer = header_offset + if right_child.is_some() { 12 } else { 8 };
+        let mut content_start = PAGE_SIZE;
+        for cell in cells {
+            content_start -= cell.len();
+            page[content_start..content_start + cell.len()].copy_from_slice(cell);
+            page[pointer..pointer + 2].copy_from_slice(&(content_start as u16).to_be_bytes());
+            pointer += 2;
+        }
+        assert!(pointer <= content_start);
+
+        page[header_offset] = page_type;
+        page[header_offset + 3..header_offset + 5]
+            .copy_from_slice(&(cells.len() as u16).to_be_bytes());
+        page[header_offset + 5..header_offset + 7]
+            .copy_from_slice(&(content_start as u16).to_be_bytes());
+        if let Some(right_child) = right_child {
+            page[header_offset + 8..header_offset + 12].copy_from_slice(&right_child.to_be_bytes());
+        }
+        page
+    }
+
+    // the pages of a table btree rooted at root_page_num, a single leaf when the rows fit or an interior root over leaves
+    fn table_btree_pages(root_page_num: u32, rows: &[(i64, Vec<u8>)]) -> Vec<Vec<u8>> {
+        let cell = |(rowid, payload): &(i64, Vec<u8>)| {
+            [
+                varint(payload.len() as u64),
+                varint(*rowid as u64),
+                payload.clone(),
+            ]
+            .concat()
+        };
+        if rows.len() <= ENTRIES_PER_LEAF {
+            return vec![btree_page(
+                0x0d,
+                &rows.iter().map(cell).collect::<Vec<_>>(),
+                None,
+                0,
+            )];
+        }
+
+        let leaves = rows.chunks(ENTRIES_PER_LEAF).collect::<Vec<_>>();
+        let dividers = leaves[..leaves.len() - 1]
+            .iter()
+            .enumerate()
+            .map(|(i, leaf)| {
+                let child_page_num = root_page_num + 1 + i as u32;
+                let max_rowid = leaf.last().unwrap().0;
+                [
+                    child_page_num.to_be_bytes().to_vec(),
+                    varint(max_rowid as u64),
+                ]
+                .concat()
+            })
+            .collect::<Vec<_>>();
+        let right_child = root_page_num + leaves.len() as u32;
+        let mut pages = vec![btree_page(0x05, &dividers, Some(right_child), 0)];
+        for leaf in leaves {
+            pages.push(btree_page(
+                0x0d,
+                &leaf.iter().map(cell).collect::<Vec<_>>(),
+                None,
+                0,
+            ));
+        }
+        pages
+    }
+
+    // the pages of an index btree rooted at root_page_num, the interior root holds the entries between its leaves
+    fn index_btree_pages(root_page_num: u32, entries: &[Vec<u8>]) -> Vec<Vec<u8>> {
+        let cell = |payload: &Vec<u8>| [varint(payload.len() as u64), payload.clone()].concat();
+        let mut leaves = Vec::new();
+        let mut dividers = Vec::new();
+        let mut rest = entries;
+        while rest.len() > ENTRIES_PER_LEAF + 1 {
+            let child_page_num = root_page_num + 1 + leaves.len() as u32;
+            leaves.push(&rest[..ENTRIES_PER_LEAF]);
+            let divider = &rest[ENTRIES_PER_LEAF];
+            dividers.push([child_page_num.to_be_bytes().to_vec(), cell(divider)].concat());
+            rest = &rest[ENTRIES_PER_LEAF + 1..];
+        }
+        if leaves.is_empty() {
+            return vec![btree_page(
+                0x0a,
+                &rest.iter().map(cell).collect::<Vec<_>>(),
+                None,
+                0,
+            )];
+        }
+
+        leaves.push(rest);
+        let right_child = root_page_num + leaves.len() as u32;
+        let mut pages = vec![btree_page(0x02, &dividers, Some(right_child), 0)];
+        for leaf in leaves {
+            pages.push(btree_page(
+                0x0a,
+                &leaf.iter().map(cell).collect::<Vec<_>>(),
+                None,
+                0,
+            ));
+        }
+        pages
+    }
+
+    /*
+     * Writes a database file to the temp directory the way SQLite would lay it out, with a table t whose rows have the
+     * given rowids, an index t_a over its first column and a table without rows. Row r holds the text of r / 2 so
+     * every value of a is shared by two neighbouring rowids, and r itself in b. Returns the path of the file.
+     */
+    pub(crate) fn write_database(name: &str, rowids: &[i64]) -> String {
+        let values = |rowid: i64| {
+            vec![
+                SerialData::Text(format!("{:05}", rowid / 2)),
+                SerialData::I64(rowid),
+            ]
+        };
+        let rows = rowids
+            .iter()
+            .map(|rowid| (*rowid, record(&values(*rowid))))
+            .collect::<Vec<_>>();
+        let entries = rowids
+            .iter()
+            .map(|rowid| record(&values(*rowid)))
+            .collect::<Vec<_>>();
+
+        let mut pages = table_btree_pages(2, &rows);
+        let index_root_page_num = pages.len() as u32 + 2;
+        pages.extend(index_btree_pages(index_root_page_num, &entries));
+        let empty_root_page_num = pages.len() as u32 + 2;
+        pages.push(btree_page(0x0d, &[], None, 0));
+
+        let schema_rows = [
+            ("table", "t", 2, "CREATE TABLE t (a TEXT, b INTEGER)"),
+            (
+                "index",
+                "t_a",
+                index_root_page_num,
+                "CREATE INDEX t_a ON t (a)",
+            ),
+            (
+                "table",
+                "empty",
+                empty_root_page_num,
+                "CREATE TABLE empty (a TEXT, b INTEGER)",
+            ),
+        ];
+        let schema_cells = schema_rows
+            .iter()
+            .enumerate()
+            .map(|(i, (obj_type, name, root_page_num, sql))| {
+                let table_name = if *obj_type == "index" { "t" } else { name };
+                let payload = record(&[
+                    SerialData::Text(obj_type.to_string()),
+                    SerialData::Text(name.to_string()),
+                    SerialData::Text(table_name.to_string()),
+                    SerialData::I64(*root_page_num as i64),
+                    SerialData::Text(sql.to_string()),
+                ]);
+                [varint(payload.len() as u64), varint(i as u64 + 1), payload].concat()
+            })
+            .collect::<Vec<_>>();
+
+        let mut first_page = btree_page(0x0d, &schema_cells, None, 100);
+        first_page[..16].copy_from_slice(b"SQLite format 3\0");
+        first_page[16..18].copy_from_slice(&(PAGE_SIZE as u16).to_be_bytes());
+        // file format versions, reserved bytes and the payload fractions SQLite requires
+        first_page[18..24].copy_from_slice(&[1, 1, 0, 64, 32, 32]);
+        first_page[28..32].copy_from_slice(&(pages.len() as u32 + 1).to_be_bytes());
+        first_page[44..48].copy_from_slice(&4u32.to_be_bytes());
+        first_page[56..60].copy_from_slice(&1u32.to_be_bytes());
+
+        let path = std::env::temp_dir().join(format!("{name}-{}.db", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let mut file = File::create(&path).unwrap();
+        file.write_all(&first_page).unwrap();
+        for page in &pages {
+            file.write_all(page).unwrap();
+        }
+        path
+    }
+
+    // the rowid every test entry ends with, the key of table rows and the last column of index entries
+    fn trailing_rowid(entry: Option<Result<ReadableRecord>>) -> Option<i64> {
+        entry.map(
+            |record| match record.unwrap().read_record().unwrap().last() {
+                Some(SerialData::I64(rowid)) => *rowid,
+                other => panic!("expected a trailing rowid, got {other:?}"),
+            },
+        )
+    }
+
+    #[test]
+    fn test_cursor_moves_both_ways_across_pages() {
+        let count = 600;
+        let path = write_database("cursor", &(1..=count).collect::<Vec<_>>());
+        let database = Database::from_file(&path).unwrap();
+
+        let mut cursor = database.get_table("empty").unwrap().iter();
+        assert!(cursor.seek_first().is_none());
+        assert!(cursor.seek_last().is_none());
+        assert!(cursor.next().is_none());
+        assert!(cursor.prev().is_none());
+
+        for btree in [
+            database.get_table("t").unwrap(),
+            database.get_index("t_a").unwrap(),
+        ] {
+            let mut cursor = btree.iter();
+            assert_eq!(trailing_rowid(cursor.seek_first()), Some(1));
+            for n in 2..=count {
+                assert_eq!(trailing_rowid(cursor.next()), Some(n));
+            }
+            assert!(cursor.next().is_none());
+            assert!(cursor.next().is_none());
+            // a cursor that ran off the end starts over from the other end when turned around
+            assert_eq!(trailing_rowid(cursor.prev()), Some(count));
+
+            assert_eq!(trailing_rowid(cursor.seek_last()), Some(count));
+            for n in (1..count).rev() {
+                assert_eq!(trailing_rowid(cursor.prev()), Some(n));
+            }
+            assert!(cursor.prev().is_none());
+            assert!(cursor.prev().is_none());
+            assert_eq!(trailing_rowid(cursor.next()), Some(1));
+
+            // turning around on every entry, across every leaf and interior entry
+            assert_eq!(trailing_rowid(cursor.seek_first()), Some(1));
+            for n in 2..=count {
+                assert_eq!(trailing_rowid(cursor.next()), Some(n));
+                assert_eq!(trailing_rowid(cursor.prev()), Some(n - 1));
+                assert_eq!(trailing_rowid(cursor.next()), Some(n));
+            }
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_cursor_stops_at_a_corrupt_page() {
+        let path = write_database("corrupt-cursor", &(1..=600).collect::<Vec<_>>());
+        // page 2 is the root of t, the second of its leaves no longer reads as a btree page
+        let mut file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(3 * PAGE_SIZE as u64)).unwrap();
+        file.write_all(&[0xff; PAGE_SIZE]).unwrap();
+        let btree = Database::from_file(&path).unwrap().get_table("t").unwrap();
+
+        // stopping at the error rather than starting over is what lets callers skipping errors come to an end
+        let forward = btree.iter().take(10_000).collect::<Vec<_>>();
+        assert_eq!(forward.len(), ENTRIES_PER_LEAF + 1);
+        assert!(forward.last().unwrap().is_err());
+        assert_eq!(
+            btree.iter().filter_map(Result::ok).take(10_000).count(),
+            ENTRIES_PER_LEAF
+        );
+
+        let mut cursor = btree.iter();
+        cursor.seek_last().unwrap().unwrap();
+        let mut backward = 1;
+        while let Some(Ok(_)) = cursor.prev() {
+            backward += 1;
+        }
+        assert_eq!(backward, 600 - 2 * ENTRIES_PER_LEAF);
+        assert!(cursor.prev().is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_get_by_rowid_finds_rows_at_every_level() {
+        // every third rowid, so each row has a missing rowid on either side
+        let rowids = (0..600).map(|n| n * 3 + 5).collect::<Vec<i64>>();
+        let path = write_database("get-by-rowid", &rowids);
+        let database = Database::from_file(&path).unwrap();
+        let btree = database.get_table("t").unwrap();
+        let get = |rowid: i64| {
+            btree
+                .get_by_rowid(rowid)
+                .unwrap()
+                .map(|record| trailing_rowid(Some(Ok(record))).unwrap())
+        };
+
+        // the first and last rows of every leaf are the ones next to the dividers of the interior root
+        for rowid in &rowids {
+            assert_eq!(get(*rowid), Some(*rowid));
+            assert_eq!(get(rowid - 1), None);
+            assert_eq!(get(rowid + 1), None);
+        }
+        for rowid in [i64::MIN, -1, 0, 4, 1803, i64::MAX] {
+            assert_eq!(get(rowid), None);
+        }
+        let empty = database.get_table("empty").unwrap();
+        assert!(empty.get_by_rowid(1).unwrap().is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_scan_rowid_range_honours_every_kind_of_bound() {
+        let rowids = (0..600).map(|n| n * 2).collect::<Vec<i64>>();
+        let path = write_database("rowid-range", &rowids);
+        let btree = Database::from_file(&path).unwrap().get_table("t").unwrap();
+        let scan = |lower_bound: Bound<i64>, upper_bound: Bound<i64>| {
+            btree
+                .scan_rowid_range(lower_bound, upper_bound)
+                .unwrap()
+                .map(|row| {
+                    let (rowid, record) = row.unwrap();
+                    assert_eq!(trailing_rowid(Some(Ok(record))), Some(rowid));
+                    rowid
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // rowids on either side of the first ones of some leaves, where a range has to carry on onto the next leaf
+        let mut points = vec![i64::MIN, -1, 0, 1, 599, 600, 1197, 1198, 1199, i64::MAX];
+        for start in rowids.iter().step_by(ENTRIES_PER_LEAF).skip(1).take(3) {
+            points.extend([start - 1, *start, start + 1]);
+        }
+        let bounds = points
+            .iter()
+            .flat_map(|point| [Bound::Included(*point), Bound::Excluded(*point)])
+            .chain([Bound::Unbounded])
+            .collect::<Vec<_>>();
+        for lower_bound in &bounds {
+            for upper_bound in &bounds {
+                let expected = rowids
+                    .iter()
+                    .copied()
+                    .filter(|rowid| (*lower_bound, *upper_bound).contains(rowid))
+                    .collect::<Vec<i64>>();
+                assert_eq!(
+                    scan(*lower_bound, *upper_bound),
+                    expected,
+                    "{lower_bound:?}..{upper_bound:?}"
+                );
+            }
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    // a new database in the temp directory and its sqlite_schema btree, the only btree an empty database has
+    fn create_schema_btree(name: &str, options: CreateOptions) -> (String, Btree) {
+        let path = std::env::temp_dir().join(format!("{name}-{}.db", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let _ = std::fs::remove_file(&path);
+        Database::create(&path, options).unwrap();
+
+        let pager = Rc::new(RefCell::new(Pager::open(&path).unwrap()));
+        (path, Btree::read_schema_table(pager).unwrap())
+    }
+
+    // a new index btree in the database of another btree, rooted on an empty leaf page at the end of the file
+    fn create_empty_index(btree: &Btree) -> Btree {
+        let mut pager = btree.pager.borrow_mut();
+        let page_num = pager.allocate_page().unwrap();
+        let root =
+            BtreePage::new_empty(page_num, PageType::LeafIndex, pager.page_size(), 0, 0).unwrap();
+        pager.write_page(&root).unwrap();
+        drop(pager);
+        Btree::open(btree.pager.clone(), page_num).unwrap()
+    }
+
+    // the depth of the subtree under page_num, checking every page but an empty root holds cells at the same depth
+    fn check_subtree(btree: &Btree, page_num: u32) -> usize {
+        let page = btree.read_page(page_num).unwrap();
+        if page_num != btree.root_page_num() {
+            assert!(page.num_cells() > 0, "page {page_num} is empty");
+        }
+        if page.is_leaf() {
+            return 1;
+        }
+        let depths = (0..=page.num_cells())
+            .map(|idx| check_subtree(btree, btree.child_page_number(&page, idx).unwrap()))
+            .collect::<Vec<_>>();
+        assert!(depths.iter().all(|depth| *depth == depths[0]));
+        depths[0] + 1
+    }
+
+    #[test]
+    fn test_insert_splits_pages_up_to_the_root() {
+        let options = CreateOptions {
+            page_size: 512,
+            ..CreateOptions::default()
+        };
+        // sqlite_schema is rooted on page 1, so its splits have to work around the database header
+        let (path, btree) = create_schema_btree("insert", options);
+        let path = path.as_str();
+        let rowids = (0..3000)
+            .map(|i| (i * 7919) % 3001 - 1500)
+            .collect::<Vec<i64>>();
+        for rowid in &rowids {
+            let text = "x".repeat((*rowid).unsigned_abs() as usize % 120);
+            btree
+                .insert(*rowid, &[SerialData::I64(*rowid), SerialData::Text(text)])
+                .unwrap();
+        }
+        assert!(matches!(
+            btree.insert(rowids[0], &[]),
+            Err(StorageError::Constraint(_))
+        ));
+
+        let root = btree.read_page(1).unwrap();
+        assert!(!root.is_leaf());
+        assert_eq!(root.header_offset(), 100);
+
+        let mut sorted = rowids.clone();
+        sorted.sort();
+        let mut cursor = btree.iter();
+        for rowid in &sorted {
+            let record = cursor.next().unwrap().unwrap().read_record().unwrap();
+            assert_eq!(cursor.current_rowid().unwrap(), Some(*rowid));
+            assert_eq!(record[0].as_integer(), Some(*rowid));
+        }
+        assert!(cursor.next().is_none());
+        assert!(btree.get_by_rowid(-1500).unwrap().is_some());
+        assert_eq!(btree.max_rowid().unwrap(), sorted.last().copied());
+
+        // the header follows the file as it grows
+        let reopened = Pager::open(path).unwrap();
+        assert_eq!(
+            reopened.metadata().db_size_in_pages as u64 * 512,
+            std::fs::metadata(path).unwrap().len()
+        );
+        assert_eq!(
+            reopened.metadata().version_valid_for,
+            reopened.metadata().file_change_counter
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_delete_merges_pages_and_collapses_the_root() {
+        let options = CreateOptions {
+            page_size: 512,
+            ..CreateOptions::default()
+        };
+        let (path, btree) = create_schema_btree("delete", options);
+        for rowid in 0..2000 {
+            // every tenth row spills onto an overflow page
+            let len = if rowid % 10 == 0 { 1000 } else { 40 };
+            btree
+                .insert(rowid, &[SerialData::Text("x".repeat(len))])
+                .unwrap();
+        }
+        assert!(!btree.delete(2000).unwrap());
+
+        // delete all but every seventh row in a scattered order
+        let doomed = (0..2000)
+            .map(|i| (i * 1117) % 2000)
+            .filter(|rowid| rowid % 7 != 0)
+            .collect::<Vec<i64>>();
+        for rowid in &doomed {
+            assert!(btree.delete(*rowid).unwrap());
+        }
+        let mut cursor = btree.iter();
+        for rowid in (0..2000).step_by(7) {
+            cursor.next().unwrap().unwrap();
+            assert_eq!(cursor.current_rowid().unwrap(), Some(rowid));
+        }
+        assert!(cursor.next().is_none());
+
+        for rowid in (0..2000).step_by(7) {
+            assert!(btree.delete(rowid).unwrap());
+        }
+        assert!(btree.get_rows().unwrap().is_empty());
+        let root = btree.read_page(1).unwrap();
+        assert!(root.is_leaf());
+        assert_eq!(root.num_cells(), 0);
+
+        // every page but page 1 ended up on the freelist
+        let pager = btree.pager.borrow();
+        assert_eq!(
+            pager.metadata().total_freelist_pages,
+            pager.page_count() - 1
+        );
+        drop(pager);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_update_rewrites_records_in_place_or_moves_them() {
+        let options = CreateOptions {
+            page_size: 512,
+            ..CreateOptions::default()
+        };
+        let (path, btree) = create_schema_btree("update", options);
+        let text = |len: usize| SerialData::Text("u".repeat(len));
+        for rowid in 0..200 {
+            btree.insert(rowid, &[text(70)]).unwrap();
+        }
+        let leaf_of = |rowid: i64| {
+            let (page, idx, _) = btree.find_leaf(rowid).unwrap();
+            (page.page_num, page.cell_offset(idx).unwrap())
+        };
+
+        // a shorter record stays in the cell it had, at its end
+        let (page_num, offset) = leaf_of(50);
+        assert!(btree.update(50, &[text(64)]).unwrap());
+        assert_eq!(leaf_of(50), (page_num, offset + 6));
+        // one that outgrows its cell is moved, onto overflow pages and back again
+        assert!(btree.update(51, &[text(300)]).unwrap());
+        assert!(btree.update(52, &[text(3000)]).unwrap());
+        assert!(btree.update(52, &[text(1)]).unwrap());
+        assert!(!btree.update(200, &[text(1)]).unwrap());
+
+        let lengths = btree
+            .get_rows()
+            .unwrap()
+            .iter_mut()
+            .map(|record| match &record.read_record().unwrap()[0] {
+                SerialData::Text(text) => text.len(),
+                other => panic!("unexpected {other:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(lengths.len(), 200);
+        assert_eq!(lengths[49..54], [70, 64, 300, 1, 70]);
+        // the pages the 3000 byte record spilled onto went back to the freelist
+        assert_eq!(btree.pager.borrow().metadata().total_freelist_pages, 5);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_insert_spills_large_records_onto_overflow_pages() {
+        let options = CreateOptions {
+            page_size: 512,
+            reserved_bytes_per_page: 8,
+            ..CreateOptions::default()
+        };
+        let (path, btree) = create_schema_btree("overflow", options);
+
+        // from fitting on the leaf, to just spilling, to a chain of dozens of overflow pages
+        let lengths = [400, 470, 471, 1000, 4000, 20000];
+        for (rowid, len) in lengths.iter().enumerate() {
+            let blob = (0..*len).map(|n| (n % 251) as u8).collect::<Vec<u8>>();
+            btree
+                .insert(rowid as i64, &[SerialData::Blob(blob), SerialData::I64(-1)])
+                .unwrap();
+        }
+
+        for (record, len) in btree.get_rows().unwrap().iter_mut().zip(lengths) {
+            let record = record.read_record().unwrap();
+            let blob = (0..len).map(|n| (n % 251) as u8).collect::<Vec<u8>>();
+            assert!(matches!(&record[0], SerialData::Blob(read) if *read == blob));
+            assert_eq!(record[1].as_integer(), Some(-1));
+        }
+
+        let usable_size = 512 - 8;
+        let overflow_pages = lengths
+            .iter()
+            .map(|len| {
+                let record = [SerialData::Blob(vec![0; *len]), SerialData::I64(-1)];
+                let payload_size = Record::new(record.to_vec())
+                    .to_be_bytes(&SchemaFormatNumber::Fmt4)
+                    .len();
+                let local_size =
+                    local_payload_size(&PageType::LeafTable, payload_size, usable_size);
+                (payload_size - local_size).div_ceil(usable_size - 4)
+            })
+            .sum::<usize>();
+        assert!(overflow_pages > 40);
+        // the leaves do not all fit on page 1, which now points at one leaf per divider plus the right most one
+        let root = btree.read_page(1).unwrap();
+        let btree_pages = 1 + root.num_cells() + 1;
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().len(),
+            ((btree_pages + overflow_pages) * 512) as u64
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_index_entries_are_kept_in_key_order() {
+        let options = CreateOptions {
+            page_size: 512,
+            ..CreateOptions::default()
+        };
+        let (path, schema) = create_schema_btree("index", options);
+        let index = create_empty_index(&schema);
+        let root_page_num = index.root_page_num();
+        // every thirtieth key is too large for a page and spills onto overflow pages
+        let key = |rowid: i64| {
+            let len = if rowid % 30 == 0 { 900 } else { 20 };
+            vec![
+                SerialData::Text(format!("{:04}", rowid % 97).repeat(len / 4)),
+                SerialData::I64(rowid),
+            ]
+        };
+        let rowids = (0..1500).map(|i| (i * 7919) % 1500).collect::<Vec<i64>>();
+        for rowid in &rowids {
+            index.insert_index_entry(&key(*rowid)).unwrap();
+        }
+        assert!(matches!(
+            index.insert_index_entry(&key(3)),
+            Err(StorageError::Constraint(_))
+        ));
+        assert!(!index.read_page(root_page_num).unwrap().is_leaf());
+        assert!(matches!(
+            schema.insert_index_entry(&key(3)),
+            Err(StorageError::InvalidOperation(_))
+        ));
+
+        let entries = |index: &Btree| {
+            index
+                .iter()
+                .map(|record| record.unwrap().read_record().unwrap())
+                .collect::<Vec<_>>()
+        };
+        let mut sorted = rowids.iter().map(|rowid| key(*rowid)).collect::<Vec<_>>();
+        sorted.sort_by(|a, b| compare_records(a, b));
+        let found = entries(&index);
+        assert_eq!(found.len(), sorted.len());
+        assert!(found
+            .iter()
+            .zip(&sorted)
+            .all(|(a, b)| compare_records(a, b) == Ordering::Equal));
+        let seek = index.index_seek(&key(45)[..1]).unwrap();
+        assert!(seek.iter().any(|(_, rowid)| *rowid == 45));
+
+        // deleting entries that sit on interior pages moves the entry before them up in their place
+        for rowid in rowids.iter().filter(|rowid| *rowid % 3 != 0) {
+            assert!(index.delete_index_entry(&key(*rowid)).unwrap());
+        }
+        assert!(!index.delete_index_entry(&key(1)).unwrap());
+        sorted.retain(|entry| entry[1].as_integer().unwrap() % 3 == 0);
+        let found = entries(&index);
+        assert_eq!(found.len(), sorted.len());
+        assert!(found
+            .iter()
+            .zip(&sorted)
+            .all(|(a, b)| compare_records(a, b) == Ordering::Equal));
+
+        for rowid in rowids.iter().filter(|rowid| *rowid % 3 == 0) {
+            assert!(index.delete_index_entry(&key(*rowid)).unwrap());
+        }
+        let root = index.read_page(root_page_num).unwrap();
+        assert!(root.is_leaf());
+        assert_eq!(root.num_cells(), 0);
+        // every page but the schema table and the index root went back to the freelist
+        let pager = index.pager.borrow();
+        assert_eq!(
+            pager.metadata().total_freelist_pages,
+            pager.page_count() - 2
+        );
+        drop(pager);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_build_index_packs_sorted_entries_bottom_up() {
+        let options = CreateOptions {
+            page_size: 512,
+            ..CreateOptions::default()
+        };
+        let (path, schema) = create_schema_btree("build-index", options);
+        // every seventeenth entry spills onto overflow pages
+        let entry = |n: i64| {
+            let len = if n % 17 == 0 {
+                700
+            } else {
+                10 + n as usize % 30
+            };
+            vec![
+                SerialData::Text(format!("{n:06}").repeat(len / 6 + 1)),
+                SerialData::I64(n),
+            ]
+        };
+        let entries = |index: &Btree| {
+            index
+                .iter()
+                .map(|record| {
+                    record.unwrap().read_record().unwrap()[1]
+                        .as_integer()
+                        .unwrap()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // the entries run out at every point of filling the pages on each level
+        for count in (0..1500).step_by(37).chain([3000]) {
+            let index = create_empty_index(&schema);
+            index.build_index((0..count).map(|n| Ok(entry(n)))).unwrap();
+            assert_eq!(entries(&index), (0..count).collect::<Vec<_>>());
+            let depth = check_subtree(&index, index.root_page_num());
+            if count == 3000 {
+                assert!(depth >= 3);
+                // the built tree takes inserts and deletes like any other
+                for n in (0..count).step_by(2) {
+                    assert!(index.delete_index_entry(&entry(n)).unwrap());
+                }
+                index.insert_index_entry(&entry(count)).unwrap();
+                let mut expected = (1..count).step_by(2).collect::<Vec<_>>();
+                expected.push(count);
+                assert_eq!(entries(&index), expected);
+                check_subtree(&index, index.root_page_num());
+            }
+        }
+
+        // a failed build leaves the index empty and hands back the pages written for it
+        let index = create_empty_index(&schema);
+        let page_counts = || {
+            let pager = schema.pager.borrow();
+            (pager.page_count(), pager.metadata().total_freelist_pages)
+        };
+        let (page_count, free_page_count) = page_counts();
+        let failing = (0..500).map(|n| match n {
+            400 => Err(StorageError::Constraint("duplicate".to_string())),
+            n => Ok(entry(n)),
+        });
+        assert!(matches!(
+            index.build_index(failing),
+            Err(StorageError::Constraint(_))
+        ));
+        assert_eq!(
+            index.read_page(index.root_page_num()).unwrap().num_cells(),
+            0
+        );
+        let (grown_page_count, grown_free_page_count) = page_counts();
+        assert!(grown_free_page_count > 0);
+        assert_eq!(
+            grown_free_page_count - free_page_count,
+            grown_page_count - page_count
+        );
+        assert!(matches!(
+            schema.build_index(std::iter::empty()),
+            Err(StorageError::InvalidOperation(_))
+        ));
+        std::fs::remove_file(path).unwrap();
     }
 }
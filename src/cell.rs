@@ -1,16 +1,128 @@
 use crate::{
+    error::{Result, StorageError},
+    page::{read_u32, PageType},
     record::{OverflowRecord, ReadableRecord, Record},
     sql_data_types::VarInt,
 };
-use anyhow::Result;
+
+// how many bytes of a payload are stored on a btree page of page_type itself, see the spillage rules in the
+// from_be_bytes of the leaf cells, what does not fit here goes onto overflow pages
+pub fn local_payload_size(page_type: &PageType, payload_size: usize, usable_size: usize) -> usize {
+    let max_local = match page_type {
+        PageType::LeafTable | PageType::InteriorTable => usable_size - 35,
+        PageType::LeafIndex | PageType::InteriorIndex => (usable_size - 12) * 64 / 255 - 23,
+    };
+    if payload_size <= max_local {
+        return payload_size;
+    }
+
+    let min_local = (usable_size - 12) * 32 / 255 - 23;
+    let k = min_local + (payload_size - min_local) % (usable_size - 4);
+    if k <= max_local {
+        k
+    } else {
+        min_local
+    }
+}
+
+// the number of bytes a cell takes up on a page of page_type, cell_content starts at the cell and may run on past it
+pub fn cell_size(page_type: &PageType, cell_content: &[u8], usable_size: usize) -> Result<usize> {
+    let size = match local_payload_end(page_type, cell_content, usable_size)? {
+        // the page number of the first overflow page follows the local payload
+        (end, true) => end + 4,
+        (end, false) => end,
+    };
+
+    // freeing a cell turns it into a freeblock, which needs 4 bytes
+    Ok(size.max(4))
+}
+
+// the first page of the overflow chain a cell spills onto, None when its payload is stored on the page in full
+pub fn first_overflow_page(
+    page_type: &PageType,
+    cell_content: &[u8],
+    usable_size: usize,
+) -> Result<Option<u32>> {
+    match local_payload_end(page_type, cell_content, usable_size)? {
+        (end, true) => Ok(Some(read_u32(cell_content, end)?)),
+        (_, false) => Ok(None),
+    }
+}
+
+// where the part of a cell stored on the page ends, leaving out the overflow page number, and whether the payload spills
+fn local_payload_end(
+    page_type: &PageType,
+    cell_content: &[u8],
+    usable_size: usize,
+) -> Result<(usize, bool)> {
+    // interior cells lead with the 4 byte left child page number
+    let mut size = match page_type {
+        PageType::InteriorIndex | PageType::InteriorTable => 4,
+        PageType::LeafIndex | PageType::LeafTable => 0,
+    };
+
+    if let PageType::InteriorTable = page_type {
+        let integer_key = VarInt::from_be_bytes(cell_content.get(size..).unwrap_or_default())?;
+        return Ok((size + integer_key.1 as usize, false));
+    }
+
+    let payload_size = VarInt::from_be_bytes(cell_content.get(size..).unwrap_or_default())?;
+    size += payload_size.1 as usize;
+    if let PageType::LeafTable = page_type {
+        size += VarInt::from_be_bytes(cell_content.get(size..).unwrap_or_default())?.1 as usize;
+    }
+
+    let payload_size = payload_bytes(payload_size.0)?;
+    let local_size = local_payload_size(page_type, payload_size, usable_size);
+    Ok((size + local_size, local_size < payload_size))
+}
+
+fn payload_bytes(payload_size: i64) -> Result<usize> {
+    usize::try_from(payload_size)
+        .map_err(|_| StorageError::corrupt(format!("negative payload size {payload_size}")))
+}
+
+// appends the bytes of a varint
+fn push_varint(cell: &mut Vec<u8>, value: i64) {
+    let (bytes, len) = VarInt::encode(value);
+    cell.extend_from_slice(&bytes[..len]);
+}
 
 pub struct TableLeafCell {
-    pub total_bytes_of_payload: VarInt,
-    pub integer_key: VarInt,
     pub payload: ReadableRecord,
 }
 
 impl TableLeafCell {
+    // reads only the rowid of a leaf cell, skipping over the payload size without decoding the record
+    pub fn integer_key_from_be_bytes(cell_content: &[u8]) -> Result<i64> {
+        let total_bytes_of_payload = VarInt::from_be_bytes(cell_content)?;
+        let integer_key =
+            VarInt::from_be_bytes(&cell_content[total_bytes_of_payload.1 as usize..])?;
+        Ok(integer_key.0)
+    }
+
+    /*
+     * The cell for a row, local_payload is the part of the payload of payload_size bytes that stays on the leaf and
+     * first_overflow_page heads the chain holding the rest when it spills. Cells are never shorter than 4 bytes so the
+     * space can become a freeblock once the cell is deleted.
+     */
+    pub fn to_be_bytes(
+        rowid: i64,
+        payload_size: usize,
+        local_payload: &[u8],
+        first_overflow_page: Option<u32>,
+    ) -> Vec<u8> {
+        let mut cell = Vec::with_capacity(local_payload.len() + 22);
+        push_varint(&mut cell, payload_size as i64);
+        push_varint(&mut cell, rowid);
+        cell.extend_from_slice(local_payload);
+        if let Some(page_num) = first_overflow_page {
+            cell.extend_from_slice(&page_num.to_be_bytes());
+        }
+        cell.resize(cell.len().max(4), 0);
+        cell
+    }
+
     // returns the (cell content and the number of bytes read) from cell_content buffer
     pub fn from_be_bytes(
         db_file_name: String,
@@ -39,27 +151,18 @@ impl TableLeafCell {
          * If P is greater than X then the number of bytes stored on the table b-tree leaf page is K if K is less or equal to X or M otherwise.
          * The number of bytes stored on the leaf page is never less than M.
          */
-        let usable_page_size = page_size - reserved_bytes_per_page as u16;
-        let x = usable_page_size - 35;
-        let m: u64 = ((usable_page_size - 12) as u64 * 32 / 255) - 23;
-        let k = m as i64
-            + ((total_bytes_of_payload.0 as i64 - m as i64) % (usable_page_size as i64 - 4));
-        let bytes_stored_on_leaf_page = if total_bytes_of_payload.0 <= x as i64 {
-            total_bytes_of_payload.0
-        } else {
-            if k <= x as i64 {
-                k
-            } else {
-                m as i64
-            }
-        };
+        let usable_page_size = (page_size - reserved_bytes_per_page as u16) as usize;
+        let payload_size = payload_bytes(total_bytes_of_payload.0)?;
+        let bytes_stored_on_leaf_page =
+            local_payload_size(&PageType::LeafTable, payload_size, usable_page_size);
 
-        let record = if total_bytes_of_payload.0 > x.try_into()? {
+        let record = if bytes_stored_on_leaf_page < payload_size {
             let record = OverflowRecord::from_be_bytes(
-                bytes_stored_on_leaf_page,
+                bytes_stored_on_leaf_page as i64,
                 &cell_content[bytes_read..],
                 db_file_name.clone(),
                 page_size,
+                reserved_bytes_per_page,
             )?;
             bytes_read += record.1 as usize;
             ReadableRecord::Lazy(record.0)
@@ -69,14 +172,7 @@ impl TableLeafCell {
             ReadableRecord::Fit(record.0)
         };
 
-        Ok((
-            Self {
-                total_bytes_of_payload,
-                integer_key,
-                payload: record,
-            },
-            bytes_read as u64,
-        ))
+        Ok((Self { payload: record }, bytes_read as u64))
     }
 }
 
@@ -91,8 +187,14 @@ pub struct TableInteriorCell {
 }
 
 impl TableInteriorCell {
+    pub fn to_be_bytes(left_child_page_number: u32, integer_key: i64) -> Vec<u8> {
+        let mut cell = left_child_page_number.to_be_bytes().to_vec();
+        push_varint(&mut cell, integer_key);
+        cell
+    }
+
     pub fn from_be_bytes(cell_content: &[u8]) -> Result<(Self, u64)> {
-        let left_child_page_number = u32::from_be_bytes(cell_content[..4].try_into()?);
+        let left_child_page_number = read_u32(cell_content, 0)?;
 
         let integer_key = VarInt::from_be_bytes(&cell_content[4..])?;
 
@@ -109,11 +211,26 @@ impl TableInteriorCell {
 // Index Cells
 
 pub struct IndexLeafCell {
-    pub total_bytes_of_payload: VarInt,
     pub payload: ReadableRecord,
 }
 
 impl IndexLeafCell {
+    // an index leaf cell holding local_payload, the first payload_size bytes of the key when it spills, see to_be_bytes of TableLeafCell
+    pub fn to_be_bytes(
+        payload_size: usize,
+        local_payload: &[u8],
+        first_overflow_page: Option<u32>,
+    ) -> Vec<u8> {
+        let mut cell = Vec::with_capacity(local_payload.len() + 13);
+        push_varint(&mut cell, payload_size as i64);
+        cell.extend_from_slice(local_payload);
+        if let Some(page_num) = first_overflow_page {
+            cell.extend_from_slice(&page_num.to_be_bytes());
+        }
+        cell.resize(cell.len().max(4), 0);
+        cell
+    }
+
     pub fn from_be_bytes(
         db_file_name: String,
         cell_content: &[u8],
@@ -138,28 +255,18 @@ impl IndexLeafCell {
         stored on the index b-tree page is K if K is less than or equal to X or M otherwise.
         The number of bytes stored on the index page is never less than M.
         */
-        let usable_page_size = page_size - reserved_bytes_per_page as u16;
-        let x = (((usable_page_size as f64 - 12.0) * 64.0 / 255.0) - 23.0).round() as i32;
-
-        let record = if total_bytes_of_payload.0 > x.try_into()? {
-            let m: u64 = ((usable_page_size - 12) as u64 * 32 / 255) - 23;
-            let k = m as i64
-                + ((total_bytes_of_payload.0 as i64 - m as i64) % (usable_page_size as i64 - 4));
-            let bytes_stored_on_leaf_page = if total_bytes_of_payload.0 <= x as i64 {
-                total_bytes_of_payload.0
-            } else {
-                if k <= x as i64 {
-                    k
-                } else {
-                    m as i64
-                }
-            };
+        let usable_page_size = (page_size - reserved_bytes_per_page as u16) as usize;
+        let payload_size = payload_bytes(total_bytes_of_payload.0)?;
+        let bytes_stored_on_leaf_page =
+            local_payload_size(&PageType::LeafIndex, payload_size, usable_page_size);
 
+        let record = if bytes_stored_on_leaf_page < payload_size {
             let record = OverflowRecord::from_be_bytes(
-                bytes_stored_on_leaf_page,
+                bytes_stored_on_leaf_page as i64,
                 &cell_content[bytes_read..],
                 db_file_name.clone(),
                 page_size,
+                reserved_bytes_per_page,
             )?;
             ReadableRecord::Lazy(record.0)
         } else {
@@ -167,46 +274,52 @@ impl IndexLeafCell {
             // BUG CAUGHT! Each entry in an index b-tree consists of an arbitrary key of up to 2147483647 bytes in length and no data
             // THIS IS DFIFERENT FROM FUCKING TABLE BTREE THAT STORES REcord in FUCKING Entry
             if total_bytes_of_payload.0 > (cell_content.len() - bytes_read) as i64 {
-                panic!("Corrupted leaf index cell");
+                return Err(StorageError::Corrupt {
+                    page: 0,
+                    offset: bytes_read,
+                    reason: format!(
+                        "index cell payload of {} bytes runs past the end of the page",
+                        total_bytes_of_payload.0
+                    ),
+                });
             }
             let record = Record::from_be_bytes(&cell_content[bytes_read..])?;
             bytes_read += record.1 as usize;
             ReadableRecord::Fit(record.0)
         };
 
-        Ok((
-            Self {
-                total_bytes_of_payload,
-                payload: record,
-            },
-            bytes_read as u64,
-        ))
+        Ok((Self { payload: record }, bytes_read as u64))
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct IndexInteriorCell {
     pub left_child_page_number: u32,
-    pub total_bytes_of_payload: VarInt,
     pub payload: ReadableRecord,
 }
 
 impl IndexInteriorCell {
-    pub fn from_be_bytes(cell_content: &[u8]) -> Result<(Self, u64)> {
-        let left_child_page_number = u32::from_be_bytes(cell_content[..4].try_into()?);
-
-        let total_bytes_of_payload = VarInt::from_be_bytes(&cell_content[4..])?;
-        let bytes_read = 4 + total_bytes_of_payload.1 as u64;
+    // an interior index cell is the left child page number followed by what an index leaf cell holds
+    pub fn from_be_bytes(
+        db_file_name: String,
+        cell_content: &[u8],
+        page_size: u16,
+        reserved_bytes_per_page: u8,
+    ) -> Result<(Self, u64)> {
+        let left_child_page_number = read_u32(cell_content, 0)?;
+        let (leaf_cell, bytes_read) = IndexLeafCell::from_be_bytes(
+            db_file_name,
+            &cell_content[4..],
+            page_size,
+            reserved_bytes_per_page,
+        )?;
 
         Ok((
             Self {
                 left_child_page_number,
-                total_bytes_of_payload,
-                payload: ReadableRecord::Fit(
-                    Record::from_be_bytes(&cell_content[bytes_read as usize..])?.0,
-                ),
+                payload: leaf_cell.payload,
             },
-            bytes_read,
+            4 + bytes_read,
         ))
     }
 }
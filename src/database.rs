@@ -27,14 +27,25 @@ Offset	Size	Description
 */
 
 use crate::btree::Btree;
-use crate::sql_data_types::{SerialData, SerialType};
-use anyhow::{bail, Result};
-use nom::character::complete::tab;
-use std::convert::TryInto;
-use std::fs::File;
-use std::io::Read;
-
-#[derive(Debug)]
+use crate::error::{Result, StorageError};
+use crate::page::{read_u16, read_u32, BtreePage, PageType};
+use crate::pager::Pager;
+use crate::sql_data_types::SerialData;
+use crate::sql_parser::{
+    find_schema_from_create_stmt, parse_create_table_stmt, ColumnConstraint, CreateIndexStmt,
+    CreateTableStmt, SqlColumn, TableConstraint,
+};
+use crate::table::Table;
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Bound;
+use std::rc::Rc;
+
+// the release of SQLite whose file format is written, recorded in the header of every file we change like sqlite3 would
+const SQLITE_VERSION_NUMBER: u32 = 3051002;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum FileFormatVersion {
     LEGACY,
     WAL,
@@ -48,9 +59,16 @@ impl FileFormatVersion {
             _ => None,
         }
     }
+
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Self::LEGACY => 1,
+            Self::WAL => 2,
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum SchemaFormatNumber {
     Fmt1,
     Fmt2,
@@ -68,9 +86,18 @@ impl SchemaFormatNumber {
             _ => None,
         }
     }
+
+    pub fn to_u32(&self) -> u32 {
+        match self {
+            Self::Fmt1 => 1,
+            Self::Fmt2 => 2,
+            Self::Fmt3 => 3,
+            Self::Fmt4 => 4,
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum DatabaseTextEncoding {
     Utf8,
     Utf16le,
@@ -86,10 +113,18 @@ impl DatabaseTextEncoding {
             _ => None,
         }
     }
+
+    pub fn to_u32(&self) -> u32 {
+        match self {
+            Self::Utf8 => 1,
+            Self::Utf16le => 2,
+            Self::Utf16be => 3,
+        }
+    }
 }
 
 // only making this class for ser-deser help
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum IsIncrementalVacuumMode {
     True,
     False,
@@ -102,9 +137,16 @@ impl IsIncrementalVacuumMode {
             _ => Self::True,
         }
     }
+
+    pub fn to_u32(&self) -> u32 {
+        match self {
+            Self::False => 0,
+            Self::True => 1,
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct DataBaseMetadata {
     // 0 - 16
     pub header_str: [u8; 16],
@@ -161,36 +203,96 @@ impl DataBaseMetadata {
         // reads 0-100
         file.read_exact(&mut buffer)?;
 
-        let header_str: [u8; 16] = buffer[0..16].try_into().unwrap();
-        let page_size = u16::from_be_bytes(buffer[16..18].try_into().unwrap());
-        let file_format_write_version = FileFormatVersion::from_u8(buffer[18]).unwrap();
-        let file_format_read_version = FileFormatVersion::from_u8(buffer[19]).unwrap();
+        Self::from_bytes(&buffer).map_err(|err| match err {
+            StorageError::UnsupportedFormat(reason) => {
+                StorageError::UnsupportedFormat(format!("{file_name}: {reason}"))
+            }
+            err => err,
+        })
+    }
+
+    pub fn from_bytes(buffer: &[u8; 100]) -> Result<DataBaseMetadata> {
+        let mut header_str = [0u8; 16];
+        header_str.copy_from_slice(&buffer[0..16]);
+        if &header_str != b"SQLite format 3\0" {
+            return Err(StorageError::UnsupportedFormat(
+                "not a SQLite database".to_string(),
+            ));
+        }
+
+        // a page size of 65536 is stored as 1, every other size is a power of two between 512 and 32768
+        let page_size = read_u16(buffer, 16)?;
+        if page_size == 1 {
+            return Err(StorageError::UnsupportedFormat(
+                "65536 byte pages are not supported".to_string(),
+            ));
+        }
+        if !page_size.is_power_of_two() || page_size < 512 {
+            return Err(StorageError::Corrupt {
+                page: 1,
+                offset: 16,
+                reason: format!("invalid page size {page_size}"),
+            });
+        }
+
+        let file_format_write_version = match FileFormatVersion::from_u8(buffer[18]) {
+            Some(version) => version,
+            None => {
+                return Err(unsupported_header_value(
+                    "file format write version",
+                    18,
+                    buffer[18],
+                ))
+            }
+        };
+        let file_format_read_version = match FileFormatVersion::from_u8(buffer[19]) {
+            Some(version) => version,
+            None => {
+                return Err(unsupported_header_value(
+                    "file format read version",
+                    19,
+                    buffer[19],
+                ))
+            }
+        };
         let bytes_unused_reserved_space_at_page_end = buffer[20];
         let max_embedded_payload_fraction = buffer[21];
         let min_embedded_payload_fraction = buffer[22];
         let leaf_payload_fraction = buffer[23];
-        let file_change_counter = u32::from_be_bytes(buffer[24..28].try_into().unwrap());
-        let db_size_in_pages = u32::from_be_bytes(buffer[28..32].try_into().unwrap());
-        let first_freelist_trunk_page_num = u32::from_be_bytes(buffer[32..36].try_into().unwrap());
-        let total_freelist_pages = u32::from_be_bytes(buffer[36..40].try_into().unwrap());
-        let schema_cookie = u32::from_be_bytes(buffer[40..44].try_into().unwrap());
-        let schema_format_number =
-            SchemaFormatNumber::from_u32(u32::from_be_bytes(buffer[44..48].try_into().unwrap()))
-                .unwrap();
-        let default_page_cache_size = u32::from_be_bytes(buffer[48..52].try_into().unwrap());
-        let page_num_largest_root_btee_in_vacccum =
-            u32::from_be_bytes(buffer[52..56].try_into().unwrap());
-        let database_text_encoding =
-            DatabaseTextEncoding::from_u32(u32::from_be_bytes(buffer[56..60].try_into().unwrap()))
-                .unwrap();
-        let user_version = u32::from_be_bytes(buffer[60..64].try_into().unwrap());
-        let incremental_vacuum_mode = IsIncrementalVacuumMode::from_u32(u32::from_be_bytes(
-            buffer[64..68].try_into().unwrap(),
-        ));
-        let application_id = u32::from_be_bytes(buffer[68..72].try_into().unwrap());
-        let expansion_reserved: [u8; 20] = buffer[72..92].try_into().unwrap();
-        let version_valid_for = u32::from_be_bytes(buffer[92..96].try_into().unwrap());
-        let sqlite_vesion_number = u32::from_be_bytes(buffer[96..100].try_into().unwrap());
+        let file_change_counter = read_u32(buffer, 24)?;
+        let db_size_in_pages = read_u32(buffer, 28)?;
+        let first_freelist_trunk_page_num = read_u32(buffer, 32)?;
+        let total_freelist_pages = read_u32(buffer, 36)?;
+        let schema_cookie = read_u32(buffer, 40)?;
+        let schema_format_number = match SchemaFormatNumber::from_u32(read_u32(buffer, 44)?) {
+            Some(format) => format,
+            None => {
+                return Err(unsupported_header_value(
+                    "schema format number",
+                    44,
+                    read_u32(buffer, 44)?,
+                ))
+            }
+        };
+        let default_page_cache_size = read_u32(buffer, 48)?;
+        let page_num_largest_root_btee_in_vacccum = read_u32(buffer, 52)?;
+        let database_text_encoding = match DatabaseTextEncoding::from_u32(read_u32(buffer, 56)?) {
+            Some(encoding) => encoding,
+            None => {
+                return Err(unsupported_header_value(
+                    "text encoding",
+                    56,
+                    read_u32(buffer, 56)?,
+                ))
+            }
+        };
+        let user_version = read_u32(buffer, 60)?;
+        let incremental_vacuum_mode = IsIncrementalVacuumMode::from_u32(read_u32(buffer, 64)?);
+        let application_id = read_u32(buffer, 68)?;
+        let mut expansion_reserved = [0u8; 20];
+        expansion_reserved.copy_from_slice(&buffer[72..92]);
+        let version_valid_for = read_u32(buffer, 92)?;
+        let sqlite_vesion_number = read_u32(buffer, 96)?;
 
         Ok(DataBaseMetadata {
             header_str,
@@ -218,13 +320,157 @@ impl DataBaseMetadata {
             sqlite_vesion_number,
         })
     }
+
+    // the header of a database that consists of nothing but an empty page 1
+    pub fn new(options: &CreateOptions) -> Self {
+        DataBaseMetadata {
+            header_str: *b"SQLite format 3\0",
+            page_size: options.page_size,
+            file_format_write_version: FileFormatVersion::LEGACY,
+            file_format_read_version: FileFormatVersion::LEGACY,
+            bytes_unused_reserved_space_at_page_end: options.reserved_bytes_per_page,
+            // the payload fractions are fixed by the file format
+            max_embedded_payload_fraction: 64,
+            min_embedded_payload_fraction: 32,
+            leaf_payload_fraction: 32,
+            file_change_counter: 1,
+            db_size_in_pages: 1,
+            first_freelist_trunk_page_num: 0,
+            total_freelist_pages: 0,
+            schema_cookie: 0,
+            schema_format_number: SchemaFormatNumber::Fmt4,
+            default_page_cache_size: 0,
+            page_num_largest_root_btee_in_vacccum: 0,
+            database_text_encoding: DatabaseTextEncoding::Utf8,
+            user_version: options.user_version,
+            incremental_vacuum_mode: IsIncrementalVacuumMode::False,
+            application_id: options.application_id,
+            expansion_reserved: [0; 20],
+            version_valid_for: 1,
+            sqlite_vesion_number: SQLITE_VERSION_NUMBER,
+        }
+    }
+
+    // the inverse of from_bytes, lays the header out exactly as it is stored in the first 100 bytes of the file
+    pub fn to_bytes(&self) -> [u8; 100] {
+        let mut buffer = [0u8; 100];
+        buffer[0..16].copy_from_slice(&self.header_str);
+        buffer[16..18].copy_from_slice(&self.page_size.to_be_bytes());
+        buffer[18] = self.file_format_write_version.to_u8();
+        buffer[19] = self.file_format_read_version.to_u8();
+        buffer[20] = self.bytes_unused_reserved_space_at_page_end;
+        buffer[21] = self.max_embedded_payload_fraction;
+        buffer[22] = self.min_embedded_payload_fraction;
+        buffer[23] = self.leaf_payload_fraction;
+        let fields = [
+            (24, self.file_change_counter),
+            (28, self.db_size_in_pages),
+            (32, self.first_freelist_trunk_page_num),
+            (36, self.total_freelist_pages),
+            (40, self.schema_cookie),
+            (44, self.schema_format_number.to_u32()),
+            (48, self.default_page_cache_size),
+            (52, self.page_num_largest_root_btee_in_vacccum),
+            (56, self.database_text_encoding.to_u32()),
+            (60, self.user_version),
+            (64, self.incremental_vacuum_mode.to_u32()),
+            (68, self.application_id),
+        ];
+        for (offset, value) in fields {
+            buffer[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+        }
+        buffer[72..92].copy_from_slice(&self.expansion_reserved);
+        buffer[92..96].copy_from_slice(&self.version_valid_for.to_be_bytes());
+        buffer[96..100].copy_from_slice(&self.sqlite_vesion_number.to_be_bytes());
+        buffer
+    }
+
+    // overwrites the header at the start of the file, the rest of page 1 is left alone
+    pub fn write_to<W: Write + Seek>(&self, writer: &mut W) -> Result<()> {
+        writer.seek(SeekFrom::Start(0))?;
+        writer.write_all(&self.to_bytes())?;
+        Ok(())
+    }
+
+    /*
+     * Has to be called for every transaction that changes the file before the header is written out. SQLite caches
+     * pages across connections for as long as the change counter stays the same, and only trusts the in-header
+     * database size when the version-valid-for number matches the change counter, so all three move together.
+     */
+    pub fn record_change(&mut self, db_size_in_pages: u32) {
+        self.file_change_counter = self.file_change_counter.wrapping_add(1);
+        self.db_size_in_pages = db_size_in_pages;
+        self.version_valid_for = self.file_change_counter;
+        self.sqlite_vesion_number = SQLITE_VERSION_NUMBER;
+    }
+
+    // every change to sqlite_schema has to bump the cookie so other connections re-read their cached schema
+    pub fn bump_schema_cookie(&mut self) {
+        self.schema_cookie = self.schema_cookie.wrapping_add(1);
+    }
+}
+
+fn unsupported_header_value(
+    field: &str,
+    offset: usize,
+    value: impl std::fmt::Display,
+) -> StorageError {
+    StorageError::Corrupt {
+        page: 1,
+        offset,
+        reason: format!("unsupported {field} {value}"),
+    }
+}
+
+/*
+ * The settings a new database file is created with, they are fixed for the lifetime of the file except for the
+ * user version and application id. The default matches what a fresh sqlite3 database looks like.
+ */
+#[derive(Debug, Clone)]
+pub struct CreateOptions {
+    // power of two between 512 and 32768
+    pub page_size: u16,
+    pub reserved_bytes_per_page: u8,
+    pub user_version: u32,
+    pub application_id: u32,
+}
+
+impl Default for CreateOptions {
+    fn default() -> Self {
+        CreateOptions {
+            page_size: 4096,
+            reserved_bytes_per_page: 0,
+            user_version: 0,
+            application_id: 0,
+        }
+    }
+}
+
+impl CreateOptions {
+    fn validate(&self) -> Result<()> {
+        if !self.page_size.is_power_of_two() || self.page_size < 512 {
+            return Err(StorageError::InvalidArgument(format!(
+                "page size {} is not a power of two between 512 and 32768",
+                self.page_size
+            )));
+        }
+        // sqlite needs at least 480 usable bytes per page to fit 4 cells on an interior page
+        if (self.page_size as usize) - (self.reserved_bytes_per_page as usize) < 480 {
+            return Err(StorageError::InvalidArgument(format!(
+                "{} reserved bytes leave less than 480 usable bytes on a {} byte page",
+                self.reserved_bytes_per_page, self.page_size
+            )));
+        }
+        Ok(())
+    }
 }
 
 // While we may call this a database struct this is actually just holding metadata shit
 // most of the actual stuff is happening in our btree
 pub struct Database {
     pub db_file: String,
-    pub metadata: DataBaseMetadata,
+    // every btree of the database reads and writes its pages through this one pager, which also owns the header
+    pager: Rc<RefCell<Pager>>,
     //  sqlite_schema table contains the root page number for every other table and index in the database file.
     schema_table_btree: Btree,
 }
@@ -241,130 +487,767 @@ pub struct TableInfo {
 }
 
 impl Database {
-    pub fn from_file(db_file_name: &String) -> Result<Self> {
-        let metadata = DataBaseMetadata::read_from_file(db_file_name)?;
+    pub fn from_file(db_file_name: &str) -> Result<Self> {
+        let pager = Pager::open(db_file_name)?;
+        let encoding = &pager.metadata().database_text_encoding;
+        if !matches!(encoding, DatabaseTextEncoding::Utf8) {
+            return Err(StorageError::UnsupportedFormat(format!(
+                "{:?} text encoding, only UTF-8 databases are supported",
+                encoding
+            )));
+        }
 
-        let schema_table_btree = Btree::read_schema_table(
-            db_file_name,
-            metadata.page_size.try_into()?,
-            metadata.bytes_unused_reserved_space_at_page_end,
-        )?;
+        let pager = Rc::new(RefCell::new(pager));
+        let schema_table_btree = Btree::read_schema_table(pager.clone())?;
 
         Ok(Database {
-            metadata,
-            db_file: db_file_name.clone(),
+            db_file: db_file_name.to_string(),
+            pager,
             schema_table_btree,
         })
     }
 
+    // the database header as of the last change made through this database
+    pub fn metadata(&self) -> DataBaseMetadata {
+        self.pager.borrow().metadata().clone()
+    }
+
+    /*
+     * Creates a new database file holding nothing but page 1, the database header followed by the empty leaf page of
+     * the sqlite_schema table. Refuses to touch a file that already exists.
+     */
+    pub fn create(db_file_name: &str, options: CreateOptions) -> Result<Self> {
+        options.validate()?;
+
+        let schema_page = BtreePage::new_empty(
+            1,
+            PageType::LeafTable,
+            options.page_size as usize,
+            100,
+            options.reserved_bytes_per_page,
+        )?;
+        let mut page = schema_page.as_bytes().to_vec();
+        page[0..100].copy_from_slice(&DataBaseMetadata::new(&options).to_bytes());
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(db_file_name)?;
+        file.write_all(&page)?;
+        file.sync_all()?;
+
+        Database::from_file(db_file_name)
+    }
+
+    /*
+     * Create(TableName, Schema): creates an empty table, along with the indices SQLite keeps for its UNIQUE and
+     * PRIMARY KEY constraints. Every btree gets its own root page and a row in sqlite_schema, the table row holding
+     * the CREATE TABLE statement the schema is read back from. With IF NOT EXISTS an existing table is handed back
+     * as it is instead of failing.
+     */
+    pub fn create_table(&self, schema: &CreateTableStmt) -> Result<Table> {
+        let table_name = &schema.table_name;
+        if schema.temporary {
+            return Err(StorageError::InvalidOperation(format!(
+                "{table_name} is a temporary table, which does not live in the database file"
+            )));
+        }
+        check_schema_name(schema.schema_name.as_deref())?;
+        check_object_name(table_name)?;
+
+        if let Some(existing) = self.find_object(table_name)? {
+            if schema.if_not_exists && existing.obj_type == "table" {
+                return self.table(&existing.name);
+            }
+            return Err(StorageError::InvalidOperation(format!(
+                "{} {} already exists",
+                existing.obj_type, existing.name
+            )));
+        }
+
+        for (idx, column) in schema.columns.iter().enumerate() {
+            if schema.columns[..idx]
+                .iter()
+                .any(|other| other.name.eq_ignore_ascii_case(&column.name))
+            {
+                return Err(StorageError::InvalidArgument(format!(
+                    "duplicate column name {} in {table_name}",
+                    column.name
+                )));
+            }
+        }
+        let primary_keys = schema
+            .columns
+            .iter()
+            .flat_map(|column| &column.constraints)
+            .filter(|constraint| matches!(constraint, ColumnConstraint::PrimaryKey { .. }))
+            .count()
+            + schema
+                .constraints
+                .iter()
+                .filter(|constraint| matches!(constraint, TableConstraint::PrimaryKey(..)))
+                .count();
+        if primary_keys > 1 {
+            return Err(StorageError::InvalidArgument(format!(
+                "{table_name} has more than one primary key"
+            )));
+        }
+        if schema.without_rowid && primary_keys == 0 {
+            return Err(StorageError::InvalidArgument(format!(
+                "WITHOUT ROWID table {table_name} has no primary key"
+            )));
+        }
+        let autoincrement = schema.columns.iter().any(|column| {
+            column.constraints.iter().any(|constraint| {
+                matches!(
+                    constraint,
+                    ColumnConstraint::PrimaryKey {
+                        autoincrement: true,
+                        ..
+                    }
+                )
+            })
+        });
+        if autoincrement {
+            return Err(StorageError::InvalidOperation(format!(
+                "{table_name} uses AUTOINCREMENT, which needs sqlite_sequence and is not supported yet"
+            )));
+        }
+
+        // a WITHOUT ROWID table is stored in an index btree keyed on its primary key
+        let table_page_type = if schema.without_rowid {
+            PageType::LeafIndex
+        } else {
+            PageType::LeafTable
+        };
+        let root_page_num = self.create_btree(table_page_type)?;
+        self.insert_schema_row(
+            "table",
+            table_name,
+            table_name,
+            root_page_num,
+            Some(schema.to_sql()),
+        )?;
+        for n in 1..=schema.autoindex_columns().len() {
+            let root_page_num = self.create_btree(PageType::LeafIndex)?;
+            let index_name = format!("sqlite_autoindex_{table_name}_{n}");
+            self.insert_schema_row("index", &index_name, table_name, root_page_num, None)?;
+        }
+
+        let mut pager = self.pager.borrow_mut();
+        pager.bump_schema_cookie();
+        pager.commit()?;
+        drop(pager);
+
+        self.table(table_name)
+    }
+
+    /*
+     * CREATE [UNIQUE] INDEX: builds an index over every row the table already has. The entries are sorted first and the
+     * btree is then built bottom-up from them, which is what makes indexing a large table practical, inserting the
+     * entries one by one would descend the tree and rewrite pages for every row. The index only shows up in
+     * sqlite_schema once it is complete, a UNIQUE index over rows sharing a key is never registered at all.
+     */
+    pub fn create_index(&self, stmt: &CreateIndexStmt) -> Result<Btree> {
+        check_schema_name(stmt.schema_name.as_deref())?;
+        check_object_name(&stmt.index_name)?;
+        if let Some(existing) = self.find_object(&stmt.index_name)? {
+            if stmt.if_not_exists && existing.obj_type == "index" {
+                return self.open_btree(&existing);
+            }
+            return Err(StorageError::InvalidOperation(format!(
+                "{} {} already exists",
+                existing.obj_type, existing.name
+            )));
+        }
+
+        let table_info = self
+            .find_object(&stmt.table_name)?
+            .filter(|info| info.obj_type == "table")
+            .ok_or_else(|| StorageError::NotFound(format!("Table {}", stmt.table_name)))?;
+        let table = self.table(&table_info.name)?;
+        let entries = table.sorted_index_entries(stmt)?;
+
+        let root_page_num = self.create_btree(PageType::LeafIndex)?;
+        let index = Btree::open(self.pager.clone(), root_page_num)?;
+        if let Err(err) = index.build_index(entries) {
+            let mut pager = self.pager.borrow_mut();
+            pager.free_page(root_page_num)?;
+            pager.commit()?;
+            return Err(err);
+        }
+        let definition = CreateIndexStmt {
+            table_name: table_info.name.clone(),
+            ..stmt.clone()
+        };
+        self.insert_schema_row(
+            "index",
+            &stmt.index_name,
+            &table_info.name,
+            root_page_num,
+            Some(definition.to_sql()),
+        )?;
+
+        let mut pager = self.pager.borrow_mut();
+        pager.bump_schema_cookie();
+        pager.commit()?;
+        Ok(index)
+    }
+
+    /*
+     * Delete(TableName): drops a table along with its indices and triggers. Their rows leave sqlite_schema first and
+     * only then are their pages handed to the freelist, so a drop cut short leaks pages instead of leaving sqlite_schema
+     * pointing at pages that may be reused.
+     */
+    pub fn drop_table(&self, table_name: &str) -> Result<()> {
+        check_object_name(table_name)?;
+        let table = self
+            .find_object(table_name)?
+            .filter(|info| info.obj_type == "table")
+            .ok_or_else(|| StorageError::NotFound(format!("Table {table_name}")))?;
+
+        let dropped = self.delete_schema_rows(|info| {
+            info.name == table.name || info.table_name.eq_ignore_ascii_case(&table.name)
+        })?;
+        self.free_btrees(&dropped)
+    }
+
+    // drops an index created with CREATE INDEX, the indices of UNIQUE and PRIMARY KEY constraints go with their table
+    pub fn drop_index(&self, index_name: &str) -> Result<()> {
+        let index = self
+            .find_object(index_name)?
+            .filter(|info| info.obj_type == "index")
+            .ok_or_else(|| StorageError::NotFound(format!("Index {index_name}")))?;
+        if index.sql.is_empty() {
+            return Err(StorageError::InvalidOperation(format!(
+                "{} belongs to a UNIQUE or PRIMARY KEY constraint of {} and cannot be dropped",
+                index.name, index.table_name
+            )));
+        }
+
+        let dropped = self.delete_schema_rows(|info| info.name == index.name)?;
+        self.free_btrees(&dropped)
+    }
+
     pub fn get_master_table(&self) -> Result<Vec<TableInfo>> {
+        Ok(self
+            .schema_rows()?
+            .into_iter()
+            .map(|(_, info)| info)
+            .collect())
+    }
+
+    pub fn table_names(&self) -> Result<Vec<String>> {
+        Ok(self
+            .get_master_table()?
+            .into_iter()
+            .filter(|x| x.obj_type == "table")
+            .map(|x| x.name)
+            .collect())
+    }
+
+    // the table with its schema and indices, for reading rows by column name
+    pub fn table(&self, table_name: &str) -> Result<Table> {
+        let info = self.get_obj_info(&format!("Table {table_name}"), |x: &TableInfo| {
+            x.obj_type == "table" && x.name.eq_ignore_ascii_case(table_name)
+        })?;
+        let schema = parse_table_schema(&info)?;
+        let btree = self.open_btree(&info)?;
+        let indices = self.get_indices_for_table(&info.name)?;
+
+        Ok(Table::new(info, schema, btree, indices))
+    }
+
+    // the raw table btree, records are handed back as stored without a schema applied
+    pub fn get_table(&self, table_name: &str) -> Result<Btree> {
+        let info = self.get_obj_info(&format!("Table {table_name}"), |x: &TableInfo| {
+            x.obj_type == "table" && x.table_name.eq_ignore_ascii_case(table_name)
+        })?;
+        self.open_btree(&info)
+    }
+
+    pub fn get_index(&self, index_name: &str) -> Result<Btree> {
+        let info = self.get_obj_info(&format!("Index {index_name}"), |x: &TableInfo| {
+            x.obj_type == "index" && x.name.eq_ignore_ascii_case(index_name)
+        })?;
+        self.open_btree(&info)
+    }
+
+    pub fn get_indices_for_table(&self, table_name: &str) -> Result<Vec<(TableInfo, Btree)>> {
+        let mut results = Vec::new();
+        let records = self.get_master_table()?;
+        for record in records {
+            if record.obj_type == "index" && record.table_name.eq_ignore_ascii_case(table_name) {
+                let index = self.open_btree(&record)?;
+                results.push((record, index));
+            }
+        }
+
+        Ok(results)
+    }
+
+    pub fn get_table_columns(&self, table_name: &str) -> Result<Vec<SqlColumn>> {
+        let table_finder =
+            |x: &TableInfo| x.obj_type == "table" && x.table_name.eq_ignore_ascii_case(table_name);
+        let table_info = self.get_obj_info(&format!("Table {table_name}"), table_finder)?;
+
+        // the stored create statement is the only place sqlite keeps the schema of a table
+        find_schema_from_create_stmt(&table_info.sql)
+            .map_err(|err| StorageError::SchemaError(format!("{}: {err}", table_info.name)))
+    }
+
+    pub fn get_table_schema(&self, table_name: &str) -> Result<CreateTableStmt> {
+        let table_finder =
+            |x: &TableInfo| x.obj_type == "table" && x.table_name.eq_ignore_ascii_case(table_name);
+        let table_info = self.get_obj_info(&format!("Table {table_name}"), table_finder)?;
+
+        parse_table_schema(&table_info)
+    }
+
+    // every row of sqlite_schema along with its rowid, in rowid order
+    fn schema_rows(&self) -> Result<Vec<(i64, TableInfo)>> {
         let mut results = Vec::new();
-        let mut records = self.schema_table_btree.get_rows(true)?;
-        // now since we know the schema of the schema table we can map the record to TableInfo
-        for record in &mut records {
+        let rows = self
+            .schema_table_btree
+            .scan_rowid_range(Bound::Unbounded, Bound::Unbounded)?;
+        for row in rows {
+            let (rowid, mut record) = row?;
             let record_data = record.read_record()?;
             if record_data.len() != 5 {
-                bail!("Invalid record");
+                return Err(invalid_schema_row("record"));
             }
 
             let obj_type = match &record_data[0] {
                 SerialData::Text(txt) => txt.clone(),
-                _ => bail!("Invalid obj_type"),
+                _ => return Err(invalid_schema_row("obj_type")),
             };
 
             let name = match &record_data[1] {
                 SerialData::Text(txt) => txt.clone(),
-                _ => bail!("Invalid name"),
+                _ => return Err(invalid_schema_row("name")),
             };
 
             let table_name = match &record_data[2] {
                 SerialData::Text(txt) => txt.clone(),
-                _ => bail!("Invalid table_name"),
+                _ => return Err(invalid_schema_row("table_name")),
             };
 
-            let root_page_num = match &record_data[3] {
-                SerialData::I64(num) => *num,
-                SerialData::I8(num) => i64::from(*num),
-                SerialData::I16(num) => i64::from(*num),
-                SerialData::I24(num) => i64::from(*num),
-                SerialData::I48(num) => i64::from(*num),
-                _ => bail!("Invalid root_page_num"),
+            let root_page_num = match record_data[3].as_integer() {
+                Some(num) => num,
+                None => return Err(invalid_schema_row("root_page_num")),
             };
 
             let sql = match &record_data[4] {
                 SerialData::Text(txt) => txt.clone(),
-                _ => bail!("Invalid sql"),
+                // indices sqlite creates for UNIQUE and PRIMARY KEY constraints have no sql
+                SerialData::Null => String::new(),
+                _ => return Err(invalid_schema_row("sql")),
             };
 
-            results.push(TableInfo {
-                obj_type,
-                name,
-                table_name,
-                root_page_num,
-                sql,
-            });
+            results.push((
+                rowid,
+                TableInfo {
+                    obj_type,
+                    name,
+                    table_name,
+                    root_page_num,
+                    sql,
+                },
+            ));
         }
 
         Ok(results)
     }
 
-    pub fn get_table(&self, table_name: &str) -> Result<Btree> {
-        self.btree_from_info(|x: &TableInfo| x.obj_type == "table" && x.table_name == table_name)
+    // tables and indices share one namespace, which like every SQL name is case insensitive
+    fn find_object(&self, name: &str) -> Result<Option<TableInfo>> {
+        Ok(self.get_master_table()?.into_iter().find(|x| {
+            matches!(x.obj_type.as_str(), "table" | "index") && x.name.eq_ignore_ascii_case(name)
+        }))
     }
 
-    pub fn get_index(&self, index_name: &str) -> Result<Btree> {
-        self.btree_from_info(|x: &TableInfo| x.obj_type == "index" && x.name == index_name)
+    /*
+     * Removes the sqlite_schema rows of the objects matching the predicate and bumps the schema cookie, returning the
+     * rows removed. The rows are found first and deleted afterwards, deleting rebalances the btree being read.
+     */
+    fn delete_schema_rows<F>(&self, predicate: F) -> Result<Vec<TableInfo>>
+    where
+        F: Fn(&TableInfo) -> bool,
+    {
+        let mut rowids = Vec::new();
+        let mut deleted = Vec::new();
+        for (rowid, info) in self.schema_rows()? {
+            if predicate(&info) {
+                rowids.push(rowid);
+                deleted.push(info);
+            }
+        }
+
+        for rowid in rowids {
+            self.schema_table_btree.delete(rowid)?;
+        }
+        let mut pager = self.pager.borrow_mut();
+        pager.bump_schema_cookie();
+        pager.commit()?;
+        Ok(deleted)
     }
 
-    pub fn get_indices_for_table(&self, table_name: &str) -> Result<Vec<Btree>> {
-        let mut results = Vec::new();
-        let records = self.get_master_table()?;
-        for record in records {
-            if record.obj_type == "index" && record.table_name == table_name {
-                results.push(Btree::read_table(
-                    &self.db_file,
-                    self.metadata.page_size.try_into()?,
-                    ((record.root_page_num - 1) * self.metadata.page_size as i64).try_into()?,
-                    self.metadata.bytes_unused_reserved_space_at_page_end,
-                )?);
+    // hands every page of the btrees of tables and indices to the freelist, views and triggers have none
+    fn free_btrees(&self, objects: &[TableInfo]) -> Result<()> {
+        for info in objects {
+            if matches!(info.obj_type.as_str(), "table" | "index") && info.root_page_num != 0 {
+                self.open_btree(info)?.free_all_pages()?;
             }
         }
+        Ok(())
+    }
 
-        Ok(results)
+    // the root page of a new empty btree
+    fn create_btree(&self, page_type: PageType) -> Result<u32> {
+        let mut pager = self.pager.borrow_mut();
+        let page_num = pager.allocate_page()?;
+        let root = BtreePage::new_empty(
+            page_num,
+            page_type,
+            pager.page_size(),
+            0,
+            pager.reserved_bytes_per_page(),
+        )?;
+        pager.write_page(&root)?;
+        Ok(page_num)
     }
 
-    pub fn get_table_columns(&self, table_name: &str) -> Result<Vec<(String, SerialType)>> {
-        // Parse the Create SQL message to do this?
-        let table_finder = |x: &TableInfo| x.obj_type == "table" && x.table_name == table_name;
-        let table_info = self.get_obj_info(table_finder)?;
+    // the inverse of get_master_table(), sql is None for the indices SQLite creates for constraints
+    fn insert_schema_row(
+        &self,
+        obj_type: &str,
+        name: &str,
+        table_name: &str,
+        root_page_num: u32,
+        sql: Option<String>,
+    ) -> Result<()> {
+        let rowid = self.schema_table_btree.max_rowid()?.unwrap_or(0) + 1;
+        let record = vec![
+            SerialData::Text(obj_type.to_string()),
+            SerialData::Text(name.to_string()),
+            SerialData::Text(table_name.to_string()),
+            SerialData::I64(root_page_num as i64),
+            sql.map_or(SerialData::Null, SerialData::Text),
+        ];
+        self.schema_table_btree.insert(rowid, &record)
+    }
 
-        // now use the stored create statement to parse shit
-        // let schema = find_schema_from_create_stmt(table_info.sql)?;
+    fn open_btree(&self, info: &TableInfo) -> Result<Btree> {
+        if info.root_page_num < 1 || info.root_page_num > u32::MAX as i64 {
+            return Err(StorageError::SchemaError(format!(
+                "{} has an invalid root page {}",
+                info.name, info.root_page_num
+            )));
+        }
 
-        todo!()
+        Btree::open(self.pager.clone(), info.root_page_num as u32)
     }
 
-    fn btree_from_info<F>(&self, predicate: F) -> Result<Btree>
+    // description names what is being looked for in the error when nothing matches
+    fn get_obj_info<F>(&self, description: &str, predicate: F) -> Result<TableInfo>
     where
         F: Fn(&TableInfo) -> bool,
     {
-        let info = self.get_obj_info(predicate)?;
+        match self.get_master_table()?.iter().find(|x| predicate(x)) {
+            Some(t) => Ok(t.clone()),
+            None => Err(StorageError::NotFound(description.to_string())),
+        }
+    }
+}
 
-        Btree::read_table(
-            &self.db_file,
-            self.metadata.page_size.try_into()?,
-            ((info.root_page_num - 1) * self.metadata.page_size as i64).try_into()?,
-            self.metadata.bytes_unused_reserved_space_at_page_end,
-        )
+// everything lives in the main database, the only schema a database file has
+fn check_schema_name(schema_name: Option<&str>) -> Result<()> {
+    match schema_name {
+        Some(schema_name) if !schema_name.eq_ignore_ascii_case("main") => {
+            Err(StorageError::InvalidArgument(format!(
+                "unknown database {schema_name}, only main can be written"
+            )))
+        }
+        _ => Ok(()),
     }
+}
 
-    fn get_obj_info<F>(&self, predicate: F) -> Result<TableInfo>
-    where
-        F: Fn(&TableInfo) -> bool,
+// names starting with sqlite_ belong to the objects SQLite creates itself
+fn check_object_name(name: &str) -> Result<()> {
+    if name
+        .get(..7)
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case("sqlite_"))
     {
-        match self.get_master_table()?.iter().find(|x| predicate(x)) {
-            Some(t) => Ok(t.clone()),
-            None => bail!("Obj not found in master table"),
+        return Err(StorageError::InvalidArgument(format!(
+            "object name {name} is reserved for internal use"
+        )));
+    }
+    Ok(())
+}
+
+fn invalid_schema_row(field: &str) -> StorageError {
+    StorageError::SchemaError(format!("sqlite_schema row has an invalid {field}"))
+}
+
+// the stored create statement is the only place sqlite keeps the schema of a table
+fn parse_table_schema(info: &TableInfo) -> Result<CreateTableStmt> {
+    parse_create_table_stmt(&info.sql)
+        .map_err(|err| StorageError::SchemaError(format!("{}: {err}", info.name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_writes_an_empty_database() {
+        let path = std::env::temp_dir().join(format!("create-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        let options = CreateOptions {
+            page_size: 1024,
+            reserved_bytes_per_page: 8,
+            user_version: 7,
+            application_id: 0x1234,
+        };
+        let database = Database::create(path, options.clone()).unwrap();
+        assert_eq!(database.metadata().page_size, 1024);
+        assert_eq!(
+            database.metadata().bytes_unused_reserved_space_at_page_end,
+            8
+        );
+        assert_eq!(database.metadata().user_version, 7);
+        assert_eq!(database.metadata().application_id, 0x1234);
+        assert_eq!(database.metadata().db_size_in_pages, 1);
+        assert!(database.table_names().unwrap().is_empty());
+        assert_eq!(std::fs::metadata(path).unwrap().len(), 1024);
+
+        // an existing file is never overwritten
+        assert!(matches!(
+            Database::create(path, options),
+            Err(StorageError::Io(_))
+        ));
+        std::fs::remove_file(path).unwrap();
+
+        let too_small = CreateOptions {
+            page_size: 512,
+            reserved_bytes_per_page: 40,
+            ..CreateOptions::default()
+        };
+        assert!(matches!(
+            Database::create(path, too_small),
+            Err(StorageError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_create_table_registers_it_in_sqlite_schema() {
+        let path = std::env::temp_dir().join(format!("create-table-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+        let database = Database::create(path, CreateOptions::default()).unwrap();
+
+        let schema = CreateTableStmt::parse(
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT UNIQUE, name TEXT, UNIQUE (name, email))",
+        )
+        .unwrap();
+        let table = database.create_table(&schema).unwrap();
+        assert_eq!(table.name(), "users");
+        assert_eq!(
+            table.index_names(),
+            vec!["sqlite_autoindex_users_1", "sqlite_autoindex_users_2"]
+        );
+        assert_eq!(database.metadata().schema_cookie, 1);
+        assert_eq!(database.metadata().db_size_in_pages, 4);
+
+        // the table is read back from the statement stored for it, in a fresh connection as well
+        let reopened = Database::from_file(path).unwrap();
+        let master = reopened.get_master_table().unwrap();
+        assert_eq!(master[0].sql, schema.to_sql());
+        assert_eq!(master[0].root_page_num, 2);
+        assert_eq!(master[1].sql, "");
+        // names are looked up whatever their case, the indices of the table included
+        let table = reopened.table("USERS").unwrap();
+        assert_eq!(table.name(), "users");
+        assert_eq!(table.index_names().len(), 2);
+        table
+            .insert(
+                &["email", "name"],
+                vec![SerialData::Text("a@b".to_string()), SerialData::Null],
+            )
+            .unwrap();
+        assert!(matches!(
+            table.insert(&["email"], vec![SerialData::Text("a@b".to_string())]),
+            Err(StorageError::Constraint(_))
+        ));
+
+        assert!(matches!(
+            reopened.create_table(&schema),
+            Err(StorageError::InvalidOperation(_))
+        ));
+        let if_not_exists =
+            CreateTableStmt::parse("CREATE TABLE IF NOT EXISTS USERS (other)").unwrap();
+        assert_eq!(
+            reopened.create_table(&if_not_exists).unwrap().name(),
+            "users"
+        );
+        for invalid in [
+            "CREATE TABLE sqlite_users (a)",
+            "CREATE TABLE twice (a, A)",
+            "CREATE TABLE keys (a PRIMARY KEY, b, PRIMARY KEY (b))",
+            "CREATE TABLE other.t (a)",
+        ] {
+            let schema = CreateTableStmt::parse(invalid).unwrap();
+            assert!(matches!(
+                reopened.create_table(&schema),
+                Err(StorageError::InvalidArgument(_))
+            ));
+        }
+        assert_eq!(reopened.metadata().schema_cookie, 1);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_create_index_over_existing_rows() {
+        let path = std::env::temp_dir().join(format!("create-index-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+        let database = Database::create(path, CreateOptions::default()).unwrap();
+        let schema = CreateTableStmt::parse("CREATE TABLE people (name TEXT, age INT)").unwrap();
+        let table = database.create_table(&schema).unwrap();
+        for n in 0..500 {
+            let values = vec![
+                SerialData::Text(format!("person {}", n % 250)),
+                SerialData::I64(n),
+            ];
+            table.insert(&["name", "age"], values).unwrap();
+        }
+
+        // two rows share every name, so the UNIQUE index is never registered
+        let unique =
+            CreateIndexStmt::parse("CREATE UNIQUE INDEX by_name ON people (name)").unwrap();
+        assert!(matches!(
+            database.create_index(&unique),
+            Err(StorageError::Constraint(_))
+        ));
+        assert_eq!(database.get_master_table().unwrap().len(), 1);
+
+        let stmt = CreateIndexStmt::parse("create index by_name on PEOPLE (name)").unwrap();
+        let index = database.create_index(&stmt).unwrap();
+        assert_eq!(index.iter().count(), 500);
+        let info = database.get_master_table().unwrap().pop().unwrap();
+        assert_eq!(info.table_name, "people");
+        assert_eq!(info.sql, r#"CREATE INDEX "by_name" ON "people" ("name")"#);
+        assert_eq!(database.metadata().schema_cookie, 2);
+
+        // the new index serves lookups and is kept up to date by writes, names match whatever their case
+        assert_eq!(database.get_index("BY_NAME").unwrap().iter().count(), 500);
+        let table = database.table("People").unwrap();
+        assert_eq!(table.name(), "people");
+        assert_eq!(table.index_names(), vec!["by_name"]);
+        let value = SerialData::Text("person 7".to_string());
+        let rows = table.find_by_index("name", &value).unwrap().unwrap();
+        assert_eq!(
+            rows.iter().map(|row| row.rowid()).collect::<Vec<_>>(),
+            vec![8, 258]
+        );
+        table.delete(8).unwrap();
+        assert_eq!(
+            table.find_by_index("name", &value).unwrap().unwrap().len(),
+            1
+        );
+
+        assert!(matches!(
+            database.create_index(&stmt),
+            Err(StorageError::InvalidOperation(_))
+        ));
+        let missing = CreateIndexStmt::parse("CREATE INDEX by_age ON nobody (age)").unwrap();
+        assert!(matches!(
+            database.create_index(&missing),
+            Err(StorageError::NotFound(_))
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_drop_table_frees_every_page() {
+        let path = std::env::temp_dir().join(format!("drop-table-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+        let database = Database::create(path, CreateOptions::default()).unwrap();
+        let schema = CreateTableStmt::parse(
+            "CREATE TABLE notes (id INTEGER PRIMARY KEY, title TEXT UNIQUE, body TEXT)",
+        )
+        .unwrap();
+        let table = database.create_table(&schema).unwrap();
+        // bodies this long spill into overflow chains
+        for n in 0..200 {
+            let values = vec![
+                SerialData::Text(format!("note {n}")),
+                SerialData::Text("x".repeat(n as usize * 50)),
+            ];
+            table.insert(&["title", "body"], values).unwrap();
         }
+        let stmt = CreateIndexStmt::parse("CREATE INDEX by_body ON notes (body)").unwrap();
+        database.create_index(&stmt).unwrap();
+        assert_eq!(database.get_master_table().unwrap().len(), 3);
+
+        // the index of the UNIQUE constraint goes with its table only
+        assert!(matches!(
+            database.drop_index("sqlite_autoindex_notes_1"),
+            Err(StorageError::InvalidOperation(_))
+        ));
+        database.drop_index("BY_BODY").unwrap();
+        assert_eq!(database.get_master_table().unwrap().len(), 2);
+        assert!(database.metadata().total_freelist_pages > 0);
+
+        database.drop_table("notes").unwrap();
+        assert!(database.get_master_table().unwrap().is_empty());
+        let metadata = database.metadata();
+        assert_eq!(metadata.total_freelist_pages, metadata.db_size_in_pages - 1);
+        assert_eq!(metadata.schema_cookie, 4);
+
+        assert!(matches!(
+            database.drop_table("notes"),
+            Err(StorageError::NotFound(_))
+        ));
+        assert!(matches!(
+            database.drop_index("by_body"),
+            Err(StorageError::NotFound(_))
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_metadata_round_trips_through_bytes() {
+        let mut metadata = DataBaseMetadata::new(&CreateOptions::default());
+        metadata.file_format_write_version = FileFormatVersion::WAL;
+        metadata.file_format_read_version = FileFormatVersion::WAL;
+        metadata.first_freelist_trunk_page_num = 9;
+        metadata.total_freelist_pages = 3;
+        metadata.default_page_cache_size = 2000;
+        metadata.incremental_vacuum_mode = IsIncrementalVacuumMode::True;
+        metadata.page_num_largest_root_btee_in_vacccum = 5;
+        metadata.user_version = u32::MAX;
+        metadata.bump_schema_cookie();
+        metadata.record_change(12);
+
+        let bytes = metadata.to_bytes();
+        let decoded = DataBaseMetadata::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded, metadata);
+        assert_eq!(decoded.to_bytes(), bytes);
+
+        // the change counter, in-header size and version-valid-for move together
+        assert_eq!(decoded.file_change_counter, 2);
+        assert_eq!(decoded.version_valid_for, 2);
+        assert_eq!(decoded.db_size_in_pages, 12);
+        assert_eq!(decoded.schema_cookie, 1);
+
+        let mut file = std::io::Cursor::new(vec![0xffu8; 200]);
+        metadata.write_to(&mut file).unwrap();
+        let written = file.into_inner();
+        assert_eq!(written[..100], bytes[..]);
+        assert!(written[100..].iter().all(|byte| *byte == 0xff));
     }
 }
@@ -0,0 +1,80 @@
+use thiserror::Error;
+
+use crate::sql_data_types::{SerialDataError, VarIntError};
+
+/*
+ * The error of every fallible operation in the library, so callers can tell a corrupt file apart from a missing
+ * table or a malformed filter without matching on message strings.
+ *
+ * Corruption is usually noticed while decoding a cell or record, which only sees a slice of bytes and does not know
+ * which page they came from. Those errors start out with page 0 (page numbers start at 1) and an offset into the
+ * slice, and whoever knows where the slice sits attaches the location with at().
+ */
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Database is corrupt, page {page} offset {offset}: {reason}")]
+    Corrupt {
+        page: u32,
+        offset: usize,
+        reason: String,
+    },
+    #[error("{0} not found")]
+    NotFound(String),
+    #[error("Unsupported format: {0}")]
+    UnsupportedFormat(String),
+    #[error("Schema error: {0}")]
+    SchemaError(String),
+    #[error("Parse error: {0}")]
+    Parse(String),
+    #[error("Type mismatch: {0}")]
+    TypeMismatch(String),
+    #[error("Invalid operation: {0}")]
+    InvalidOperation(String),
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error("Constraint failed: {0}")]
+    Constraint(String),
+}
+
+pub type Result<T, E = StorageError> = std::result::Result<T, E>;
+
+impl StorageError {
+    // corruption found in a slice of bytes whose location is not known yet
+    pub(crate) fn corrupt(reason: impl Into<String>) -> Self {
+        StorageError::Corrupt {
+            page: 0,
+            offset: 0,
+            reason: reason.into(),
+        }
+    }
+
+    // places a corruption found in a slice of a page at the page and the offset the slice starts at
+    pub(crate) fn at(self, page: u32, offset: usize) -> Self {
+        match self {
+            StorageError::Corrupt {
+                page: 0,
+                offset: slice_offset,
+                reason,
+            } => StorageError::Corrupt {
+                page,
+                offset: offset + slice_offset,
+                reason,
+            },
+            err => err,
+        }
+    }
+}
+
+impl From<VarIntError> for StorageError {
+    fn from(err: VarIntError) -> Self {
+        StorageError::corrupt(err.to_string())
+    }
+}
+
+impl From<SerialDataError> for StorageError {
+    fn from(err: SerialDataError) -> Self {
+        StorageError::corrupt(err.to_string())
+    }
+}
@@ -0,0 +1,41 @@
+/*
+ * A storage engine built on persistent B-trees in the SQLite database file format, so the files it works with can
+ * be inspected with the sqlite3 shell and every other tool built around SQLite.
+ *
+ * The public API is the layer on top of the btrees:
+ * - Database opens a database file and lists what is in it
+ * - Table reads rows of a table, by scanning, by rowid or through an index
+ * - Row hands back the columns of a row by name, converted to rust types through FromSqlValue
+ * - Predicate filters rows using SQLite's comparison semantics
+ * - CreateTableStmt and friends describe the schema of a table
+ * Btree and ReadableRecord are exposed for exploring the raw btrees without a schema applied.
+ */
+mod btree;
+mod cell;
+mod database;
+mod error;
+mod page;
+mod pager;
+mod predicate;
+mod record;
+mod row;
+mod sorter;
+mod sql_data_types;
+mod sql_parser;
+mod table;
+
+pub use btree::{Btree, BtreeIterator, RowidRangeIterator};
+pub use database::{
+    CreateOptions, DataBaseMetadata, Database, DatabaseTextEncoding, FileFormatVersion,
+    IsIncrementalVacuumMode, SchemaFormatNumber, TableInfo,
+};
+pub use error::{Result, StorageError};
+pub use predicate::{ComparisonOp, Predicate};
+pub use record::ReadableRecord;
+pub use row::{FromSqlValue, Row, Rows};
+pub use sql_data_types::{Affinity, SerialData};
+pub use sql_parser::{
+    ColumnConstraint, ConflictResolution, CreateIndexStmt, CreateTableStmt, Deferral,
+    ForeignKeyAction, ForeignKeyClause, IndexedColumn, SortOrder, SqlColumn, TableConstraint,
+};
+pub use table::Table;
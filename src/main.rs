@@ -1,63 +1,9 @@
-mod btree;
-mod cell;
-mod database;
-mod page;
-mod record;
-mod sql_data_types;
-mod sql_parser;
-
 use anyhow::{bail, Result};
-use database::Database;
 use env_logger::Env;
-
-enum SupportedOperators {
-    Equal,
-    NotEqual,
-    LessThan,
-    GreaterThan,
-}
-
-struct ParsedFilterArgs {
-    column_name: String,
-    operator: SupportedOperators,
-    value: String,
-}
-
-impl ParsedFilterArgs {
-    fn from_string(raw_filter: &str) -> Result<ParsedFilterArgs> {
-        if raw_filter.contains("=") {
-            let parts = raw_filter.split("=").collect::<Vec<_>>();
-            Ok(ParsedFilterArgs {
-                column_name: parts[0].to_string(),
-                operator: SupportedOperators::Equal,
-                value: parts[1].to_string(),
-            })
-        } else if raw_filter.contains("!=") {
-            let parts = raw_filter.split("!=").collect::<Vec<_>>();
-            Ok(ParsedFilterArgs {
-                column_name: parts[0].to_string(),
-                operator: SupportedOperators::NotEqual,
-                value: parts[1].to_string(),
-            })
-        } else if raw_filter.contains("<") {
-            let parts = raw_filter.split("<").collect::<Vec<_>>();
-            Ok(ParsedFilterArgs {
-                column_name: parts[0].to_string(),
-                operator: SupportedOperators::LessThan,
-                value: parts[1].to_string(),
-            })
-        } else if raw_filter.contains(">") {
-            let parts = raw_filter.split(">").collect::<Vec<_>>();
-            Ok(ParsedFilterArgs {
-                column_name: parts[0].to_string(),
-                operator: SupportedOperators::GreaterThan,
-                value: parts[1].to_string(),
-            })
-        } else {
-            bail!("Unsupported Operator in filter: {raw_filter}")
-        }
-    }
-}
+use sqlite_rust::{
+    ComparisonOp, CreateIndexStmt, CreateTableStmt, Database, Predicate, Row, SerialData,
+    StorageError,
+};
 
 // Temporary Driver program so I can test my top level api's for the database without making a separate project using the LIB
 fn main() -> Result<()> {
@@ -65,6 +11,11 @@ fn main() -> Result<()> {
 
     // Parse arguments
     let args = std::env::args().collect::<Vec<_>>();
+    run(&args)
+}
+
+// runs the command given on the command line, args[0] being the program name like std::env::args() hands it over
+fn run(args: &[String]) -> Result<()> {
     match args.len() {
         0 | 1 => bail!("Missing <database path> and <command>"),
         2 => bail!("Missing <command>"),
@@ -76,14 +27,8 @@ fn main() -> Result<()> {
     let command = &args[2];
     match command.as_str() {
         ".tables" => {
-            let tables = database.get_master_table()?;
-
             // string join table names with space in between
-            let table_names: String = tables
-                .iter()
-                .map(|x| x.name.clone())
-                .collect::<Vec<_>>()
-                .join(", ");
+            let table_names = database.table_names()?.join(", ");
 
             println!("{table_names}");
         }
@@ -94,30 +39,28 @@ fn main() -> Result<()> {
             // run the processed query on the VM
             let table = database.get_table(table_name)?;
 
-            let rows = table.get_rows(false)?;
-            let num_rows = rows.len();
-
-            println!("{num_rows} Rows for table {table_name}:");
-
-            for row in rows {
-                let row_data = row.clone().read_record()?;
+            let mut num_rows = 0;
+            for row in table.iter() {
+                let row_data = row?.read_record()?;
                 println!("{:?}", row_data);
+                num_rows += 1;
             }
+
+            println!("{num_rows} Rows for table {table_name}");
         }
         ".index" => {
             // Purely exists for Index exploration not provided by lib api
             let index_name = &args[3];
             let idx_table = database.get_index(index_name)?;
 
-            let rows = idx_table.get_rows(false)?;
-            let num_rows = rows.len();
-
-            println!("{num_rows} Rows for Index {index_name}:");
-
-            for row in rows {
-                let row_data = row.clone().read_record()?;
+            let mut num_rows = 0;
+            for row in idx_table.iter() {
+                let row_data = row?.read_record()?;
                 println!("{:?}", row_data);
+                num_rows += 1;
             }
+
+            println!("{num_rows} Rows for Index {index_name}");
         }
         ".get" => {
             // Get(Table, Fields[], Filters[]))
@@ -126,56 +69,213 @@ fn main() -> Result<()> {
 
             let columns_raw = args[4].as_str(); // "," delimitted column names, and * for all
 
-            // ","" delimitted "" based key value pairs, key and values are separated by =, <,>,!=
-            let filters_raw = if args.len() >= 7 {
-                Some(&args[5])
-            } else {
-                None
+            // a WHERE clause, e.g. "age >= 30 AND name LIKE 'A%'", P.S this is optional
+            let filters_raw = args.get(5).filter(|f| !f.is_empty());
+
+            // an equality on an indexed column, e.g. "name='Alice'", P.S this is optional
+            let index_filter = args.get(6);
+
+            let table = database.table(table_name)?;
+            let schema = table.schema();
+
+            // lets make sure the table has the said columns being used by the projection, filters and index filter
+            let column_idx = |column_name: &str| match schema.column_index(column_name) {
+                Some(idx) => Ok(idx),
+                None => bail!("Table {table_name} has no column {column_name}"),
             };
 
-            // "," delimitted column and "=" P.S this is optional
-            let index_filter = if args.len() >= 6 {
-                Some(&args[6])
-            } else {
-                None
+            let projection = match columns_raw {
+                "*" => (0..schema.columns.len()).collect::<Vec<_>>(),
+                _ => columns_raw
+                    .split(',')
+                    .map(|column_name| column_idx(column_name.trim()))
+                    .collect::<Result<Vec<_>>>()?,
             };
 
-            let table = database.get_table(table_name)?;
+            let filter = filters_raw.map(|f| Predicate::parse(f)).transpose()?;
+            if let Some(filter) = &filter {
+                for column_name in filter.columns() {
+                    column_idx(column_name)?;
+                }
+            }
+
+            let index_filter = match index_filter {
+                Some(f) => match Predicate::parse(f)? {
+                    Predicate::Comparison {
+                        column,
+                        op: ComparisonOp::Eq,
+                        value,
+                    } => {
+                        column_idx(&column)?;
+                        Some((column, value))
+                    }
+                    _ => bail!("Index filter must be an equality on a column: {f}"),
+                },
+                None => None,
+            };
 
-            // see if the where clauses can use any indices
-            let specific_columns = match columns_raw {
-                "*" => None,
-                _ => Some(columns_raw.split(",").collect::<Vec<_>>()),
+            // the index filter narrows the rows down through the index, everything else is a full table scan
+            let rows: Box<dyn Iterator<Item = Result<Row, StorageError>>> = match index_filter {
+                Some((column_name, value)) => match table.find_by_index(&column_name, &value)? {
+                    Some(rows) => Box::new(rows.into_iter().map(Ok)),
+                    None => bail!("No index on {table_name} leads with column {column_name}"),
+                },
+                None => Box::new(table.scan()),
             };
 
-            let filters = filters_raw.map(|f| {
-                f.split(",")
-                    .map(|x| ParsedFilterArgs::from_string(x))
+            let header = projection
+                .iter()
+                .map(|idx| schema.columns[*idx].name.as_str())
+                .collect::<Vec<_>>()
+                .join("|");
+            println!("{header}");
+
+            let mut num_rows = 0;
+            for row in rows {
+                let row = row?;
+                if let Some(filter) = &filter {
+                    if !filter.evaluate(&row)? {
+                        continue;
+                    }
+                }
+
+                let values = projection
+                    .iter()
+                    .map(|idx| row.values()[*idx].to_string())
                     .collect::<Vec<_>>()
-            });
+                    .join("|");
+                println!("{values}");
+                num_rows += 1;
+            }
 
-            let index_filter = index_filter.map(|f| {
-                // check what equality operator is being used in this  filter
-                let parts = f.split("=").collect::<Vec<_>>();
-                // Column, Value Tuple, Operator is implicitly equality
-                (parts[0].to_string(), parts[1].to_string())
-            });
+            println!("{num_rows} Rows for table {table_name}");
+        }
+        ".insert" => {
+            // Insert(Table, Fields, Values)
+            let table_name = &args[3];
+            // "," delimitted column names
+            let columns = args[4].split(',').map(str::trim).collect::<Vec<_>>();
+            // "," delimitted SQL literals, e.g. "1, 'Alice', NULL"
+            let values = SerialData::parse_list(&args[5])?;
 
-            // lets make sure the table has the said columns being used by specific_columns and indices and what not
+            let table = database.table(table_name)?;
+            let rowid = table.insert(&columns, values)?;
 
-            todo!()
+            println!("Inserted row {rowid} into table {table_name}");
         }
         ".set" => {
-            todo!()
+            // Update(Table, Rowid, Fields, Values)
+            let table_name = &args[3];
+            let rowid = args[4].parse::<i64>()?;
+            // "," delimitted column names and SQL literals, as for .insert
+            let columns = args[5].split(',').map(str::trim).collect::<Vec<_>>();
+            let values = SerialData::parse_list(&args[6])?;
+
+            let table = database.table(table_name)?;
+            if !table.update(rowid, &columns, values)? {
+                bail!("Table {table_name} has no row {rowid}");
+            }
+
+            println!("Updated row {rowid} of table {table_name}");
         }
         ".create" => {
-            todo!()
+            // Create(TableName, Schema)
+            let table_name = &args[3];
+            // "," delimitted column definitions and table constraints, e.g. "id INTEGER PRIMARY KEY, name TEXT"
+            let definitions = &args[4];
+            let stmt = format!(
+                "CREATE TABLE \"{}\" ({definitions})",
+                table_name.replace('"', "\"\"")
+            );
+
+            let table = database.create_table(&CreateTableStmt::parse(&stmt)?)?;
+
+            println!("Created table {}", table.name());
+        }
+        ".create_index" => {
+            // CREATE [UNIQUE] INDEX, e.g. "CREATE UNIQUE INDEX users_email ON users (email)"
+            let stmt = CreateIndexStmt::parse(&args[3])?;
+            database.create_index(&stmt)?;
+
+            println!(
+                "Created index {} on table {}",
+                stmt.index_name, stmt.table_name
+            );
         }
         ".delete" => {
-            todo!()
+            // Delete(TableName), its indices go with it
+            let table_name = &args[3];
+            database.drop_table(table_name)?;
+
+            println!("Deleted table {table_name}");
+        }
+        ".delete_index" => {
+            let index_name = &args[3];
+            database.drop_index(index_name)?;
+
+            println!("Deleted index {index_name}");
         }
         _ => bail!("Unknown command: {command}"),
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlite_rust::CreateOptions;
+
+    fn run_command(command: &[&str]) -> Result<()> {
+        let args = std::iter::once("sqlite-rust")
+            .chain(command.iter().copied())
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        run(&args)
+    }
+
+    #[test]
+    fn test_set_updates_a_row_and_its_indices() {
+        let path = std::env::temp_dir().join(format!("set-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+        Database::create(path, CreateOptions::default()).unwrap();
+
+        let definitions = "id INTEGER PRIMARY KEY, name TEXT UNIQUE, age INT";
+        run_command(&[path, ".create", "people", definitions]).unwrap();
+        run_command(&[path, ".insert", "people", "name, age", "'Al', 30"]).unwrap();
+        run_command(&[path, ".insert", "people", "name, age", "'Bo', 40"]).unwrap();
+
+        run_command(&[path, ".set", "people", "1", "age, name", "'31', 'Alice'"]).unwrap();
+        let table = Database::from_file(path).unwrap().table("people").unwrap();
+        let row = table.get(1).unwrap().unwrap();
+        assert_eq!(row.get::<String>("name").unwrap(), "Alice");
+        // the text '31' was stored as an integer, the affinity of the column
+        assert!(matches!(row.values()[2], SerialData::I8(31)));
+        // the entry of the row in the UNIQUE index on name moved along with the name
+        let index = Database::from_file(path)
+            .unwrap()
+            .get_index("sqlite_autoindex_people_1")
+            .unwrap();
+        let names = index
+            .iter()
+            .map(|entry| entry.unwrap().read_record().unwrap()[0].clone())
+            .map(|name| match name {
+                SerialData::Text(name) => name,
+                other => panic!("expected a name, got {other:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["Alice", "Bo"]);
+
+        // a missing row, a taken name and a value list that does not parse leave the rows as they were
+        assert!(run_command(&[path, ".set", "people", "3", "age", "1"]).is_err());
+        assert!(run_command(&[path, ".set", "people", "2", "name", "'Alice'"]).is_err());
+        assert!(run_command(&[path, ".set", "people", "2", "age", "'41"]).is_err());
+        assert!(run_command(&[path, ".set", "people", "two", "age", "41"]).is_err());
+        let table = Database::from_file(path).unwrap().table("people").unwrap();
+        let row = table.get(2).unwrap().unwrap();
+        assert_eq!(row.get::<String>("name").unwrap(), "Bo");
+        assert_eq!(row.get::<i64>("age").unwrap(), 40);
+        std::fs::remove_file(path).unwrap();
+    }
+}
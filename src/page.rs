@@ -1,7 +1,7 @@
-use anyhow::{anyhow, Result};
-use std::convert::TryInto;
+use crate::cell::cell_size;
+use crate::error::{Result, StorageError};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum PageType {
     InteriorIndex,
     InteriorTable,
@@ -19,6 +19,15 @@ impl PageType {
             _ => None,
         }
     }
+
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            PageType::InteriorIndex => 2,
+            PageType::InteriorTable => 5,
+            PageType::LeafIndex => 10,
+            PageType::LeafTable => 13,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -36,18 +45,12 @@ pub struct CommonPageHeader {
 }
 
 impl CommonPageHeader {
-    fn from_buffer(page_buffer: &Vec<u8>, offset: usize) -> Result<Self> {
-        let start_of_first_free_block =
-            u16::from_be_bytes(page_buffer[1 + offset..3 + offset].try_into()?);
-        let num_cells = u16::from_be_bytes(page_buffer[3 + offset..5 + offset].try_into()?);
-        let start_of_cell_content_area =
-            u16::from_be_bytes(page_buffer[5 + offset..7 + offset].try_into()?);
-        let num_fragmented_free_bytes = u8::from_be(page_buffer[7 + offset]);
+    fn from_buffer(page_buffer: &[u8], offset: usize) -> Result<Self> {
         Ok(CommonPageHeader {
-            start_of_first_free_block,
-            num_cells,
-            start_of_cell_content_area,
-            num_fragmented_free_bytes,
+            start_of_first_free_block: read_u16(page_buffer, 1 + offset)?,
+            num_cells: read_u16(page_buffer, 3 + offset)?,
+            start_of_cell_content_area: read_u16(page_buffer, 5 + offset)?,
+            num_fragmented_free_bytes: read_u8(page_buffer, 7 + offset)?,
         })
     }
 }
@@ -65,9 +68,12 @@ pub struct InteriorPageHeader {
 
 #[derive(Debug, Clone)]
 pub struct BtreePage {
+    pub page_num: u32,
     pub page_type: PageType,
     pub page_header: PageHeader,
     raw_byte_buffer: Vec<u8>,
+    // 100 on page 1 where the database header precedes the btree page header, 0 everywhere else
+    header_offset: usize,
     pub reserved_bytes_per_page: u8,
 }
 
@@ -76,39 +82,638 @@ impl BtreePage {
      * Give a buffer and an offset to the header read in the header and return the obj
      */
     pub fn new(
+        page_num: u32,
         page_byte_buffer: Vec<u8>,
         offset: usize,
         reserved_bytes_per_page: u8,
     ) -> Result<Self> {
-        let page_type = PageType::from_u8(page_byte_buffer[0 + offset])
-            .ok_or(anyhow!("invalid page type of btree page"))?;
-
-        let common_header = CommonPageHeader::from_buffer(&page_byte_buffer, offset)?;
-
-        let page_header = match page_type {
-            PageType::InteriorIndex | PageType::InteriorTable => {
-                let right_most_pointer =
-                    u32::from_be_bytes(page_byte_buffer[8 + offset..12 + offset].try_into()?);
-                PageHeader::Interior(InteriorPageHeader {
-                    common_header,
-                    right_most_pointer,
-                })
-            }
-            PageType::LeafIndex | PageType::LeafTable => {
-                PageHeader::Leaf(LeafPageHeader { common_header })
-            }
-        };
+        let (page_type, page_header) = parse_header(page_num, &page_byte_buffer, offset)?;
 
         Ok(Self {
+            page_num,
             page_type,
             page_header,
             raw_byte_buffer: page_byte_buffer,
+            header_offset: offset,
             reserved_bytes_per_page,
         })
     }
 
-    // returns only the byte array for the cell content into th
-    pub fn get_raw_bytes_buffer(&self) -> &Vec<u8> {
+    /*
+     * A page of the given type without any cells. The cell content area starts at the end of the usable space since
+     * nothing has been written into it yet, an interior page starts out with a right most pointer of 0 which the
+     * caller has to fill in before the page is written.
+     */
+    pub fn new_empty(
+        page_num: u32,
+        page_type: PageType,
+        page_size: usize,
+        offset: usize,
+        reserved_bytes_per_page: u8,
+    ) -> Result<Self> {
+        let mut page_byte_buffer = vec![0u8; page_size];
+        let usable_size = page_size - reserved_bytes_per_page as usize;
+        page_byte_buffer[offset] = page_type.to_u8();
+        write_content_start(&mut page_byte_buffer, offset, usable_size);
+        Self::new(page_num, page_byte_buffer, offset, reserved_bytes_per_page)
+    }
+
+    // 100 on page 1, behind the database header, and 0 on every other page
+    pub fn header_offset(&self) -> usize {
+        self.header_offset
+    }
+
+    // the page as it is laid out on disk
+    pub fn as_bytes(&self) -> &[u8] {
         &self.raw_byte_buffer
     }
+
+    pub fn is_leaf(&self) -> bool {
+        matches!(self.page_header, PageHeader::Leaf(_))
+    }
+
+    pub fn num_cells(&self) -> usize {
+        match &self.page_header {
+            PageHeader::Leaf(header) => header.common_header.num_cells as usize,
+            PageHeader::Interior(header) => header.common_header.num_cells as usize,
+        }
+    }
+
+    pub fn right_most_pointer(&self) -> Option<u32> {
+        match &self.page_header {
+            PageHeader::Leaf(_) => None,
+            PageHeader::Interior(header) => Some(header.right_most_pointer),
+        }
+    }
+
+    // the cell pointer array immediately follows the page header
+    fn cell_pointer_array_start(&self) -> usize {
+        self.header_offset + page_header_size(&self.page_type)
+    }
+
+    // returns the offset into the page of the idx'th cell in key order
+    pub fn cell_offset(&self, idx: usize) -> Result<usize> {
+        if idx >= self.num_cells() {
+            return Err(StorageError::InvalidOperation(format!(
+                "cell index {idx} out of bounds for page with {} cells",
+                self.num_cells()
+            )));
+        }
+
+        let pointer_start = self.cell_pointer_array_start() + idx * 2;
+        let cell_offset = read_u16(&self.raw_byte_buffer, pointer_start)
+            .map_err(|err| err.at(self.page_num, 0))? as usize;
+        if cell_offset >= self.raw_byte_buffer.len() {
+            return Err(StorageError::Corrupt {
+                page: self.page_num,
+                offset: pointer_start,
+                reason: format!("cell pointer {cell_offset} points past the end of the page"),
+            });
+        }
+        Ok(cell_offset)
+    }
+
+    // returns the page content starting at the idx'th cell
+    pub fn cell_content(&self, idx: usize) -> Result<&[u8]> {
+        let cell_offset = self.cell_offset(idx)?;
+        Ok(&self.raw_byte_buffer[cell_offset..])
+    }
+
+    // the page can be used up to here, the reserved region follows
+    pub fn usable_size(&self) -> usize {
+        self.raw_byte_buffer.len() - self.reserved_bytes_per_page as usize
+    }
+
+    // the bytes the idx'th cell takes up on the page, local payload and overflow page pointer included
+    pub fn cell_size(&self, idx: usize) -> Result<usize> {
+        let cell_offset = self.cell_offset(idx)?;
+        let cell_size = cell_size(&self.page_type, self.cell_content(idx)?, self.usable_size())
+            .map_err(|err| err.at(self.page_num, cell_offset))?;
+        if cell_offset + cell_size > self.usable_size() {
+            return Err(self.corrupt_at(cell_offset, "cell runs past the end of the page"));
+        }
+        Ok(cell_size)
+    }
+
+    pub fn cell_bytes(&self, idx: usize) -> Result<&[u8]> {
+        let cell_offset = self.cell_offset(idx)?;
+        Ok(&self.raw_byte_buffer[cell_offset..cell_offset + self.cell_size(idx)?])
+    }
+
+    // copies of every cell in key order
+    pub fn cells(&self) -> Result<Vec<Vec<u8>>> {
+        (0..self.num_cells())
+            .map(|idx| self.cell_bytes(idx).map(|cell| cell.to_vec()))
+            .collect()
+    }
+
+    // the room for cells and their pointers on an empty page of page_type
+    pub fn cell_capacity(page_type: &PageType, usable_size: usize, header_offset: usize) -> usize {
+        usable_size - header_offset - page_header_size(page_type)
+    }
+
+    /*
+     * The total free space on the page is the unallocated region plus every freeblock plus the fragmented bytes,
+     * all of it can be handed to a new cell, if need be by defragmenting the page first.
+     */
+    pub fn free_space(&self) -> Result<usize> {
+        let freeblock_space: usize = self.freeblocks()?.iter().map(|(_, size)| size).sum();
+        Ok(self.unallocated_space()
+            + freeblock_space
+            + self.common_header().num_fragmented_free_bytes as usize)
+    }
+
+    /*
+     * Places a cell so it becomes the idx'th cell in key order, returns false when the page has no room for it.
+     * Like SQLite the space comes from the first freeblock big enough to hold the cell, else from the unallocated
+     * region, and when the free space is scattered too thinly for either the page is defragmented first.
+     */
+    pub fn insert_cell(&mut self, idx: usize, cell: &[u8]) -> Result<bool> {
+        let num_cells = self.num_cells();
+        if idx > num_cells {
+            return Err(StorageError::InvalidOperation(format!(
+                "cannot insert cell {idx} on a page with {num_cells} cells"
+            )));
+        }
+        if cell.len() + 2 > self.free_space()? {
+            return Ok(false);
+        }
+
+        // the new cell pointer needs 2 bytes of the unallocated region
+        if self.unallocated_space() < 2 {
+            self.defragment()?;
+        }
+
+        let cell_offset = match self.allocate_from_freeblocks(cell.len())? {
+            Some(cell_offset) => cell_offset,
+            None => {
+                if self.unallocated_space() < cell.len() + 2 {
+                    self.defragment()?;
+                }
+                let cell_offset = self.cell_content_area_start() - cell.len();
+                write_content_start(&mut self.raw_byte_buffer, self.header_offset, cell_offset);
+                cell_offset
+            }
+        };
+        self.raw_byte_buffer[cell_offset..cell_offset + cell.len()].copy_from_slice(cell);
+
+        let pointer = self.cell_pointer_array_start() + idx * 2;
+        let pointer_array_end = self.cell_pointer_array_start() + num_cells * 2;
+        self.raw_byte_buffer
+            .copy_within(pointer..pointer_array_end, pointer + 2);
+        write_u16(&mut self.raw_byte_buffer, pointer, cell_offset as u16);
+        write_u16(
+            &mut self.raw_byte_buffer,
+            self.header_offset + 3,
+            (num_cells + 1) as u16,
+        );
+
+        self.reload_header()?;
+        Ok(true)
+    }
+
+    /*
+     * Removes the idx'th cell. Its bytes join the freeblock chain the way SQLite frees them: a freeblock touching the
+     * freed bytes, or separated from them by fewer than 4 fragmented bytes, is merged with them, and a freeblock that
+     * ends up at the start of the cell content area is handed back to the unallocated region instead.
+     */
+    pub fn drop_cell(&mut self, idx: usize) -> Result<()> {
+        let cell_offset = self.cell_offset(idx)?;
+        let cell_size = self.cell_size(idx)?;
+        let num_cells = self.num_cells();
+
+        let pointer = self.cell_pointer_array_start() + idx * 2;
+        let pointer_array_end = self.cell_pointer_array_start() + num_cells * 2;
+        self.raw_byte_buffer
+            .copy_within(pointer + 2..pointer_array_end, pointer);
+        write_u16(&mut self.raw_byte_buffer, pointer_array_end - 2, 0);
+        write_u16(
+            &mut self.raw_byte_buffer,
+            self.header_offset + 3,
+            (num_cells - 1) as u16,
+        );
+
+        self.free_range(cell_offset, cell_size)?;
+        self.reload_header()
+    }
+
+    /*
+     * Writes a cell over the idx'th cell, returns false when it is larger than the cell it replaces.
+     * The new cell goes at the end of the old cell's bytes and the bytes in front of it are freed like drop_cell()
+     * frees them. Fewer than 4 of them cannot be a freeblock and become fragmented bytes instead, unless that would
+     * take the page past the 60 fragmented bytes a well-formed page may have.
+     */
+    pub fn overwrite_cell(&mut self, idx: usize, cell: &[u8]) -> Result<bool> {
+        let old_offset = self.cell_offset(idx)?;
+        let old_size = self.cell_size(idx)?;
+        if cell.len() > old_size {
+            return Ok(false);
+        }
+
+        let leftover = old_size - cell.len();
+        let fragmented = self.common_header().num_fragmented_free_bytes as usize + leftover;
+        let becomes_fragments = leftover < 4 && old_offset != self.cell_content_area_start();
+        if becomes_fragments && fragmented > 60 {
+            return Ok(false);
+        }
+
+        let cell_offset = old_offset + leftover;
+        self.raw_byte_buffer[cell_offset..cell_offset + cell.len()].copy_from_slice(cell);
+        let pointer = self.cell_pointer_array_start() + idx * 2;
+        write_u16(&mut self.raw_byte_buffer, pointer, cell_offset as u16);
+        if becomes_fragments {
+            self.raw_byte_buffer[self.header_offset + 7] = fragmented as u8;
+        } else if leftover > 0 {
+            self.free_range(old_offset, leftover)?;
+        }
+
+        self.reload_header()?;
+        Ok(true)
+    }
+
+    /*
+     * Rewrites the page from scratch as a page_type page holding the given cells, packed against the end of the usable
+     * space without any freeblocks or fragments. Also how a page changes type, a root leaf becomes an interior page
+     * when it splits. Anything in front of the page header, the database header on page 1, is left untouched.
+     */
+    pub fn rebuild(
+        &mut self,
+        page_type: PageType,
+        cells: &[Vec<u8>],
+        right_most_pointer: Option<u32>,
+    ) -> Result<()> {
+        let usable_size = self.usable_size();
+        let needed: usize = cells.iter().map(|cell| cell.len() + 2).sum();
+        if needed > Self::cell_capacity(&page_type, usable_size, self.header_offset) {
+            return Err(StorageError::InvalidOperation(format!(
+                "{} cells of {needed} bytes do not fit on page {}",
+                cells.len(),
+                self.page_num
+            )));
+        }
+
+        let header_offset = self.header_offset;
+        let pointer_array_start = header_offset + page_header_size(&page_type);
+        let buffer = &mut self.raw_byte_buffer;
+        buffer[header_offset..usable_size].fill(0);
+        buffer[header_offset] = page_type.to_u8();
+        write_u16(buffer, header_offset + 3, cells.len() as u16);
+        if matches!(page_type, PageType::InteriorIndex | PageType::InteriorTable) {
+            write_u32(buffer, header_offset + 8, right_most_pointer.unwrap_or(0));
+        }
+
+        let mut content_start = usable_size;
+        for (idx, cell) in cells.iter().enumerate() {
+            content_start -= cell.len();
+            buffer[content_start..content_start + cell.len()].copy_from_slice(cell);
+            write_u16(buffer, pointer_array_start + idx * 2, content_start as u16);
+        }
+        write_content_start(buffer, header_offset, content_start);
+
+        self.reload_header()
+    }
+
+    // moves every cell against the end of the page so all the free space ends up in the unallocated region
+    fn defragment(&mut self) -> Result<()> {
+        let cells = self.cells()?;
+        self.rebuild(self.page_type.clone(), &cells, self.right_most_pointer())
+    }
+
+    /*
+     * Carves size bytes out of the first freeblock that can hold them, taking them from the end of the block so its
+     * header stays where it is. A block left with less than 4 bytes cannot stay a freeblock, those bytes become
+     * fragments instead, unless that would push the fragment count past the 60 bytes a well-formed page allows.
+     */
+    fn allocate_from_freeblocks(&mut self, size: usize) -> Result<Option<usize>> {
+        // where the pointer to the current freeblock is stored, the page header for the first one
+        let mut link = self.header_offset + 1;
+        for (offset, block_size) in self.freeblocks()? {
+            if block_size >= size {
+                let remainder = block_size - size;
+                let fragmented =
+                    self.common_header().num_fragmented_free_bytes as usize + remainder;
+                if remainder >= 4 {
+                    write_u16(&mut self.raw_byte_buffer, offset + 2, remainder as u16);
+                    return Ok(Some(offset + remainder));
+                }
+                if fragmented <= 60 {
+                    let next = read_u16(&self.raw_byte_buffer, offset)?;
+                    write_u16(&mut self.raw_byte_buffer, link, next);
+                    self.raw_byte_buffer[self.header_offset + 7] = fragmented as u8;
+                    return Ok(Some(offset));
+                }
+            }
+            link = offset;
+        }
+
+        Ok(None)
+    }
+
+    // adds size bytes at offset to the free space of the page, the header is rewritten but not reloaded
+    fn free_range(&mut self, offset: usize, size: usize) -> Result<()> {
+        let mut freeblocks = self.freeblocks()?;
+        let mut fragmented = self.common_header().num_fragmented_free_bytes as usize;
+        let position = freeblocks.partition_point(|(block_offset, _)| *block_offset < offset);
+        freeblocks.insert(position, (offset, size));
+
+        // a gap of less than 4 bytes between two freeblocks cannot hold a cell, so it must be fragmented bytes
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(freeblocks.len());
+        for (block_offset, block_size) in freeblocks {
+            if let Some((previous_offset, previous_size)) = merged.last_mut() {
+                let previous_end = *previous_offset + *previous_size;
+                if block_offset < previous_end {
+                    return Err(self.corrupt_at(block_offset, "freed space overlaps a freeblock"));
+                }
+                let gap = block_offset - previous_end;
+                if gap < 4 {
+                    fragmented = fragmented.checked_sub(gap).ok_or_else(|| {
+                        self.corrupt_at(
+                            previous_end,
+                            "more fragmented bytes than the header counts",
+                        )
+                    })?;
+                    *previous_size += gap + block_size;
+                    continue;
+                }
+            }
+            merged.push((block_offset, block_size));
+        }
+
+        let mut content_start = self.cell_content_area_start();
+        if let Some(&(first_offset, first_size)) = merged.first() {
+            if first_offset == content_start {
+                content_start += first_size;
+                merged.remove(0);
+            }
+        }
+
+        let header_offset = self.header_offset;
+        let buffer = &mut self.raw_byte_buffer;
+        let first = merged.first().map_or(0, |(block_offset, _)| *block_offset);
+        write_u16(buffer, header_offset + 1, first as u16);
+        for (n, (block_offset, block_size)) in merged.iter().enumerate() {
+            let next = merged.get(n + 1).map_or(0, |(next_offset, _)| *next_offset);
+            write_u16(buffer, *block_offset, next as u16);
+            write_u16(buffer, block_offset + 2, *block_size as u16);
+        }
+        write_content_start(buffer, header_offset, content_start);
+        buffer[header_offset + 7] = fragmented as u8;
+        Ok(())
+    }
+
+    // the (offset, size) of every freeblock, the chain runs in order of increasing offset
+    fn freeblocks(&self) -> Result<Vec<(usize, usize)>> {
+        let mut freeblocks = Vec::new();
+        let mut offset = self.common_header().start_of_first_free_block as usize;
+        while offset != 0 {
+            let next = read_u16(&self.raw_byte_buffer, offset)
+                .map_err(|err| err.at(self.page_num, 0))? as usize;
+            let size = read_u16(&self.raw_byte_buffer, offset + 2)
+                .map_err(|err| err.at(self.page_num, 0))? as usize;
+            if size < 4 || offset + size > self.usable_size() || (next != 0 && next < offset + size)
+            {
+                return Err(self.corrupt_at(offset, "malformed freeblock chain"));
+            }
+            freeblocks.push((offset, size));
+            offset = next;
+        }
+
+        Ok(freeblocks)
+    }
+
+    fn common_header(&self) -> &CommonPageHeader {
+        match &self.page_header {
+            PageHeader::Leaf(header) => &header.common_header,
+            PageHeader::Interior(header) => &header.common_header,
+        }
+    }
+
+    fn cell_content_area_start(&self) -> usize {
+        match self.common_header().start_of_cell_content_area {
+            0 => 65536,
+            start => start as usize,
+        }
+    }
+
+    // the gap between the end of the cell pointer array and the start of the cell content area
+    fn unallocated_space(&self) -> usize {
+        let pointer_array_end = self.cell_pointer_array_start() + self.num_cells() * 2;
+        self.cell_content_area_start()
+            .saturating_sub(pointer_array_end)
+    }
+
+    // picks up the header again after the raw bytes changed underneath it
+    fn reload_header(&mut self) -> Result<()> {
+        let (page_type, page_header) =
+            parse_header(self.page_num, &self.raw_byte_buffer, self.header_offset)?;
+        self.page_type = page_type;
+        self.page_header = page_header;
+        Ok(())
+    }
+
+    fn corrupt_at(&self, offset: usize, reason: &str) -> StorageError {
+        StorageError::Corrupt {
+            page: self.page_num,
+            offset,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+// 8 bytes for leaf pages, interior pages add the 4 byte right most pointer
+fn page_header_size(page_type: &PageType) -> usize {
+    match page_type {
+        PageType::LeafIndex | PageType::LeafTable => 8,
+        PageType::InteriorIndex | PageType::InteriorTable => 12,
+    }
+}
+
+// reads the btree page header that starts at offset into the page
+fn parse_header(page_num: u32, buffer: &[u8], offset: usize) -> Result<(PageType, PageHeader)> {
+    let at_page = |err: StorageError| err.at(page_num, 0);
+    let page_type = match PageType::from_u8(read_u8(buffer, offset).map_err(at_page)?) {
+        Some(page_type) => page_type,
+        None => {
+            return Err(StorageError::Corrupt {
+                page: page_num,
+                offset,
+                reason: "invalid page type of btree page".to_string(),
+            })
+        }
+    };
+
+    let common_header = CommonPageHeader::from_buffer(buffer, offset).map_err(at_page)?;
+    // a cell content area of 0 stands for 65536, which only fits a page of the largest size
+    let start_of_cell_content_area = match common_header.start_of_cell_content_area {
+        0 => 65536,
+        start => start as usize,
+    };
+    if start_of_cell_content_area > buffer.len()
+        || common_header.start_of_first_free_block as usize >= buffer.len()
+        || common_header.num_fragmented_free_bytes > 60
+    {
+        return Err(StorageError::Corrupt {
+            page: page_num,
+            offset,
+            reason: "page header points outside of the page".to_string(),
+        });
+    }
+
+    let page_header = match page_type {
+        PageType::InteriorIndex | PageType::InteriorTable => {
+            let right_most_pointer = read_u32(buffer, 8 + offset).map_err(at_page)?;
+            PageHeader::Interior(InteriorPageHeader {
+                common_header,
+                right_most_pointer,
+            })
+        }
+        PageType::LeafIndex | PageType::LeafTable => {
+            PageHeader::Leaf(LeafPageHeader { common_header })
+        }
+    };
+
+    Ok((page_type, page_header))
+}
+
+// big-endian integers at an offset into a page, running off the end of the page means the page is corrupt
+fn read_bytes<const N: usize>(buffer: &[u8], offset: usize) -> Result<[u8; N]> {
+    match buffer.get(offset..offset + N) {
+        Some(bytes) => {
+            let mut fixed = [0; N];
+            fixed.copy_from_slice(bytes);
+            Ok(fixed)
+        }
+        None => Err(StorageError::Corrupt {
+            page: 0,
+            offset,
+            reason: format!("{N} byte integer runs past the end of the page"),
+        }),
+    }
+}
+
+pub(crate) fn read_u8(buffer: &[u8], offset: usize) -> Result<u8> {
+    Ok(read_bytes::<1>(buffer, offset)?[0])
+}
+
+pub(crate) fn read_u16(buffer: &[u8], offset: usize) -> Result<u16> {
+    Ok(u16::from_be_bytes(read_bytes(buffer, offset)?))
+}
+
+pub(crate) fn read_u32(buffer: &[u8], offset: usize) -> Result<u32> {
+    Ok(u32::from_be_bytes(read_bytes(buffer, offset)?))
+}
+
+fn write_u16(buffer: &mut [u8], offset: usize, value: u16) {
+    buffer[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+// the start of the cell content area into the page header, 65536 byte pages are not supported so it always fits
+fn write_content_start(buffer: &mut [u8], header_offset: usize, content_start: usize) {
+    write_u16(buffer, header_offset + 5, content_start as u16);
+}
+
+fn write_u32(buffer: &mut [u8], offset: usize, value: u32) {
+    buffer[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::TableLeafCell;
+
+    #[test]
+    fn test_corruption_is_reported_with_its_location() {
+        let mut buffer = vec![0u8; 512];
+        buffer[0] = 0x07;
+        match BtreePage::new(3, buffer.clone(), 0, 0) {
+            Err(StorageError::Corrupt { page, offset, .. }) => assert_eq!((page, offset), (3, 0)),
+            other => panic!("expected a corrupt page error, got {:?}", other),
+        }
+
+        // a leaf table page with one cell whose pointer runs off the end of the page
+        buffer[0] = 0x0d;
+        buffer[3..5].copy_from_slice(&1u16.to_be_bytes());
+        buffer[5..7].copy_from_slice(&500u16.to_be_bytes());
+        buffer[8..10].copy_from_slice(&600u16.to_be_bytes());
+        let page = BtreePage::new(7, buffer, 0, 0).unwrap();
+        match page.cell_content(0) {
+            Err(StorageError::Corrupt { page, offset, .. }) => assert_eq!((page, offset), (7, 8)),
+            other => panic!("expected a corrupt cell pointer error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_insert_cell_reuses_free_space() {
+        // a table leaf cell of exactly len bytes, with single byte varints for the payload size and rowid
+        let cell = |rowid: u8, len: usize| {
+            let cell =
+                TableLeafCell::to_be_bytes(rowid as i64, len - 2, &vec![rowid; len - 2], None);
+            assert_eq!(cell.len(), len);
+            cell
+        };
+        let mut page = BtreePage::new_empty(2, PageType::LeafTable, 512, 0, 0).unwrap();
+        let cells = (0..4).map(|n| cell(n, 110)).collect::<Vec<_>>();
+        page.rebuild(PageType::LeafTable, &cells, None).unwrap();
+        assert_eq!(page.free_space().unwrap(), 512 - 8 - 4 * 112);
+
+        // drop cell 1 by hand, leaving a freeblock in the middle of the cell content
+        let freeblock = page.cell_offset(1).unwrap();
+        page.raw_byte_buffer[1..3].copy_from_slice(&(freeblock as u16).to_be_bytes());
+        page.raw_byte_buffer[freeblock..freeblock + 4].copy_from_slice(&[0, 0, 0, 110]);
+        page.raw_byte_buffer[3..5].copy_from_slice(&3u16.to_be_bytes());
+        page.raw_byte_buffer.copy_within(12..16, 10);
+        page.reload_header().unwrap();
+        assert_eq!(page.free_space().unwrap(), 512 - 8 - 3 * 112);
+
+        // the tail of the freeblock is handed out first
+        assert!(page.insert_cell(1, &cell(7, 60)).unwrap());
+        assert_eq!(page.cell_offset(1).unwrap(), freeblock + 50);
+        assert_eq!(page.freeblocks().unwrap(), vec![(freeblock, 50)]);
+
+        // too big for the freeblock or the unallocated region, but fits once the page is defragmented
+        let free_space = page.free_space().unwrap();
+        assert!(page.insert_cell(0, &cell(9, free_space - 2)).unwrap());
+        assert_eq!(page.free_space().unwrap(), 0);
+        assert!(!page.insert_cell(0, &cell(1, 4)).unwrap());
+        assert_eq!(
+            page.cells().unwrap(),
+            vec![
+                cell(9, free_space - 2),
+                cell(0, 110),
+                cell(7, 60),
+                cell(2, 110),
+                cell(3, 110)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_drop_cell_coalesces_free_space() {
+        let cell = |rowid: u8, len: usize| {
+            TableLeafCell::to_be_bytes(rowid as i64, len - 2, &vec![rowid; len - 2], None)
+        };
+        let mut page = BtreePage::new_empty(2, PageType::LeafTable, 512, 0, 0).unwrap();
+        let cells = (0..5).map(|n| cell(n, 50)).collect::<Vec<_>>();
+        page.rebuild(PageType::LeafTable, &cells, None).unwrap();
+        let offsets = (0..5)
+            .map(|idx| page.cell_offset(idx).unwrap())
+            .collect::<Vec<_>>();
+
+        // cells are packed from the end of the page, cell 4 is the one at the start of the content area
+        page.drop_cell(1).unwrap();
+        assert_eq!(page.freeblocks().unwrap(), vec![(offsets[1], 50)]);
+        page.drop_cell(2).unwrap();
+        assert_eq!(
+            page.freeblocks().unwrap(),
+            vec![(offsets[3], 50), (offsets[1], 50)]
+        );
+        // cell 2 sits between the two freeblocks and joins them into one
+        page.drop_cell(1).unwrap();
+        assert_eq!(page.freeblocks().unwrap(), vec![(offsets[3], 150)]);
+        // the freeblock now starts the content area, so all of it goes back to the unallocated region
+        page.drop_cell(1).unwrap();
+        assert_eq!(page.freeblocks().unwrap(), vec![]);
+        assert_eq!(page.free_space().unwrap(), 512 - 8 - 52);
+        assert_eq!(page.cells().unwrap(), vec![cell(0, 50)]);
+    }
 }
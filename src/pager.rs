@@ -0,0 +1,325 @@
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+
+use crate::database::DataBaseMetadata;
+use crate::error::{Result, StorageError};
+use crate::page::{read_u32, BtreePage};
+
+/*
+ * Owns the database file and moves whole pages in and out of it, every btree of a database shares one pager.
+ *
+ * Writes go straight to the file, there is no journal so a write that is interrupted halfway can leave the file
+ * inconsistent. The header on page 1 is kept in memory and only written back on commit(), which is also where the
+ * change counter moves, so callers commit once after each operation that changed the file.
+ */
+pub(crate) struct Pager {
+    db_file_name: String,
+    file: File,
+    metadata: DataBaseMetadata,
+    page_size: usize,
+    // taken from the file length, the in-header size is only trustworthy when the header says so
+    page_count: u32,
+}
+
+impl Pager {
+    pub fn open(db_file_name: &str) -> Result<Self> {
+        let metadata = DataBaseMetadata::read_from_file(db_file_name)?;
+        // a database we may not write to can still be read
+        let file = match OpenOptions::new().read(true).write(true).open(db_file_name) {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::PermissionDenied => File::open(db_file_name)?,
+            Err(err) => return Err(err.into()),
+        };
+
+        let page_size = metadata.page_size as usize;
+        let page_count = (file.metadata()?.len() / page_size as u64) as u32;
+
+        Ok(Pager {
+            db_file_name: db_file_name.to_string(),
+            file,
+            metadata,
+            page_size,
+            page_count,
+        })
+    }
+
+    pub fn db_file_name(&self) -> &str {
+        &self.db_file_name
+    }
+
+    pub fn metadata(&self) -> &DataBaseMetadata {
+        &self.metadata
+    }
+
+    pub fn page_count(&self) -> u32 {
+        self.page_count
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    pub fn reserved_bytes_per_page(&self) -> u8 {
+        self.metadata.bytes_unused_reserved_space_at_page_end
+    }
+
+    // page size less the reserved region at the end of every page
+    pub fn usable_size(&self) -> usize {
+        self.page_size - self.reserved_bytes_per_page() as usize
+    }
+
+    pub fn read_page(&mut self, page_num: u32) -> Result<BtreePage> {
+        let buffer = self.read_raw_page(page_num)?;
+        // only page 1 carries the 100 byte database header in front of the btree page header
+        let header_offset = if page_num == 1 { 100 } else { 0 };
+        BtreePage::new(
+            page_num,
+            buffer,
+            header_offset,
+            self.reserved_bytes_per_page(),
+        )
+    }
+
+    pub fn read_raw_page(&mut self, page_num: u32) -> Result<Vec<u8>> {
+        if page_num == 0 || page_num > self.page_count {
+            return Err(StorageError::corrupt(format!(
+                "page {page_num} is out of range for a database of {} pages",
+                self.page_count
+            )));
+        }
+
+        let mut buffer = vec![0; self.page_size];
+        self.file.seek(SeekFrom::Start(self.page_start(page_num)))?;
+        self.file.read_exact(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    pub fn write_page(&mut self, page: &BtreePage) -> Result<()> {
+        self.write_raw_page(page.page_num, page.as_bytes())
+    }
+
+    pub fn write_raw_page(&mut self, page_num: u32, bytes: &[u8]) -> Result<()> {
+        if page_num == 0 || page_num > self.page_count || bytes.len() != self.page_size {
+            return Err(StorageError::InvalidOperation(format!(
+                "cannot write {} bytes to page {page_num}",
+                bytes.len()
+            )));
+        }
+
+        self.file.seek(SeekFrom::Start(self.page_start(page_num)))?;
+        if page_num == 1 {
+            // the copy of page 1 being written may carry an older header than the one we hold
+            self.file.write_all(&self.metadata.to_bytes())?;
+            self.file.write_all(&bytes[100..])?;
+        } else {
+            self.file.write_all(bytes)?;
+        }
+        Ok(())
+    }
+
+    /*
+     * Hands out a zeroed page, taken from the freelist when it has any so the file only grows once every freed page is
+     * in use again. The last leaf of the first trunk goes first, a trunk without leaves is handed out itself and the
+     * next trunk takes its place at the head of the list. The file grows past the lock-byte page without using it.
+     */
+    pub fn allocate_page(&mut self) -> Result<u32> {
+        let page_num = match self.take_free_page()? {
+            Some(page_num) => page_num,
+            None => {
+                self.page_count += 1;
+                if self.page_count == self.lock_byte_page() {
+                    self.page_count += 1;
+                }
+                self.page_count
+            }
+        };
+        self.write_raw_page(page_num, &vec![0; self.page_size])?;
+        Ok(page_num)
+    }
+
+    fn take_free_page(&mut self) -> Result<Option<u32>> {
+        let first_trunk = self.metadata.first_freelist_trunk_page_num;
+        if first_trunk == 0 {
+            return Ok(None);
+        }
+        if self.metadata.total_freelist_pages == 0 {
+            return Err(StorageError::corrupt(format!(
+                "freelist starts at page {first_trunk} but counts no pages"
+            )));
+        }
+
+        if first_trunk == self.lock_byte_page() {
+            return Err(StorageError::corrupt(format!(
+                "freelist trunk {first_trunk} is the lock-byte page"
+            )));
+        }
+
+        let mut trunk = self.read_raw_page(first_trunk)?;
+        let num_leaves = read_u32(&trunk, 4)? as usize;
+        if num_leaves > self.usable_size() / 4 - 2 {
+            return Err(StorageError::Corrupt {
+                page: first_trunk,
+                offset: 4,
+                reason: format!("freelist trunk claims {num_leaves} leaf pages"),
+            });
+        }
+
+        let page_num = if num_leaves == 0 {
+            self.metadata.first_freelist_trunk_page_num = read_u32(&trunk, 0)?;
+            first_trunk
+        } else {
+            let slot = 8 + (num_leaves - 1) * 4;
+            let leaf = read_u32(&trunk, slot)?;
+            if leaf <= 1 || leaf > self.page_count || leaf == self.lock_byte_page() {
+                return Err(StorageError::Corrupt {
+                    page: first_trunk,
+                    offset: slot,
+                    reason: format!("freelist leaf page {leaf} is out of range"),
+                });
+            }
+            trunk[slot..slot + 4].fill(0);
+            trunk[4..8].copy_from_slice(&(num_leaves as u32 - 1).to_be_bytes());
+            self.write_raw_page(first_trunk, &trunk)?;
+            leaf
+        };
+        self.metadata.total_freelist_pages -= 1;
+        Ok(Some(page_num))
+    }
+
+    /*
+     * Hands a page nothing uses anymore to the freelist, a chain of trunk pages that each hold the number of the next
+     * trunk, a count and that many freelist leaf page numbers. The freed page becomes a leaf of the first trunk while
+     * that has room, otherwise it becomes the new first trunk. Like SQLite trunks are never filled to the last slot,
+     * versions before 3.6.0 mishandled full trunks. Leaf pages are left as they are, nothing reads them anymore.
+     */
+    pub fn free_page(&mut self, page_num: u32) -> Result<()> {
+        if page_num <= 1 || page_num > self.page_count || page_num == self.lock_byte_page() {
+            return Err(StorageError::InvalidOperation(format!(
+                "page {page_num} cannot be freed"
+            )));
+        }
+
+        let first_trunk = self.metadata.first_freelist_trunk_page_num;
+        if first_trunk != 0 {
+            let mut trunk = self.read_raw_page(first_trunk)?;
+            let num_leaves = read_u32(&trunk, 4)? as usize;
+            if num_leaves > self.usable_size() / 4 - 2 {
+                return Err(StorageError::Corrupt {
+                    page: first_trunk,
+                    offset: 4,
+                    reason: format!("freelist trunk claims {num_leaves} leaf pages"),
+                });
+            }
+            if num_leaves < self.usable_size() / 4 - 8 {
+                let slot = 8 + num_leaves * 4;
+                trunk[slot..slot + 4].copy_from_slice(&page_num.to_be_bytes());
+                trunk[4..8].copy_from_slice(&(num_leaves as u32 + 1).to_be_bytes());
+                self.write_raw_page(first_trunk, &trunk)?;
+                self.metadata.total_freelist_pages += 1;
+                return Ok(());
+            }
+        }
+
+        let mut trunk = vec![0; self.page_size];
+        trunk[..4].copy_from_slice(&first_trunk.to_be_bytes());
+        self.write_raw_page(page_num, &trunk)?;
+        self.metadata.first_freelist_trunk_page_num = page_num;
+        self.metadata.total_freelist_pages += 1;
+        Ok(())
+    }
+
+    // every change to sqlite_schema goes through here, the new cookie is written with the next commit()
+    pub fn bump_schema_cookie(&mut self) {
+        self.metadata.bump_schema_cookie();
+    }
+
+    // writes the header back with the change counter and in-header size brought up to date
+    pub fn commit(&mut self) -> Result<()> {
+        self.metadata.record_change(self.page_count);
+        self.metadata.write_to(&mut self.file)?;
+        self.file.flush()?;
+        Ok(())
+    }
+
+    /*
+     * The page holding the bytes SQLite locks on systems with mandatory locking, starting at offset 0x40000000. It is
+     * never used for anything, so a file bigger than a gigabyte leaves a page hole there.
+     */
+    fn lock_byte_page(&self) -> u32 {
+        (0x40000000 / self.page_size + 1) as u32
+    }
+
+    fn page_start(&self, page_num: u32) -> u64 {
+        (page_num as u64 - 1) * self.page_size as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{CreateOptions, Database};
+
+    #[test]
+    fn test_freed_pages_are_allocated_again() {
+        let path = std::env::temp_dir().join(format!("freelist-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+        let options = CreateOptions {
+            page_size: 512,
+            ..CreateOptions::default()
+        };
+        Database::create(path, options).unwrap();
+
+        let mut pager = Pager::open(path).unwrap();
+        let pages = (0..300)
+            .map(|_| pager.allocate_page().unwrap())
+            .collect::<Vec<u32>>();
+        // a 512 byte trunk holds 120 leaves, so this needs a chain of trunks
+        for page_num in &pages {
+            pager.free_page(*page_num).unwrap();
+        }
+        pager.commit().unwrap();
+        assert_eq!(pager.metadata().total_freelist_pages, 300);
+        assert_ne!(pager.metadata().first_freelist_trunk_page_num, 0);
+
+        let mut pager = Pager::open(path).unwrap();
+        let mut reused = (0..300)
+            .map(|_| pager.allocate_page().unwrap())
+            .collect::<Vec<u32>>();
+        reused.sort();
+        assert_eq!(reused, pages);
+        assert_eq!(pager.page_count(), 301);
+        assert_eq!(pager.metadata().total_freelist_pages, 0);
+        assert_eq!(pager.metadata().first_freelist_trunk_page_num, 0);
+        assert!(pager.read_raw_page(300).unwrap().iter().all(|b| *b == 0));
+
+        // with the freelist used up the file grows again
+        assert_eq!(pager.allocate_page().unwrap(), 302);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_lock_byte_page_is_never_allocated() {
+        let path = std::env::temp_dir().join(format!("lock-byte-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+        let options = CreateOptions {
+            page_size: 512,
+            ..CreateOptions::default()
+        };
+        Database::create(path, options).unwrap();
+
+        // pretend the file reaches up to the lock-byte page, the pages in between stay a hole in a sparse file
+        let mut pager = Pager::open(path).unwrap();
+        let lock_byte_page = pager.lock_byte_page();
+        assert_eq!(lock_byte_page, 2097153);
+        pager.page_count = lock_byte_page - 1;
+        assert_eq!(pager.allocate_page().unwrap(), lock_byte_page + 1);
+        assert_eq!(pager.page_count(), lock_byte_page + 1);
+        assert!(pager.free_page(lock_byte_page).is_err());
+
+        pager.free_page(lock_byte_page + 1).unwrap();
+        assert_eq!(pager.allocate_page().unwrap(), lock_byte_page + 1);
+        std::fs::remove_file(path).unwrap();
+    }
+}
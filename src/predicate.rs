@@ -0,0 +1,380 @@
+use std::cmp::Ordering;
+
+use crate::error::{Result, StorageError};
+use crate::row::Row;
+use crate::sql_data_types::{format_real, Affinity, SerialData};
+use crate::sql_parser::parse_predicate;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ComparisonOp {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+}
+
+impl ComparisonOp {
+    fn matches(&self, ordering: Ordering) -> bool {
+        match self {
+            ComparisonOp::Eq => ordering == Ordering::Equal,
+            ComparisonOp::NotEq => ordering != Ordering::Equal,
+            ComparisonOp::Lt => ordering == Ordering::Less,
+            ComparisonOp::LtEq => ordering != Ordering::Greater,
+            ComparisonOp::Gt => ordering == Ordering::Greater,
+            ComparisonOp::GtEq => ordering != Ordering::Less,
+        }
+    }
+}
+
+/*
+ * A filter over the columns of a row, the Filters[] of Get(Table, Fields[], Filters[]).
+ * Parsed from the WHERE clause subset of SQL, e.g. "age >= 30 AND (name LIKE 'a%' OR id IN (1, 2, 3))".
+ *
+ * Evaluation follows SQLite:
+ * - the column's affinity is applied to the literal before comparing, so age = '30' matches an INTEGER column holding 30
+ * - values are compared with SQLite's storage class ordering (see SerialData::sqlite_cmp)
+ * - NULL makes a comparison unknown rather than false, AND/OR/NOT use three-valued logic and a row only matches
+ *   when the whole predicate is true, so neither a = 1 nor NOT (a = 1) matches a row where a is NULL
+ * - LIKE is case insensitive for ASCII letters, % matches any sequence of characters and _ matches any one character
+ */
+#[derive(Debug, Clone)]
+pub enum Predicate {
+    Comparison {
+        column: String,
+        op: ComparisonOp,
+        value: SerialData,
+    },
+    IsNull {
+        column: String,
+        negated: bool,
+    },
+    In {
+        column: String,
+        values: Vec<SerialData>,
+        negated: bool,
+    },
+    Between {
+        column: String,
+        low: SerialData,
+        high: SerialData,
+        negated: bool,
+    },
+    Like {
+        column: String,
+        pattern: String,
+        negated: bool,
+    },
+    And(Box<Predicate>, Box<Predicate>),
+    Or(Box<Predicate>, Box<Predicate>),
+    Not(Box<Predicate>),
+}
+
+impl Predicate {
+    pub fn parse(input: &str) -> Result<Predicate> {
+        parse_predicate(input)
+    }
+
+    // every column the predicate reads, in the order they appear
+    pub fn columns(&self) -> Vec<&str> {
+        match self {
+            Predicate::Comparison { column, .. }
+            | Predicate::IsNull { column, .. }
+            | Predicate::In { column, .. }
+            | Predicate::Between { column, .. }
+            | Predicate::Like { column, .. } => vec![column.as_str()],
+            Predicate::And(left, right) | Predicate::Or(left, right) => {
+                let mut columns = left.columns();
+                columns.extend(right.columns());
+                columns
+            }
+            Predicate::Not(inner) => inner.columns(),
+        }
+    }
+
+    // true only when the predicate holds, an unknown (NULL) result does not match
+    pub fn evaluate(&self, row: &Row) -> Result<bool> {
+        Ok(self.truth_value(row)? == Some(true))
+    }
+
+    // SQL three-valued logic, None is unknown
+    fn truth_value(&self, row: &Row) -> Result<Option<bool>> {
+        let truth = match self {
+            Predicate::Comparison { column, op, value } => {
+                let (operand, affinity) = column_operand(row, column)?;
+                compare(operand, &comparison_value(affinity, value))
+                    .map(|ordering| op.matches(ordering))
+            }
+            Predicate::IsNull { column, negated } => {
+                let (operand, _) = column_operand(row, column)?;
+                Some(matches!(operand, SerialData::Null) != *negated)
+            }
+            Predicate::In {
+                column,
+                values,
+                negated,
+            } => {
+                let (operand, affinity) = column_operand(row, column)?;
+                let mut truth = Some(false);
+                for value in values {
+                    match compare(operand, &comparison_value(affinity, value)) {
+                        Some(Ordering::Equal) => {
+                            truth = Some(true);
+                            break;
+                        }
+                        Some(_) => {}
+                        None => truth = None,
+                    }
+                }
+                // an empty list never matches, even a NULL
+                if values.is_empty() {
+                    truth = Some(false);
+                }
+                negate_if(truth, *negated)
+            }
+            Predicate::Between {
+                column,
+                low,
+                high,
+                negated,
+            } => {
+                let (operand, affinity) = column_operand(row, column)?;
+                let above_low = compare(operand, &comparison_value(affinity, low))
+                    .map(|ordering| ComparisonOp::GtEq.matches(ordering));
+                let below_high = compare(operand, &comparison_value(affinity, high))
+                    .map(|ordering| ComparisonOp::LtEq.matches(ordering));
+                negate_if(and(above_low, below_high), *negated)
+            }
+            Predicate::Like {
+                column,
+                pattern,
+                negated,
+            } => {
+                let (operand, _) = column_operand(row, column)?;
+                let truth = text_of(operand).map(|text| {
+                    like_matches(
+                        &pattern.chars().collect::<Vec<_>>(),
+                        &text.chars().collect::<Vec<_>>(),
+                    )
+                });
+                negate_if(truth, *negated)
+            }
+            Predicate::And(left, right) => match left.truth_value(row)? {
+                Some(false) => Some(false),
+                left => and(left, right.truth_value(row)?),
+            },
+            Predicate::Or(left, right) => match left.truth_value(row)? {
+                Some(true) => Some(true),
+                left => or(left, right.truth_value(row)?),
+            },
+            Predicate::Not(inner) => negate_if(inner.truth_value(row)?, true),
+        };
+
+        Ok(truth)
+    }
+}
+
+fn column_operand<'r>(row: &'r Row, column: &str) -> Result<(&'r SerialData, Affinity)> {
+    let schema = row.schema();
+    match schema.column_index(column) {
+        Some(idx) => Ok((&row.values()[idx], schema.columns[idx].affinity)),
+        None => Err(StorageError::NotFound(format!(
+            "Column {column} in table {}",
+            schema.table_name
+        ))),
+    }
+}
+
+/*
+ * When a column is compared with a literal the literal takes on the column's affinity first:
+ * INTEGER, REAL and NUMERIC columns apply NUMERIC affinity, TEXT columns apply TEXT affinity,
+ * and a BLOB column (or one without a declared type) compares the literal as is.
+ */
+fn comparison_value(column_affinity: Affinity, value: &SerialData) -> SerialData {
+    match column_affinity {
+        Affinity::Integer | Affinity::Real | Affinity::Numeric => {
+            Affinity::Numeric.apply(value.clone())
+        }
+        Affinity::Text => Affinity::Text.apply(value.clone()),
+        Affinity::Blob => value.clone(),
+    }
+}
+
+// None when either side is NULL, NULL is neither equal to nor different from anything
+fn compare(a: &SerialData, b: &SerialData) -> Option<Ordering> {
+    if matches!(a, SerialData::Null) || matches!(b, SerialData::Null) {
+        return None;
+    }
+    Some(a.sqlite_cmp(b))
+}
+
+fn and(a: Option<bool>, b: Option<bool>) -> Option<bool> {
+    match (a, b) {
+        (Some(false), _) | (_, Some(false)) => Some(false),
+        (Some(true), Some(true)) => Some(true),
+        _ => None,
+    }
+}
+
+fn or(a: Option<bool>, b: Option<bool>) -> Option<bool> {
+    match (a, b) {
+        (Some(true), _) | (_, Some(true)) => Some(true),
+        (Some(false), Some(false)) => Some(false),
+        _ => None,
+    }
+}
+
+fn negate_if(truth: Option<bool>, negated: bool) -> Option<bool> {
+    truth.map(|truth| truth != negated)
+}
+
+// LIKE reads its operand as text, numbers are rendered the way SQLite prints them
+fn text_of(value: &SerialData) -> Option<String> {
+    match value {
+        SerialData::Null | SerialData::Reserved => None,
+        SerialData::Text(text) => Some(text.clone()),
+        SerialData::Blob(blob) => Some(String::from_utf8_lossy(blob).into_owned()),
+        SerialData::F64(num) => Some(format_real(*num)),
+        value => value.as_integer().map(|num| num.to_string()),
+    }
+}
+
+// glob style matching, on a mismatch after a % the % is retried one character further along the text
+fn like_matches(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut last_wildcard: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('%') => {
+                last_wildcard = Some((p, t));
+                p += 1;
+            }
+            Some('_') => {
+                p += 1;
+                t += 1;
+            }
+            Some(ch) if ch.eq_ignore_ascii_case(&text[t]) => {
+                p += 1;
+                t += 1;
+            }
+            _ => match last_wildcard {
+                Some((wildcard_p, wildcard_t)) => {
+                    last_wildcard = Some((wildcard_p, wildcard_t + 1));
+                    p = wildcard_p + 1;
+                    t = wildcard_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|ch| *ch == '%')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::sql_parser::parse_create_table_stmt;
+
+    fn row(values: Vec<SerialData>) -> Row {
+        let schema = parse_create_table_stmt(
+            "CREATE TABLE people (id INTEGER PRIMARY KEY, name TEXT, age INTEGER, score REAL)",
+        )
+        .unwrap();
+        Row::new(Rc::new(schema), 7, values)
+    }
+
+    fn matches(predicate: &str, row: &Row) -> bool {
+        Predicate::parse(predicate).unwrap().evaluate(row).unwrap()
+    }
+
+    #[test]
+    fn test_comparisons_apply_column_affinity() {
+        let alice = row(vec![
+            SerialData::Null,
+            SerialData::Text("Alice".to_string()),
+            SerialData::I8(30),
+            SerialData::F64(4.5),
+        ]);
+
+        assert!(matches("id = 7", &alice));
+        assert!(matches("age != 31", &alice));
+        assert!(matches("age <> 31", &alice));
+        assert!(!matches("age != 30", &alice));
+        assert!(matches("age = '30'", &alice));
+        assert!(matches("age >= 30 AND age <= 30", &alice));
+        assert!(matches("score > 4 and score < 5", &alice));
+        assert!(matches("name = 'Alice'", &alice));
+        assert!(!matches("name = 'alice'", &alice));
+        // text sorts after every number
+        assert!(matches("name > 1000", &alice));
+        assert!(matches("age BETWEEN 18 AND 65", &alice));
+        assert!(matches("age NOT BETWEEN 31 AND 65", &alice));
+        assert!(matches("id IN (1, 7, 9)", &alice));
+        assert!(matches("name NOT IN ('Bob', 'Carol')", &alice));
+    }
+
+    #[test]
+    fn test_null_is_unknown() {
+        let nobody = row(vec![
+            SerialData::Null,
+            SerialData::Null,
+            SerialData::Null,
+            SerialData::Null,
+        ]);
+
+        assert!(!matches("age = 1", &nobody));
+        assert!(!matches("NOT (age = 1)", &nobody));
+        assert!(!matches("age != 1", &nobody));
+        assert!(!matches("age IN (1, 2)", &nobody));
+        assert!(!matches("name LIKE '%'", &nobody));
+        assert!(matches("age IS NULL", &nobody));
+        assert!(!matches("age IS NOT NULL", &nobody));
+        assert!(matches("age = 1 OR id = 7", &nobody));
+        assert!(!matches("age = 1 AND id = 7", &nobody));
+        assert!(matches("NOT (age = 1 AND id = 8)", &nobody));
+        assert!(!matches("id NOT IN (1, NULL)", &nobody));
+    }
+
+    #[test]
+    fn test_like_and_precedence() {
+        let bob = row(vec![
+            SerialData::Null,
+            SerialData::Text("Bobby Tables".to_string()),
+            SerialData::I8(12),
+            SerialData::Null,
+        ]);
+
+        assert!(matches("name LIKE 'bob%'", &bob));
+        assert!(matches("name LIKE '%TABLES'", &bob));
+        assert!(matches("name LIKE 'B_bby%s'", &bob));
+        assert!(!matches("name LIKE 'Bob'", &bob));
+        assert!(matches("name NOT LIKE 'Alice%'", &bob));
+        assert!(matches("age LIKE '1%'", &bob));
+        // AND binds tighter than OR
+        assert!(matches("age = 1 AND age = 2 OR id = 7", &bob));
+        assert!(!matches("age = 1 AND (age = 2 OR id = 7)", &bob));
+    }
+
+    #[test]
+    fn test_parse_errors_and_columns() {
+        let predicate = Predicate::parse("a!=1 AND (b IS NULL OR c LIKE 'x%')").unwrap();
+        assert_eq!(predicate.columns(), vec!["a", "b", "c"]);
+        assert!(matches!(
+            predicate,
+            Predicate::And(left, _) if matches!(*left, Predicate::Comparison { op: ComparisonOp::NotEq, .. })
+        ));
+
+        assert!(Predicate::parse("").is_err());
+        assert!(Predicate::parse("a = ").is_err());
+        assert!(Predicate::parse("a = 1 AND").is_err());
+        assert!(Predicate::parse("(a = 1").is_err());
+        assert!(Predicate::parse("a BETWEEN 1").is_err());
+        assert!(Predicate::parse("a = 1 b = 2").is_err());
+        assert!(Predicate::parse("a LIKE 1").is_err());
+    }
+}
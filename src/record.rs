@@ -39,12 +39,11 @@ use std::{
     io::{Read, Seek, SeekFrom},
 };
 
-use anyhow::{bail, Result};
-
+use crate::database::SchemaFormatNumber;
+use crate::error::{Result, StorageError};
 use crate::sql_data_types::{SerialData, SerialDataError, SerialType, VarInt, VarIntError};
 
 use log::debug;
-use std::convert::TryInto;
 
 #[derive(Debug, Clone)]
 pub struct Record {
@@ -52,13 +51,54 @@ pub struct Record {
 }
 
 impl Record {
+    pub fn new(serial_data: Vec<SerialData>) -> Self {
+        Record { serial_data }
+    }
+
+    pub fn into_serial_data(self) -> Vec<SerialData> {
+        self.serial_data
+    }
+
+    /*
+     * Encodes the record the way from_be_bytes reads it, a varint header size, one serial type varint per value and
+     * then the value bodies back to back. The header size counts its own varint, so growing the header can grow the
+     * size varint too and we settle it by trying the smallest size first.
+     */
+    pub fn to_be_bytes(&self, schema_format: &SchemaFormatNumber) -> Vec<u8> {
+        let integer_constants = matches!(schema_format, SchemaFormatNumber::Fmt4);
+        let mut serial_types = Vec::new();
+        let mut body = Vec::new();
+        for data in &self.serial_data {
+            let (serial_type, bytes) = data.to_serial_type_and_bytes(integer_constants);
+            push_varint(&mut serial_types, serial_type);
+            body.extend(bytes);
+        }
+
+        let mut header_size = serial_types.len() as i64 + 1;
+        while varint_len(header_size) + serial_types.len() as i64 != header_size {
+            header_size = varint_len(header_size) + serial_types.len() as i64;
+        }
+
+        let mut bytes = Vec::with_capacity(header_size as usize + body.len());
+        push_varint(&mut bytes, header_size);
+        bytes.extend(serial_types);
+        bytes.extend(body);
+        bytes
+    }
+
     pub fn from_be_bytes(bytes: &[u8]) -> Result<(Self, u64)> {
-        let header_size_varint = VarInt::from_be_bytes(&bytes)?;
+        let header_size_varint = VarInt::from_be_bytes(bytes)?;
         // track how many bytes varint was composed of
         let mut bytes_read_so_far = header_size_varint.1 as usize;
         // we know varint header size is folowed by header section, given that we know header size we know end of header
         let end_of_header_idx = header_size_varint.0 as usize; // includes size of header varint itself
 
+        if end_of_header_idx > bytes.len() {
+            return Err(StorageError::corrupt(format!(
+                "record header of {end_of_header_idx} bytes runs past the end of the record"
+            )));
+        }
+
         let mut serial_types = Vec::new();
         while bytes_read_so_far < end_of_header_idx {
             let serial_type_varint = VarInt::from_be_bytes(&bytes[bytes_read_so_far..])?;
@@ -66,10 +106,9 @@ impl Record {
             serial_types.push(SerialType::from_varint(serial_type_varint)?);
         }
 
-        assert!(
-            bytes_read_so_far == end_of_header_idx,
-            "Header size mismatch"
-        );
+        if bytes_read_so_far != end_of_header_idx {
+            return Err(StorageError::corrupt("record header size mismatch"));
+        }
 
         // now from serial types array read the body and create serial_data
         let mut serial_data = Vec::new();
@@ -83,7 +122,7 @@ impl Record {
             bytes_read_so_far += bytes_read;
             serial_data.push(data);
         }
-        Ok((Record { serial_data }, bytes_read_so_far.try_into()?))
+        Ok((Record { serial_data }, bytes_read_so_far as u64))
     }
 }
 
@@ -94,6 +133,7 @@ pub struct OverflowRecord {
     overflow_page: u32,
     db_file_name: String,
     page_size: u16,
+    reserved_bytes_per_page: u8,
 }
 
 impl OverflowRecord {
@@ -102,15 +142,22 @@ impl OverflowRecord {
         bytes: &[u8],
         db_file_name: String,
         page_size: u16,
+        reserved_bytes_per_page: u8,
     ) -> Result<(Self, u64)> {
         // dont read the full payload in memory just the metadata
         let record_header_size_op = VarInt::from_be_bytes(bytes)?;
         let record_header_size = record_header_size_op.0;
         let bytes_read = record_header_size_op.1;
 
-        let init_raw_record_payload_size = bytes_stored_on_leaf - bytes_read as i64;
         let start_of_record_payload = bytes_read as usize;
-        let end_of_record_payload = (bytes_read as i64 + init_raw_record_payload_size) as usize;
+        let end_of_record_payload = bytes_stored_on_leaf as usize;
+        if end_of_record_payload < start_of_record_payload
+            || end_of_record_payload + 4 > bytes.len()
+        {
+            return Err(StorageError::corrupt(format!(
+                "overflowing payload of {bytes_stored_on_leaf} local bytes runs past the end of the page"
+            )));
+        }
         let init_raw_record_payload: Vec<u8> =
             bytes[start_of_record_payload..end_of_record_payload].to_vec();
 
@@ -124,13 +171,14 @@ impl OverflowRecord {
         // return the bytes_stored_on_leaf for consistency
         Ok((
             OverflowRecord {
-                record_header_size: record_header_size.try_into()?,
+                record_header_size: record_header_size as u64,
                 raw_record_payload: init_raw_record_payload,
                 overflow_page,
                 db_file_name,
                 page_size,
+                reserved_bytes_per_page,
             },
-            bytes_stored_on_leaf.try_into()?,
+            bytes_stored_on_leaf as u64,
         ))
     }
 
@@ -139,6 +187,8 @@ impl OverflowRecord {
         // create a file handle because overflowing records need to be seeking the db file on disk for the linked list reads
         let mut db_file_handle = File::open(self.db_file_name.clone())?;
         let page_size = self.page_size;
+        // every overflow page holds the next page number followed by as much payload as the usable space allows
+        let overflow_content_size = page_size as usize - self.reserved_bytes_per_page as usize - 4;
         // read the header given that we know the header size already
         let mut total_offset: usize = 0;
         let mut local_offset: usize = 0;
@@ -146,7 +196,7 @@ impl OverflowRecord {
         while (total_offset as u64) < self.record_header_size - 1 {
             if local_offset >= self.raw_record_payload.len() {
                 if self.overflow_page == 0 {
-                    bail!("Overflow record is missing a page");
+                    return Err(missing_overflow_page());
                 }
                 // read the next page, retaining nothing from the current page
                 db_file_handle.seek(std::io::SeekFrom::Start(
@@ -157,8 +207,8 @@ impl OverflowRecord {
                 db_file_handle.read_exact(&mut next_page_num_repr)?;
                 let next_page_num = u32::from_be_bytes(next_page_num_repr);
                 self.overflow_page = next_page_num;
-                let mut next_page_bytes = vec![0; page_size as usize];
-                db_file_handle.read(&mut next_page_bytes)?;
+                let mut next_page_bytes = vec![0; overflow_content_size];
+                db_file_handle.read_exact(&mut next_page_bytes)?;
                 self.raw_record_payload = next_page_bytes;
                 local_offset = 0;
             }
@@ -169,7 +219,7 @@ impl OverflowRecord {
                     Err(err) => match err {
                         VarIntError::Incomplete => {
                             if self.overflow_page == 0 {
-                                bail!("Overflow record is missing a page");
+                                return Err(missing_overflow_page());
                             }
                             // read in the next page
                             let next_page_addr_bytes =
@@ -181,16 +231,15 @@ impl OverflowRecord {
                             self.overflow_page = next_page;
                             // retain the bytes in previous buffer from local offset till end of buffer
                             let previous_buffer = self.raw_record_payload[local_offset..].to_vec();
-                            // the -4 accounts for the metadata on each page for the next overflow page address stored in the first 4 bytes
-                            let mut next_page_bytes = vec![0; page_size as usize - 4];
+                            let mut next_page_bytes = vec![0; overflow_content_size];
 
-                            db_file_handle.read(&mut next_page_bytes)?;
+                            db_file_handle.read_exact(&mut next_page_bytes)?;
                             self.raw_record_payload = previous_buffer;
                             self.raw_record_payload.extend(next_page_bytes);
                             local_offset = 0;
                             continue;
                         }
-                        e => bail!(e),
+                        err => return Err(err.into()),
                     },
                 };
 
@@ -208,12 +257,12 @@ impl OverflowRecord {
                 .serial_type_to_serial_data(&self.raw_record_payload[local_offset..])
             {
                 Ok(res) => res,
-                Err(err) => match err.downcast_ref::<SerialDataError>() {
-                    Some(SerialDataError::OutOfBounds) => {
+                Err(err) => match err {
+                    SerialDataError::OutOfBounds => {
                         debug!("load extra page");
 
                         if self.overflow_page == 0 {
-                            bail!("Overflow record is missing a page");
+                            return Err(missing_overflow_page());
                         }
                         // read in the next page
                         let page_to_read_addr_bytes =
@@ -224,9 +273,8 @@ impl OverflowRecord {
                         db_file_handle.read_exact(&mut next_page_number_as_bytes)?;
 
                         // I am choosing to let the buffer read extra bytes since we know pages are sized as chunks of max_page_size
-                        // the - 4 accounts for the metadata on each page for the next overflow page address stored in the first 4 bytes
-                        let mut next_page_bytes = vec![0; page_size as usize - 4];
-                        db_file_handle.read(&mut next_page_bytes)?;
+                        let mut next_page_bytes = vec![0; overflow_content_size];
+                        db_file_handle.read_exact(&mut next_page_bytes)?;
 
                         let next_page = u32::from_be_bytes(next_page_number_as_bytes);
                         self.overflow_page = next_page;
@@ -241,7 +289,7 @@ impl OverflowRecord {
                         continue;
                     }
                     // Any other error while converting is not recoverable or expected
-                    _ => bail!(err),
+                    err => return Err(err.into()),
                 },
             };
 
@@ -254,6 +302,19 @@ impl OverflowRecord {
     }
 }
 
+fn push_varint(bytes: &mut Vec<u8>, value: i64) {
+    let (encoded, len) = VarInt::encode(value);
+    bytes.extend_from_slice(&encoded[..len]);
+}
+
+fn varint_len(value: i64) -> i64 {
+    VarInt::encode(value).1 as i64
+}
+
+fn missing_overflow_page() -> StorageError {
+    StorageError::corrupt("Overflow record is missing a page")
+}
+
 // lets us standardize the interface for reading records that may overflow or not overflow
 
 #[derive(Clone, Debug)]
@@ -289,14 +350,87 @@ mod test {
             SerialData::I8(val) => {
                 assert!(val == 75);
             }
-            _ => assert!(false, "Expected I8"),
+            _ => panic!("Expected I8"),
         }
 
-        match record.serial_data[1] {
-            SerialData::One => {
-                assert!(true);
+        assert!(
+            matches!(record.serial_data[1], SerialData::One),
+            "Expected Serial Data One"
+        );
+    }
+
+    #[test]
+    fn test_record_errors_instead_of_panicking() {
+        // 8 bit integer with its high bit set
+        let (record, _) = Record::from_be_bytes(&[0x02, 0x01, 0xfe]).unwrap();
+        assert!(matches!(record.serial_data[0], SerialData::I8(-2)));
+
+        // header claims more bytes than the record has
+        assert!(matches!(
+            Record::from_be_bytes(&[0x05, 0x01]),
+            Err(StorageError::Corrupt { .. })
+        ));
+        // text value cut short
+        assert!(matches!(
+            Record::from_be_bytes(&[0x02, 0x17, b'a']),
+            Err(StorageError::Corrupt { .. })
+        ));
+    }
+
+    #[test]
+    fn test_record_to_be_bytes_round_trips() {
+        let record = Record::new(vec![SerialData::I64(75), SerialData::I8(1)]);
+        assert_eq!(
+            record.to_be_bytes(&SchemaFormatNumber::Fmt4),
+            vec![0x03, 0x01, 0x09, 0x4B]
+        );
+        assert_eq!(
+            record.to_be_bytes(&SchemaFormatNumber::Fmt1),
+            vec![0x03, 0x01, 0x01, 0x4B, 0x01]
+        );
+
+        let mut values = vec![
+            SerialData::Null,
+            SerialData::F64(-1.5),
+            SerialData::Text("hello".to_string()),
+            SerialData::Blob(vec![0, 1, 2]),
+            // long enough for a 2 byte serial type varint
+            SerialData::Text("x".repeat(100)),
+        ];
+        // smallest serial type each integer fits in, on both sides of every boundary
+        let boundaries = [
+            0, 1, 127, 128, 32767, 32768, 8388607, 8388608, 2147483647, 2147483648,
+        ];
+        let widths = [0, 0, 1, 2, 2, 3, 3, 4, 4, 6];
+        for (num, width) in boundaries.iter().zip(widths) {
+            for num in [*num, -*num - 1] {
+                let bytes =
+                    Record::new(vec![SerialData::I64(num)]).to_be_bytes(&SchemaFormatNumber::Fmt4);
+                let expected_width = if num == -1 || num == -2 { 1 } else { width };
+                assert_eq!(bytes.len(), 2 + expected_width, "{num}");
+                values.push(SerialData::I64(num));
             }
-            _ => assert!(false, "Expected Serial Data One"),
+        }
+        values.extend([
+            SerialData::I64(140737488355327),
+            SerialData::I64(-140737488355328),
+            SerialData::I64(140737488355328),
+            SerialData::I64(i64::MIN),
+            SerialData::I64(i64::MAX),
+        ]);
+
+        // over 128 values so the header size itself needs 2 bytes
+        values.extend((0..120).map(SerialData::I64));
+        let bytes = Record::new(values.clone()).to_be_bytes(&SchemaFormatNumber::Fmt4);
+        let (decoded, bytes_read) = Record::from_be_bytes(&bytes).unwrap();
+        assert_eq!(bytes_read as usize, bytes.len());
+        assert_eq!(decoded.serial_data.len(), values.len());
+        for (decoded, value) in decoded.serial_data.iter().zip(&values) {
+            assert_eq!(
+                decoded.sqlite_cmp(value),
+                std::cmp::Ordering::Equal,
+                "{value:?}"
+            );
         }
     }
 }
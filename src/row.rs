@@ -0,0 +1,329 @@
+use std::rc::Rc;
+
+use crate::btree::BtreeIterator;
+use crate::error::{Result, StorageError};
+use crate::record::ReadableRecord;
+use crate::sql_data_types::SerialData;
+use crate::sql_parser::{ColumnConstraint, CreateTableStmt, SqlColumn};
+
+/*
+ * A row of a table bound to the table's parsed schema, so columns can be read by name and converted to rust types
+ * instead of callers having to know the column order of the record.
+ *
+ * Values are stored the way the record format laid them out, with a few fixups applied to get back to what the
+ * table holds logically:
+ * - the INTEGER PRIMARY KEY column (if any) is stored as NULL in the record, its value is the rowid
+ * - records written before an ALTER TABLE ADD COLUMN are missing trailing columns, those take the column default
+ * - VIRTUAL generated columns are computed on read and never stored, those read as NULL
+ * - the column's affinity is applied, e.g. a REAL column hands back integers stored to save space as reals
+ */
+#[derive(Debug, Clone)]
+pub struct Row {
+    schema: Rc<CreateTableStmt>,
+    rowid: i64,
+    values: Vec<SerialData>,
+}
+
+impl Row {
+    pub fn new(schema: Rc<CreateTableStmt>, rowid: i64, record: Vec<SerialData>) -> Self {
+        let rowid_alias_column = schema.rowid_alias_column();
+        let mut stored_values = record.into_iter();
+
+        let values = schema
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(idx, column)| {
+                if is_virtual_column(column) {
+                    return SerialData::Null;
+                }
+
+                let value = stored_values
+                    .next()
+                    .unwrap_or_else(|| default_value(column));
+                if rowid_alias_column == Some(idx) {
+                    return SerialData::I64(rowid);
+                }
+                column.affinity.apply(value)
+            })
+            .collect();
+
+        Row {
+            schema,
+            rowid,
+            values,
+        }
+    }
+
+    pub fn rowid(&self) -> i64 {
+        self.rowid
+    }
+
+    pub fn schema(&self) -> &CreateTableStmt {
+        &self.schema
+    }
+
+    // values in the order the columns were declared in
+    pub fn values(&self) -> &[SerialData] {
+        &self.values
+    }
+
+    pub fn get_value(&self, column_name: &str) -> Result<&SerialData> {
+        match self.schema.column_index(column_name) {
+            Some(idx) => Ok(&self.values[idx]),
+            None => Err(StorageError::NotFound(format!(
+                "Column {column_name} in table {}",
+                self.schema.table_name
+            ))),
+        }
+    }
+
+    // e.g. row.get::<i64>("age") or row.get::<Option<String>>("name")
+    pub fn get<T: FromSqlValue>(&self, column_name: &str) -> Result<T> {
+        T::from_sql_value(self.get_value(column_name)?)
+    }
+}
+
+pub(crate) fn is_virtual_column(column: &SqlColumn) -> bool {
+    column.constraints.iter().any(|constraint| {
+        matches!(
+            constraint,
+            ColumnConstraint::Generated { stored: false, .. }
+        )
+    })
+}
+
+// only literal defaults can be resolved without an expression evaluator, anything else reads as NULL
+pub(crate) fn default_value(column: &SqlColumn) -> SerialData {
+    let default = column
+        .constraints
+        .iter()
+        .find_map(|constraint| match constraint {
+            ColumnConstraint::Default(default) => Some(default.as_str()),
+            _ => None,
+        });
+
+    match default {
+        Some(literal)
+            if literal.starts_with('\'') && literal.ends_with('\'') && literal.len() >= 2 =>
+        {
+            SerialData::Text(literal[1..literal.len() - 1].replace("''", "'"))
+        }
+        Some(literal) => match (literal.parse::<i64>(), literal.parse::<f64>()) {
+            (Ok(num), _) => SerialData::I64(num),
+            (_, Ok(num)) if num.is_finite() => SerialData::F64(num),
+            _ => SerialData::Null,
+        },
+        None => SerialData::Null,
+    }
+}
+
+// Conversion from a stored value into a rust type, the counterpart of reading a column with a given type
+pub trait FromSqlValue: Sized {
+    fn from_sql_value(value: &SerialData) -> Result<Self>;
+}
+
+impl FromSqlValue for SerialData {
+    fn from_sql_value(value: &SerialData) -> Result<Self> {
+        Ok(value.clone())
+    }
+}
+
+impl FromSqlValue for i64 {
+    fn from_sql_value(value: &SerialData) -> Result<Self> {
+        match value.as_integer() {
+            Some(num) => Ok(num),
+            None => Err(type_mismatch(value, "an integer")),
+        }
+    }
+}
+
+impl FromSqlValue for i32 {
+    fn from_sql_value(value: &SerialData) -> Result<Self> {
+        i64::from_sql_value(value)?
+            .try_into()
+            .map_err(|_| type_mismatch(value, "a 32 bit integer"))
+    }
+}
+
+impl FromSqlValue for u32 {
+    fn from_sql_value(value: &SerialData) -> Result<Self> {
+        i64::from_sql_value(value)?
+            .try_into()
+            .map_err(|_| type_mismatch(value, "an unsigned 32 bit integer"))
+    }
+}
+
+impl FromSqlValue for bool {
+    fn from_sql_value(value: &SerialData) -> Result<Self> {
+        Ok(i64::from_sql_value(value)? != 0)
+    }
+}
+
+impl FromSqlValue for f64 {
+    fn from_sql_value(value: &SerialData) -> Result<Self> {
+        match value {
+            SerialData::F64(num) => Ok(*num),
+            value => match value.as_integer() {
+                Some(num) => Ok(num as f64),
+                None => Err(type_mismatch(value, "a real")),
+            },
+        }
+    }
+}
+
+impl FromSqlValue for String {
+    fn from_sql_value(value: &SerialData) -> Result<Self> {
+        match value {
+            SerialData::Text(text) => Ok(text.clone()),
+            value => Err(type_mismatch(value, "text")),
+        }
+    }
+}
+
+impl FromSqlValue for Vec<u8> {
+    fn from_sql_value(value: &SerialData) -> Result<Self> {
+        match value {
+            SerialData::Blob(blob) => Ok(blob.clone()),
+            SerialData::Text(text) => Ok(text.as_bytes().to_vec()),
+            value => Err(type_mismatch(value, "a blob")),
+        }
+    }
+}
+
+fn type_mismatch(value: &SerialData, expected: &str) -> StorageError {
+    StorageError::TypeMismatch(format!("Cannot read {:?} as {expected}", value))
+}
+
+// NULL reads as None, anything else has to convert to T
+impl<T: FromSqlValue> FromSqlValue for Option<T> {
+    fn from_sql_value(value: &SerialData) -> Result<Self> {
+        match value {
+            SerialData::Null => Ok(None),
+            value => Ok(Some(T::from_sql_value(value)?)),
+        }
+    }
+}
+
+// Streams the rows of a table btree in rowid order as Rows bound to the table's schema
+pub struct Rows {
+    schema: Rc<CreateTableStmt>,
+    cursor: BtreeIterator,
+}
+
+impl Rows {
+    pub fn new(schema: Rc<CreateTableStmt>, cursor: BtreeIterator) -> Self {
+        Rows { schema, cursor }
+    }
+
+    fn read_row(&mut self, record: Result<ReadableRecord>) -> Result<Row> {
+        let record = record?.read_record()?;
+        let rowid = match self.cursor.current_rowid()? {
+            Some(rowid) => rowid,
+            None => {
+                return Err(StorageError::InvalidOperation(
+                    "Rows can only be read from table btrees".to_string(),
+                ))
+            }
+        };
+        Ok(Row::new(self.schema.clone(), rowid, record))
+    }
+}
+
+impl Iterator for Rows {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = self.cursor.next()?;
+        Some(self.read_row(record))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql_parser::parse_create_table_stmt;
+
+    fn schema(stmt: &str) -> Rc<CreateTableStmt> {
+        Rc::new(parse_create_table_stmt(stmt).unwrap())
+    }
+
+    #[test]
+    fn test_get_typed_values_by_name() {
+        let schema = schema("CREATE TABLE people (name TEXT, age INTEGER, score REAL, photo BLOB)");
+        let row = Row::new(
+            schema,
+            7,
+            vec![
+                SerialData::Text("ada".to_string()),
+                SerialData::I8(36),
+                SerialData::I16(12),
+                SerialData::Null,
+            ],
+        );
+
+        assert_eq!(row.get::<String>("name").unwrap(), "ada");
+        assert_eq!(row.get::<i64>("AGE").unwrap(), 36);
+        assert_eq!(row.get::<i32>("age").unwrap(), 36);
+        // REAL affinity reads integers stored to save space back as reals
+        assert_eq!(row.get::<f64>("score").unwrap(), 12.0);
+        assert_eq!(row.get::<Option<Vec<u8>>>("photo").unwrap(), None);
+        assert_eq!(
+            row.get::<Option<String>>("name").unwrap().as_deref(),
+            Some("ada")
+        );
+        assert!(row.get::<i64>("name").is_err());
+        assert!(row.get::<String>("photo").is_err());
+        assert!(row.get::<i64>("missing").is_err());
+    }
+
+    #[test]
+    fn test_integer_primary_key_reads_the_rowid() {
+        let schema = schema("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)");
+        let row = Row::new(
+            schema,
+            42,
+            vec![SerialData::Null, SerialData::Text("x".to_string())],
+        );
+        assert_eq!(row.get::<i64>("id").unwrap(), 42);
+        assert_eq!(row.rowid(), 42);
+
+        let table_constraint = Rc::new(
+            parse_create_table_stmt("CREATE TABLE t (id integer, name TEXT, PRIMARY KEY (id))")
+                .unwrap(),
+        );
+        assert_eq!(table_constraint.rowid_alias_column(), Some(0));
+
+        // neither of these alias the rowid
+        for stmt in [
+            "CREATE TABLE t (id INT PRIMARY KEY, name TEXT)",
+            "CREATE TABLE t (id INTEGER PRIMARY KEY DESC, name TEXT)",
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT) WITHOUT ROWID",
+            "CREATE TABLE t (id INTEGER, name TEXT, PRIMARY KEY (id, name))",
+        ] {
+            assert_eq!(
+                parse_create_table_stmt(stmt).unwrap().rowid_alias_column(),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn test_missing_and_virtual_columns() {
+        let schema = schema(
+            "CREATE TABLE t (a INTEGER, doubled INTEGER AS (a * 2), b TEXT, c INTEGER DEFAULT 5, d TEXT DEFAULT 'it''s')",
+        );
+        // the record only has the stored columns, and was written before c and d were added
+        let row = Row::new(
+            schema,
+            1,
+            vec![SerialData::I8(3), SerialData::Text("b".to_string())],
+        );
+
+        assert_eq!(row.get::<i64>("a").unwrap(), 3);
+        assert_eq!(row.get::<Option<i64>>("doubled").unwrap(), None);
+        assert_eq!(row.get::<String>("b").unwrap(), "b");
+        assert_eq!(row.get::<i64>("c").unwrap(), 5);
+        assert_eq!(row.get::<String>("d").unwrap(), "it's");
+    }
+}
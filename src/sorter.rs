@@ -0,0 +1,275 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+use crate::database::SchemaFormatNumber;
+use crate::error::{Result, StorageError};
+use crate::record::Record;
+use crate::sql_data_types::{compare_records, SerialData};
+
+// how much memory the entries being sorted may take up before they are written out as a sorted run
+pub(crate) const SORT_MEMORY_BUDGET: usize = 64 * 1024 * 1024;
+
+// tells apart the run files of every sort going on in the process
+static NEXT_RUN_ID: AtomicUsize = AtomicUsize::new(0);
+
+/*
+ * Sorts records of any size into SQLite's key order, for building an index over a table whose keys do not fit in
+ * memory. Entries are gathered in memory until they take up the budget, then sorted and written out as a run to a
+ * temporary file. Once every entry is in, the runs are merged back together in a single pass, so each entry is
+ * written and read back at most once. A sort that stays within the budget never touches the disk.
+ */
+pub(crate) struct Sorter {
+    memory_budget: usize,
+    entries: Vec<Vec<SerialData>>,
+    memory_used: usize,
+    runs: Vec<Run>,
+}
+
+impl Sorter {
+    pub fn new(memory_budget: usize) -> Self {
+        Sorter {
+            memory_budget,
+            entries: Vec::new(),
+            memory_used: 0,
+            runs: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, entry: Vec<SerialData>) -> Result<()> {
+        self.memory_used += entry_size(&entry);
+        self.entries.push(entry);
+        if self.memory_used >= self.memory_budget {
+            self.write_run()?;
+        }
+        Ok(())
+    }
+
+    // every entry pushed, in key order
+    pub fn finish(mut self) -> Result<SortedEntries> {
+        if self.runs.is_empty() {
+            self.entries.sort_by(|a, b| compare_records(a, b));
+            return Ok(SortedEntries::InMemory(
+                std::mem::take(&mut self.entries).into_iter(),
+            ));
+        }
+
+        if !self.entries.is_empty() {
+            self.write_run()?;
+        }
+        let mut runs = std::mem::take(&mut self.runs);
+        let mut heads = BinaryHeap::new();
+        for (run_idx, run) in runs.iter_mut().enumerate() {
+            if let Some(entry) = run.next_entry()? {
+                heads.push(Reverse(RunHead { entry, run_idx }));
+            }
+        }
+        Ok(SortedEntries::Merged { runs, heads })
+    }
+
+    fn write_run(&mut self) -> Result<()> {
+        self.entries.sort_by(|a, b| compare_records(a, b));
+        let mut run = Run::create()?;
+        {
+            let mut writer = BufWriter::new(&run.file);
+            for entry in self.entries.drain(..) {
+                // the runs only live as long as the sort, so they can always use the newest record format
+                let bytes = Record::new(entry).to_be_bytes(&SchemaFormatNumber::Fmt4);
+                writer.write_all(&(bytes.len() as u32).to_be_bytes())?;
+                writer.write_all(&bytes)?;
+            }
+            writer.flush()?;
+        }
+        run.start_reading()?;
+        self.runs.push(run);
+        self.memory_used = 0;
+        Ok(())
+    }
+}
+
+pub(crate) enum SortedEntries {
+    InMemory(std::vec::IntoIter<Vec<SerialData>>),
+    Merged {
+        runs: Vec<Run>,
+        heads: BinaryHeap<Reverse<RunHead>>,
+    },
+}
+
+impl Iterator for SortedEntries {
+    type Item = Result<Vec<SerialData>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            SortedEntries::InMemory(entries) => entries.next().map(Ok),
+            SortedEntries::Merged { runs, heads } => {
+                let Reverse(RunHead { entry, run_idx }) = heads.pop()?;
+                match runs[run_idx].next_entry() {
+                    Ok(Some(next)) => heads.push(Reverse(RunHead {
+                        entry: next,
+                        run_idx,
+                    })),
+                    Ok(None) => {}
+                    Err(err) => return Some(Err(err)),
+                }
+                Some(Ok(entry))
+            }
+        }
+    }
+}
+
+// the smallest entry of a run not merged yet, ties between runs go to the earlier run
+pub(crate) struct RunHead {
+    entry: Vec<SerialData>,
+    run_idx: usize,
+}
+
+impl Ord for RunHead {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_records(&self.entry, &other.entry).then(self.run_idx.cmp(&other.run_idx))
+    }
+}
+
+impl PartialOrd for RunHead {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for RunHead {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for RunHead {}
+
+// a sorted run in a temporary file, each entry a 4 byte length followed by the entry as a record
+pub(crate) struct Run {
+    path: PathBuf,
+    file: File,
+    reader: Option<BufReader<File>>,
+}
+
+impl Run {
+    fn create() -> Result<Self> {
+        let run_id = NEXT_RUN_ID.fetch_add(1, AtomicOrdering::Relaxed);
+        let path = std::env::temp_dir().join(format!(
+            "sqlite-rust-sort-{}-{run_id}.tmp",
+            std::process::id()
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(Run {
+            path,
+            file,
+            reader: None,
+        })
+    }
+
+    fn start_reading(&mut self) -> Result<()> {
+        let mut file = self.file.try_clone()?;
+        std::io::Seek::rewind(&mut file)?;
+        self.reader = Some(BufReader::new(file));
+        Ok(())
+    }
+
+    fn next_entry(&mut self) -> Result<Option<Vec<SerialData>>> {
+        let reader = match self.reader.as_mut() {
+            Some(reader) => reader,
+            None => return Ok(None),
+        };
+
+        let mut len = [0; 4];
+        match reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+        let mut bytes = vec![0; u32::from_be_bytes(len) as usize];
+        reader.read_exact(&mut bytes)?;
+        let (record, bytes_read) = Record::from_be_bytes(&bytes)?;
+        if bytes_read as usize != bytes.len() {
+            return Err(StorageError::corrupt(format!(
+                "sort run {} holds a record of {bytes_read} bytes in {} bytes",
+                self.path.display(),
+                bytes.len()
+            )));
+        }
+        Ok(Some(record.into_serial_data()))
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// roughly what an entry takes up in memory, the values themselves and whatever text or blob they point at
+fn entry_size(entry: &[SerialData]) -> usize {
+    let heap_bytes: usize = entry
+        .iter()
+        .map(|value| match value {
+            SerialData::Text(text) => text.len(),
+            SerialData::Blob(blob) => blob.len(),
+            _ => 0,
+        })
+        .sum();
+    std::mem::size_of::<Vec<SerialData>>() + std::mem::size_of_val(entry) + heap_bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runs_spilled_to_disk_merge_back_in_order() {
+        let entry = |n: i64| {
+            vec![
+                SerialData::Text(format!("{:03}", n % 250)),
+                SerialData::I64(n),
+            ]
+        };
+        let numbers = (0..2000).map(|i| (i * 7919) % 2000).collect::<Vec<i64>>();
+
+        // a budget this small writes a run every few entries
+        let mut sorter = Sorter::new(2000);
+        for n in &numbers {
+            sorter.push(entry(*n)).unwrap();
+        }
+        assert!(sorter.runs.len() > 10);
+        let paths = sorter
+            .runs
+            .iter()
+            .map(|run| run.path.clone())
+            .collect::<Vec<_>>();
+
+        let sorted = sorter.finish().unwrap();
+        assert!(matches!(sorted, SortedEntries::Merged { .. }));
+        let sorted = sorted.collect::<Result<Vec<_>>>().unwrap();
+        let mut expected = numbers.iter().map(|n| entry(*n)).collect::<Vec<_>>();
+        expected.sort_by(|a, b| compare_records(a, b));
+        assert_eq!(sorted.len(), expected.len());
+        assert!(sorted
+            .iter()
+            .zip(&expected)
+            .all(|(a, b)| compare_records(a, b) == Ordering::Equal));
+        assert!(paths.iter().all(|path| !path.exists()));
+
+        let mut sorter = Sorter::new(SORT_MEMORY_BUDGET);
+        sorter.push(entry(2)).unwrap();
+        sorter.push(entry(1)).unwrap();
+        let sorted = sorter
+            .finish()
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(sorted[0][1].as_integer(), Some(1));
+    }
+}
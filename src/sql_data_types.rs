@@ -1,4 +1,6 @@
-use anyhow::bail;
+use std::cmp::Ordering;
+
+use crate::error::{Result, StorageError};
 
 /*
 A variable-length integer or "varint" is a static Huffman encoding of 64-bit
@@ -20,14 +22,12 @@ pub struct VarInt(pub i64, pub u8);
 pub enum VarIntError {
     Empty,
     Incomplete,
-    TooLong,
 }
 
 impl std::fmt::Display for VarIntError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             VarIntError::Empty => write!(f, "VarInt cannot be empty"),
-            VarIntError::TooLong => write!(f, "VarInt cannot exceed 9 bytes"),
             VarIntError::Incomplete => write!(f, "VarInt incomplete representation"),
         }
     }
@@ -39,7 +39,7 @@ impl VarInt {
     // read from big endian bytes into a i64
     pub fn from_be_bytes(bytes: &[u8]) -> Result<Self, VarIntError> {
         if bytes.is_empty() {
-            return Err(VarIntError::Empty.into());
+            return Err(VarIntError::Empty);
         }
 
         /*
@@ -49,21 +49,24 @@ impl VarInt {
          * "value" is used to accumulate all the bits read from the bytes and then casted to i64.
          * we read the big endian bytes left to right, and make space in value by shifting to the left 7 bytes each time,
          * and then adding the next byte to the right of it.
+         * A varint never goes past 9 bytes, so the 9th byte has no continuation bit and all 8 of its bits are value.
          */
         let mut bytes_used_to_encode = 0;
-        let mut value = 0;
+        let mut value: u64 = 0;
         let mut complete_repr = false;
         for byte in bytes {
-            // Remove the high bit and add the lower 7 bits to the value
-            // The high bit is removed by AND'ing with 0b0111_1111 (0x7F)
-            value = (value << 7) | (byte & 0b0111_1111) as i64;
             bytes_used_to_encode += 1;
 
-            // we cannot have more than 9 bytes to encode a VarInt
-            if (bytes_used_to_encode == 9) && (byte & 0b1000_0000) == 1 {
-                return Err(VarIntError::TooLong.into());
+            if bytes_used_to_encode == 9 {
+                value = (value << 8) | *byte as u64;
+                complete_repr = true;
+                break;
             }
 
+            // Remove the high bit and add the lower 7 bits to the value
+            // The high bit is removed by AND'ing with 0b0111_1111 (0x7F)
+            value = (value << 7) | (byte & 0b0111_1111) as u64;
+
             // If the high bit is not set, this is the last byte
             if (byte & 0b1000_0000) == 0 {
                 complete_repr = true;
@@ -72,10 +75,41 @@ impl VarInt {
         }
 
         if !complete_repr {
-            return Err(VarIntError::Incomplete.into());
+            return Err(VarIntError::Incomplete);
         }
 
-        Ok(VarInt(value, bytes_used_to_encode))
+        Ok(VarInt(value as i64, bytes_used_to_encode))
+    }
+
+    /*
+     * The inverse of from_be_bytes, returns the encoded bytes and how many of them are used. Negative numbers are
+     * twos-complement so they always have the high bits set and take the full 9 bytes, the 9th byte carries 8 bits
+     * which is what lets 8 * 7 + 8 = 64 bits fit.
+     */
+    pub fn encode(value: i64) -> ([u8; 9], usize) {
+        let mut bytes = [0u8; 9];
+        let value = value as u64;
+
+        if value >> 56 != 0 {
+            bytes[8] = value as u8;
+            let rest = value >> 8;
+            for (i, byte) in bytes[..8].iter_mut().enumerate() {
+                *byte = ((rest >> (7 * (7 - i))) & 0b0111_1111) as u8 | 0b1000_0000;
+            }
+            return (bytes, 9);
+        }
+
+        let mut len = 1;
+        while value >> (7 * len) != 0 {
+            len += 1;
+        }
+        for (i, byte) in bytes[..len].iter_mut().enumerate() {
+            *byte = ((value >> (7 * (len - 1 - i))) & 0b0111_1111) as u8;
+            if i < len - 1 {
+                *byte |= 0b1000_0000;
+            }
+        }
+        (bytes, len)
     }
 }
 
@@ -116,7 +150,7 @@ pub enum SerialType {
 }
 
 impl SerialType {
-    pub fn from_varint(varint: VarInt) -> anyhow::Result<Self> {
+    pub fn from_varint(varint: VarInt) -> Result<Self> {
         let serial_type = match varint.0 {
             0 => SerialType::Null,
             1 => SerialType::I8,
@@ -137,7 +171,7 @@ impl SerialType {
                 let size = (n - 13) / 2;
                 SerialType::Text(size)
             }
-            _ => bail!("Invalid serial type"),
+            n => return Err(StorageError::corrupt(format!("Invalid serial type {n}"))),
         };
 
         Ok(serial_type)
@@ -161,9 +195,267 @@ pub enum SerialData {
     Text(String),
 }
 
+/*
+SQLite orders values by storage class first and only then by value:
+NULL values sort before INTEGER and REAL values, which sort before TEXT, which sort before BLOB.
+INTEGER and REAL values are compared numerically with each other, TEXT is compared with the BINARY collating
+sequence (memcmp over the utf-8 bytes) and BLOBs are compared with memcmp, the shorter one winning ties on a common prefix.
+*/
+impl SerialData {
+    // values written as SQL literals separated by commas, e.g. 1, 'Alice', NULL, x'00ff'
+    pub fn parse_list(input: &str) -> Result<Vec<SerialData>> {
+        crate::sql_parser::parse_literal_list(input)
+    }
+
+    // integer value of any of the integer serial types, including the schema format 4 constants 0 and 1
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            SerialData::I8(num) => Some(i64::from(*num)),
+            SerialData::I16(num) => Some(i64::from(*num)),
+            SerialData::I24(num) | SerialData::I32(num) => Some(i64::from(*num)),
+            SerialData::I48(num) | SerialData::I64(num) => Some(*num),
+            SerialData::Zero => Some(0),
+            SerialData::One => Some(1),
+            _ => None,
+        }
+    }
+
+    // rank of the storage class in SQLite's sort order
+    fn storage_class_rank(&self) -> u8 {
+        match self {
+            SerialData::Null | SerialData::Reserved => 0,
+            SerialData::Text(_) => 2,
+            SerialData::Blob(_) => 3,
+            _ => 1,
+        }
+    }
+
+    pub fn sqlite_cmp(&self, other: &SerialData) -> Ordering {
+        let rank_ordering = self.storage_class_rank().cmp(&other.storage_class_rank());
+        if rank_ordering != Ordering::Equal {
+            return rank_ordering;
+        }
+
+        match (self, other) {
+            (SerialData::Text(a), SerialData::Text(b)) => a.as_bytes().cmp(b.as_bytes()),
+            (SerialData::Blob(a), SerialData::Blob(b)) => a.cmp(b),
+            (SerialData::F64(a), SerialData::F64(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+            (SerialData::F64(a), b) => match b.as_integer() {
+                Some(b) => compare_integer_to_float(b, *a).reverse(),
+                None => Ordering::Equal,
+            },
+            (a, SerialData::F64(b)) => match a.as_integer() {
+                Some(a) => compare_integer_to_float(a, *b),
+                None => Ordering::Equal,
+            },
+            (a, b) => match (a.as_integer(), b.as_integer()) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                // both NULL
+                _ => Ordering::Equal,
+            },
+        }
+    }
+}
+
+// renders a value the way the sqlite3 shell prints it in list mode, NULL is printed as nothing
+impl std::fmt::Display for SerialData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SerialData::Null | SerialData::Reserved => Ok(()),
+            SerialData::F64(num) => write!(f, "{}", format_real(*num)),
+            SerialData::Text(text) => write!(f, "{text}"),
+            SerialData::Blob(blob) => write!(f, "{}", String::from_utf8_lossy(blob)),
+            value => match value.as_integer() {
+                Some(num) => write!(f, "{num}"),
+                None => Ok(()),
+            },
+        }
+    }
+}
+
+// Mirrors sqlite3IntFloatCompare, an f64 cannot represent every i64 so a plain cast would lose precision
+fn compare_integer_to_float(integer: i64, float: f64) -> Ordering {
+    if float.is_nan() {
+        // SQLite never stores NaN, it becomes NULL which sorts first
+        return Ordering::Greater;
+    }
+    if float < -9223372036854775808.0 {
+        return Ordering::Greater;
+    }
+    if float >= 9223372036854775808.0 {
+        return Ordering::Less;
+    }
+
+    let truncated = float as i64;
+    match integer.cmp(&truncated) {
+        Ordering::Equal => (integer as f64)
+            .partial_cmp(&float)
+            .unwrap_or(Ordering::Equal),
+        ordering => ordering,
+    }
+}
+
+// compares two records column by column, a record that runs out of columns first sorts first
+pub fn compare_records(a: &[SerialData], b: &[SerialData]) -> Ordering {
+    for (a, b) in a.iter().zip(b.iter()) {
+        let ordering = a.sqlite_cmp(b);
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+
+    a.len().cmp(&b.len())
+}
+
+/*
+Type Affinity
+A declared column type does not pin down how values are stored, each value carries its own storage class.
+Instead the declared type gives the column an affinity, the storage class SQLite prefers for it. The affinity of a
+column is determined by the first of these rules to match its declared type:
+
+1. If the declared type contains the string "INT" then it is assigned INTEGER affinity.
+2. If the declared type of the column contains any of the strings "CHAR", "CLOB", or "TEXT" then that column has TEXT affinity.
+3. If the declared type for a column contains the string "BLOB" or if no type is specified then the column has affinity BLOB.
+4. If the declared type for a column contains any of the strings "REAL", "FLOA", or "DOUB" then the column has REAL affinity.
+5. Otherwise, the affinity is NUMERIC.
+
+A column with TEXT affinity converts numeric values to text before storing them.
+A column with NUMERIC affinity converts text that is a well-formed integer or real literal into an INTEGER or REAL,
+and a REAL that can be expressed as an integer without loss into an INTEGER.
+A column that uses INTEGER affinity behaves the same as a column with NUMERIC affinity.
+A column with REAL affinity behaves like NUMERIC except that it forces integer values into floating point.
+A column with BLOB affinity does not prefer one storage class over another.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Affinity {
+    Integer,
+    Text,
+    Blob,
+    Real,
+    Numeric,
+}
+
+impl Affinity {
+    pub fn from_declared_type(declared_type: Option<&str>) -> Self {
+        let declared_type = declared_type.unwrap_or_default().to_uppercase();
+        let contains_any = |names: &[&str]| names.iter().any(|name| declared_type.contains(name));
+
+        if declared_type.contains("INT") {
+            Affinity::Integer
+        } else if contains_any(&["CHAR", "CLOB", "TEXT"]) {
+            Affinity::Text
+        } else if declared_type.contains("BLOB") || declared_type.is_empty() {
+            Affinity::Blob
+        } else if contains_any(&["REAL", "FLOA", "DOUB"]) {
+            Affinity::Real
+        } else {
+            Affinity::Numeric
+        }
+    }
+
+    // converts a value to the storage class the column prefers, when that can be done without losing information
+    pub fn apply(&self, value: SerialData) -> SerialData {
+        match self {
+            Affinity::Blob => value,
+            Affinity::Text => match value {
+                SerialData::F64(num) => SerialData::Text(format_real(num)),
+                value => match value.as_integer() {
+                    Some(num) => SerialData::Text(num.to_string()),
+                    None => value,
+                },
+            },
+            Affinity::Integer | Affinity::Numeric => match value {
+                SerialData::Text(text) => match parse_numeric_text(&text) {
+                    Some(SerialData::F64(num)) => real_to_integer_if_lossless(num),
+                    Some(num) => num,
+                    None => SerialData::Text(text),
+                },
+                SerialData::F64(num) => real_to_integer_if_lossless(num),
+                value => value,
+            },
+            Affinity::Real => {
+                let value = match value {
+                    SerialData::Text(text) => {
+                        parse_numeric_text(&text).unwrap_or(SerialData::Text(text))
+                    }
+                    value => value,
+                };
+                match value.as_integer() {
+                    Some(num) => SerialData::F64(num as f64),
+                    None => value,
+                }
+            }
+        }
+    }
+}
+
+// a text value that is a well-formed integer or real literal (surrounding spaces allowed) as an I64 or F64
+fn parse_numeric_text(text: &str) -> Option<SerialData> {
+    let text = text.trim();
+    let digits = text.strip_prefix(['+', '-']).unwrap_or(text);
+    if !digits.starts_with(|c: char| c.is_ascii_digit() || c == '.')
+        || !digits
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-'))
+    {
+        return None;
+    }
+
+    if let Ok(num) = text.parse::<i64>() {
+        return Some(SerialData::I64(num));
+    }
+    // integer literals too large for an i64 become reals, same as SQLite
+    text.parse::<f64>().ok().map(SerialData::F64)
+}
+
+// mirrors sqlite3RealSameAsInt, only reals well within the exactly representable range are turned into integers
+fn real_to_integer_if_lossless(num: f64) -> SerialData {
+    const EXACT_INTEGER_LIMIT: f64 = 2251799813685248.0; // 2^51
+    if num.fract() == 0.0 && num > -EXACT_INTEGER_LIMIT && num < EXACT_INTEGER_LIMIT {
+        SerialData::I64(num as i64)
+    } else {
+        SerialData::F64(num)
+    }
+}
+
+// renders a real the way SQLite's "%!.15g" does, e.g. 100.0, 1.5 or 1.0e+20
+pub fn format_real(num: f64) -> String {
+    if num.is_nan() {
+        return "NaN".to_string();
+    }
+    if num.is_infinite() {
+        return if num > 0.0 { "Inf" } else { "-Inf" }.to_string();
+    }
+
+    // keeps a fractional part on everything, 100 is printed as 100.0
+    let trim_fraction = |digits: &str| {
+        let mut digits = digits.to_string();
+        if digits.contains('.') {
+            digits.truncate(digits.trim_end_matches('0').len());
+        }
+        if digits.ends_with('.') || !digits.contains('.') {
+            digits.push_str(if digits.ends_with('.') { "0" } else { ".0" });
+        }
+        digits
+    };
+
+    // 15 significant digits in scientific notation tells us the exponent after rounding
+    let scientific = format!("{:.14e}", num);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+
+    if !(-4..15).contains(&exponent) {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{sign}{:02}", trim_fraction(mantissa), exponent.abs())
+    } else {
+        trim_fraction(&format!("{:.*}", (14 - exponent) as usize, num))
+    }
+}
+
 #[derive(Debug)]
 pub enum SerialDataError {
     OutOfBounds,
+    InvalidText,
 }
 
 impl std::error::Error for SerialDataError {}
@@ -172,77 +464,66 @@ impl std::fmt::Display for SerialDataError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             SerialDataError::OutOfBounds => write!(f, "Out of bounds error"),
+            SerialDataError::InvalidText => write!(f, "Text value is not valid utf-8"),
         }
     }
 }
 
 impl SerialType {
-    pub fn serial_type_to_serial_data(&self, body: &[u8]) -> anyhow::Result<(SerialData, usize)> {
+    pub fn serial_type_to_serial_data(
+        &self,
+        body: &[u8],
+    ) -> Result<(SerialData, usize), SerialDataError> {
         match self {
             SerialType::Null => Ok((SerialData::Null, 0)),
             SerialType::Zero => Ok((SerialData::Zero, 0)),
             SerialType::One => Ok((SerialData::One, 0)),
             SerialType::Reserved => Ok((SerialData::Reserved, 0)),
-            SerialType::I8 => {
-                let data = body[0].try_into()?;
-                Ok((SerialData::I8(data), 1))
-            }
+            SerialType::I8 => match body.first() {
+                Some(byte) => Ok((SerialData::I8(*byte as i8), 1)),
+                None => Err(SerialDataError::OutOfBounds),
+            },
             SerialType::I16 => {
-                if 2 > body.len() {
-                    return Err(SerialDataError::OutOfBounds.into());
-                }
-
-                let data = i16::from_be_bytes(body[..2].try_into()?);
+                let data = i16::from_be_bytes(fixed_width(body)?);
                 Ok((SerialData::I16(data), 2))
             }
             SerialType::I24 => {
                 if 3 > body.len() {
-                    return Err(SerialDataError::OutOfBounds.into());
+                    return Err(SerialDataError::OutOfBounds);
                 }
 
+                // the value goes in the high bytes and is shifted back down so the sign bit carries over
                 let mut _32_byte_repr_of_24_byte: [u8; 4] = [0; 4];
-                _32_byte_repr_of_24_byte[1..4].copy_from_slice(&body[..3]);
-                let data = i32::from_be_bytes(_32_byte_repr_of_24_byte);
+                _32_byte_repr_of_24_byte[..3].copy_from_slice(&body[..3]);
+                let data = i32::from_be_bytes(_32_byte_repr_of_24_byte) >> 8;
                 Ok((SerialData::I24(data), 3))
             }
             SerialType::I32 => {
-                if 4 > body.len() {
-                    return Err(SerialDataError::OutOfBounds.into());
-                }
-
-                let data = i32::from_be_bytes(body[..4].try_into()?);
+                let data = i32::from_be_bytes(fixed_width(body)?);
                 Ok((SerialData::I32(data), 4))
             }
             SerialType::I48 => {
                 if 6 > body.len() {
-                    return Err(SerialDataError::OutOfBounds.into());
+                    return Err(SerialDataError::OutOfBounds);
                 }
 
                 let mut _64_byte_repr_of_48_byte: [u8; 8] = [0; 8];
-                _64_byte_repr_of_48_byte[2..8].copy_from_slice(&body[..6]);
-                let data = i64::from_be_bytes(_64_byte_repr_of_48_byte);
+                _64_byte_repr_of_48_byte[..6].copy_from_slice(&body[..6]);
+                let data = i64::from_be_bytes(_64_byte_repr_of_48_byte) >> 16;
                 Ok((SerialData::I48(data), 6))
             }
             SerialType::I64 => {
-                if 8 > body.len() {
-                    return Err(SerialDataError::OutOfBounds.into());
-                }
-
-                let data = i64::from_be_bytes(body[..8].try_into()?);
+                let data = i64::from_be_bytes(fixed_width(body)?);
                 Ok((SerialData::I64(data), 8))
             }
             SerialType::F64 => {
-                if 8 > body.len() {
-                    return Err(SerialDataError::OutOfBounds.into());
-                }
-
-                let data = f64::from_be_bytes(body[..8].try_into()?);
+                let data = f64::from_be_bytes(fixed_width(body)?);
                 Ok((SerialData::F64(data), 8))
             }
             SerialType::Blob(size) => {
                 let end_offset = *size as usize;
                 if end_offset > body.len() {
-                    return Err(SerialDataError::OutOfBounds.into());
+                    return Err(SerialDataError::OutOfBounds);
                 }
 
                 let blob = body[..end_offset].to_vec();
@@ -251,10 +532,11 @@ impl SerialType {
             SerialType::Text(size) => {
                 let end_offset = *size as usize;
                 if end_offset > body.len() {
-                    return Err(SerialDataError::OutOfBounds.into());
+                    return Err(SerialDataError::OutOfBounds);
                 }
 
-                let text = String::from_utf8(body[..end_offset].to_vec())?;
+                let text = String::from_utf8(body[..end_offset].to_vec())
+                    .map_err(|_| SerialDataError::InvalidText)?;
 
                 Ok((SerialData::Text(text), *size as usize))
             }
@@ -262,6 +544,53 @@ impl SerialType {
     }
 }
 
+impl SerialData {
+    /*
+     * The serial type a value is written with and the bytes of its body, the inverse of serial_type_to_serial_data.
+     * Integers always get the smallest serial type that holds them no matter which variant they come in, so a value
+     * is stored the same way whichever way it was produced. The 0 and 1 constants need schema format 4.
+     */
+    pub fn to_serial_type_and_bytes(&self, integer_constants: bool) -> (i64, Vec<u8>) {
+        match self {
+            SerialData::Null | SerialData::Reserved => (0, Vec::new()),
+            SerialData::F64(num) => (7, num.to_be_bytes().to_vec()),
+            SerialData::Blob(blob) => (blob.len() as i64 * 2 + 12, blob.clone()),
+            SerialData::Text(text) => (text.len() as i64 * 2 + 13, text.as_bytes().to_vec()),
+            value => {
+                let num = value.as_integer().unwrap_or_default();
+                match num {
+                    0 if integer_constants => (8, Vec::new()),
+                    1 if integer_constants => (9, Vec::new()),
+                    _ => {
+                        // width in bytes of the smallest twos-complement integer that holds the value
+                        let (serial_type, width) = match num {
+                            -0x80..=0x7f => (1, 1),
+                            -0x8000..=0x7fff => (2, 2),
+                            -0x80_0000..=0x7f_ffff => (3, 3),
+                            -0x8000_0000..=0x7fff_ffff => (4, 4),
+                            -0x8000_0000_0000..=0x7fff_ffff_ffff => (5, 6),
+                            _ => (6, 8),
+                        };
+                        (serial_type, num.to_be_bytes()[8 - width..].to_vec())
+                    }
+                }
+            }
+        }
+    }
+}
+
+// the first N bytes of a value body
+fn fixed_width<const N: usize>(body: &[u8]) -> Result<[u8; N], SerialDataError> {
+    match body.get(..N) {
+        Some(bytes) => {
+            let mut fixed = [0; N];
+            fixed.copy_from_slice(bytes);
+            Ok(fixed)
+        }
+        None => Err(SerialDataError::OutOfBounds),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -289,4 +618,198 @@ mod tests {
         assert_eq!(varint.0, 129);
         assert_eq!(varint.1, 2);
     }
+
+    // encode followed by decode is the identity and always uses the fewest bytes
+    fn assert_varint_round_trips(value: i64) {
+        let (bytes, len) = VarInt::encode(value);
+        let decoded = VarInt::from_be_bytes(&bytes[..len]).unwrap();
+        assert_eq!(decoded.0, value);
+        assert_eq!(decoded.1 as usize, len);
+
+        let unsigned = value as u64;
+        let expected_len = (1..=8).find(|n| unsigned >> (7 * n) == 0).unwrap_or(9);
+        assert_eq!(len, expected_len, "{value}");
+    }
+
+    #[test]
+    fn test_varint_encode_round_trips() {
+        for value in [
+            0,
+            1,
+            127,
+            128,
+            16383,
+            16384,
+            1 << 56,
+            (1 << 56) - 1,
+            -1,
+            -128,
+            i64::MIN,
+            i64::MAX,
+        ] {
+            assert_varint_round_trips(value);
+        }
+        // either side of every power of two, positive and negative
+        for shift in 0..63 {
+            let power = 1i64 << shift;
+            for value in [power - 1, power, power + 1] {
+                assert_varint_round_trips(value);
+                assert_varint_round_trips(-value);
+            }
+        }
+        // a deterministic xorshift stream standing in for random values
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        for _ in 0..100_000 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            assert_varint_round_trips(state as i64);
+            // and the same value at every magnitude
+            assert_varint_round_trips((state >> (state % 64)) as i64);
+        }
+
+        // the 9th byte keeps all 8 bits
+        assert_eq!(VarInt::encode(-1), ([0xff; 9], 9));
+        assert_eq!(
+            VarInt::encode(1 << 56),
+            ([0x80, 0xc0, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00], 9)
+        );
+        assert_eq!(VarInt::from_be_bytes(&[0xff; 9]).unwrap().0, -1);
+        assert!(matches!(
+            VarInt::from_be_bytes(&[0xff; 8]),
+            Err(VarIntError::Incomplete)
+        ));
+    }
+
+    #[test]
+    fn test_sqlite_cmp_orders_storage_classes() {
+        let ascending = [
+            SerialData::Null,
+            SerialData::I8(-3),
+            SerialData::F64(-2.5),
+            SerialData::Zero,
+            SerialData::One,
+            SerialData::F64(1.5),
+            SerialData::I64(i64::MAX),
+            SerialData::Text("A".to_string()),
+            SerialData::Text("a".to_string()),
+            SerialData::Text("ab".to_string()),
+            SerialData::Blob(vec![0x00]),
+            SerialData::Blob(vec![0x00, 0x01]),
+            SerialData::Blob(vec![0x01]),
+        ];
+
+        for window in ascending.windows(2) {
+            assert_eq!(window[0].sqlite_cmp(&window[1]), Ordering::Less);
+            assert_eq!(window[1].sqlite_cmp(&window[0]), Ordering::Greater);
+        }
+    }
+
+    #[test]
+    fn test_sqlite_cmp_numeric_across_serial_types() {
+        assert_eq!(
+            SerialData::I8(1).sqlite_cmp(&SerialData::One),
+            Ordering::Equal
+        );
+        assert_eq!(
+            SerialData::I16(300).sqlite_cmp(&SerialData::I48(300)),
+            Ordering::Equal
+        );
+        assert_eq!(
+            SerialData::I32(2).sqlite_cmp(&SerialData::F64(2.0)),
+            Ordering::Equal
+        );
+        assert_eq!(
+            SerialData::F64(2.5).sqlite_cmp(&SerialData::I24(2)),
+            Ordering::Greater
+        );
+        // 2^53 + 1 is not representable as an f64 but must still sort after 2^53
+        assert_eq!(
+            SerialData::I64(9007199254740993).sqlite_cmp(&SerialData::F64(9007199254740992.0)),
+            Ordering::Greater
+        );
+        assert_eq!(
+            SerialData::Null.sqlite_cmp(&SerialData::Null),
+            Ordering::Equal
+        );
+    }
+
+    #[test]
+    fn test_compare_records_uses_prefix_then_length() {
+        let short = [SerialData::Text("abc".to_string())];
+        let long = [SerialData::Text("abc".to_string()), SerialData::I8(4)];
+        assert_eq!(compare_records(&short, &long), Ordering::Less);
+        assert_eq!(compare_records(&long, &long), Ordering::Equal);
+        assert_eq!(
+            compare_records(&[SerialData::I8(5)], &[SerialData::Text("0".to_string())]),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn test_affinity_from_declared_type() {
+        let cases = [
+            (Some("INT"), Affinity::Integer),
+            (Some("UNSIGNED BIG INT"), Affinity::Integer),
+            (Some("VARCHAR(255)"), Affinity::Text),
+            (Some("NCHAR(55)"), Affinity::Text),
+            (Some("CLOB"), Affinity::Text),
+            (Some("BLOB"), Affinity::Blob),
+            (None, Affinity::Blob),
+            (Some("DOUBLE PRECISION"), Affinity::Real),
+            (Some("FLOAT"), Affinity::Real),
+            (Some("DECIMAL(10,5)"), Affinity::Numeric),
+            (Some("DATETIME"), Affinity::Numeric),
+            // "INT" wins over "CHAR" because its rule comes first
+            (Some("CHARINT"), Affinity::Integer),
+            // "POINT" contains "INT"
+            (Some("FLOATING POINT"), Affinity::Integer),
+        ];
+
+        for (declared_type, affinity) in cases {
+            assert_eq!(Affinity::from_declared_type(declared_type), affinity);
+        }
+    }
+
+    #[test]
+    fn test_affinity_apply_matches_sqlite_coercion() {
+        let text = |t: &str| SerialData::Text(t.to_string());
+        let show = |value: SerialData| format!("{:?}", value);
+
+        assert_eq!(
+            show(Affinity::Numeric.apply(SerialData::F64(3.0))),
+            "I64(3)"
+        );
+        assert_eq!(show(Affinity::Integer.apply(text("3.0e+2"))), "I64(300)");
+        assert_eq!(show(Affinity::Integer.apply(text(" 42 "))), "I64(42)");
+        assert_eq!(
+            show(Affinity::Integer.apply(text("12abc"))),
+            show(text("12abc"))
+        );
+        assert_eq!(
+            show(Affinity::Numeric.apply(text("0x10"))),
+            show(text("0x10"))
+        );
+        assert_eq!(
+            show(Affinity::Numeric.apply(text("9223372036854775808"))),
+            "F64(9.223372036854776e18)"
+        );
+        assert_eq!(show(Affinity::Real.apply(SerialData::I8(5))), "F64(5.0)");
+        assert_eq!(show(Affinity::Real.apply(text("2"))), "F64(2.0)");
+        assert_eq!(
+            show(Affinity::Text.apply(SerialData::F64(1.5))),
+            show(text("1.5"))
+        );
+        assert_eq!(
+            show(Affinity::Text.apply(SerialData::F64(100.0))),
+            show(text("100.0"))
+        );
+        assert_eq!(
+            show(Affinity::Text.apply(SerialData::F64(1e20))),
+            show(text("1.0e+20"))
+        );
+        assert_eq!(show(Affinity::Text.apply(SerialData::One)), show(text("1")));
+        assert_eq!(show(Affinity::Blob.apply(text("7"))), show(text("7")));
+        assert_eq!(show(Affinity::Text.apply(SerialData::Null)), "Null");
+    }
 }
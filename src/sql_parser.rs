@@ -1,15 +1,470 @@
-use std::{collections::HashMap, fs::read};
+use crate::error::{Result, StorageError};
+use crate::predicate::{ComparisonOp, Predicate};
+use crate::sql_data_types::{Affinity, SerialData};
 
-use crate::sql_data_types::SerialType;
-use anyhow::{bail, Result};
-use nom::Err;
+// returns a parse error from the enclosing function, the parsing counterpart of anyhow's bail!
+macro_rules! fail {
+    ($($arg:tt)*) => {
+        return Err(StorageError::Parse(format!($($arg)*)))
+    };
+}
 
 // Reference Documentation being used to implement sql parsing
 // https://www.sqlite.org/lang.html
 
+#[derive(Debug, Clone)]
 pub struct SqlColumn {
     pub name: String,
-    pub data_type: SerialType,
+    pub affinity: Affinity,
+    // the type name exactly as written in the CREATE TABLE statement, e.g. VARCHAR(255)
+    pub declared_type: Option<String>,
+    pub constraints: Vec<ColumnConstraint>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+// ON CONFLICT of a PRIMARY KEY, NOT NULL or UNIQUE constraint, what a SQL frontend does when the constraint fails
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConflictResolution {
+    Rollback,
+    Abort,
+    Fail,
+    Ignore,
+    Replace,
+}
+
+// Expressions (CHECK, DEFAULT, GENERATED) are kept as the source text they were written as
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnConstraint {
+    // CONSTRAINT name, which names the constraint after it the way SQLite's grammar reads it
+    Name(String),
+    PrimaryKey {
+        order: Option<SortOrder>,
+        conflict: Option<ConflictResolution>,
+        autoincrement: bool,
+    },
+    NotNull(Option<ConflictResolution>),
+    Null(Option<ConflictResolution>),
+    Unique(Option<ConflictResolution>),
+    Check(String),
+    Default(String),
+    Collate(String),
+    References(ForeignKeyClause),
+    Generated {
+        expr: String,
+        stored: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ForeignKeyAction {
+    SetNull,
+    SetDefault,
+    Cascade,
+    Restrict,
+    NoAction,
+}
+
+// [NOT] DEFERRABLE [INITIALLY (DEFERRED | IMMEDIATE)] at the end of a foreign key clause
+#[derive(Debug, Clone, PartialEq)]
+pub struct Deferral {
+    pub deferrable: bool,
+    pub initially_deferred: Option<bool>,
+}
+
+// the actions and deferral are enforced by a SQL frontend, the storage engine only keeps them in the schema
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForeignKeyClause {
+    pub table: String,
+    pub columns: Vec<String>,
+    pub on_delete: Option<ForeignKeyAction>,
+    pub on_update: Option<ForeignKeyAction>,
+    // SQLite parses MATCH but always matches SIMPLE whatever the name
+    pub match_name: Option<String>,
+    pub deferral: Option<Deferral>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedColumn {
+    pub name: String,
+    pub collation: Option<String>,
+    pub order: Option<SortOrder>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableConstraint {
+    // CONSTRAINT name, which names the constraint after it the way SQLite's grammar reads it
+    Name(String),
+    PrimaryKey(Vec<IndexedColumn>, Option<ConflictResolution>),
+    Unique(Vec<IndexedColumn>, Option<ConflictResolution>),
+    Check(String),
+    ForeignKey {
+        columns: Vec<String>,
+        clause: ForeignKeyClause,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct CreateTableStmt {
+    pub temporary: bool,
+    pub if_not_exists: bool,
+    pub schema_name: Option<String>,
+    pub table_name: String,
+    pub columns: Vec<SqlColumn>,
+    pub constraints: Vec<TableConstraint>,
+    pub without_rowid: bool,
+    pub strict: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct CreateIndexStmt {
+    pub unique: bool,
+    pub if_not_exists: bool,
+    pub schema_name: Option<String>,
+    pub index_name: String,
+    pub table_name: String,
+    pub columns: Vec<IndexedColumn>,
+    // the WHERE clause of a partial index, kept as the source text it was written as
+    pub where_clause: Option<String>,
+}
+
+impl CreateIndexStmt {
+    // a CREATE INDEX statement, e.g. CREATE UNIQUE INDEX users_email ON users (email)
+    pub fn parse(stmt: &str) -> Result<CreateIndexStmt> {
+        parse_create_index_stmt(stmt)
+    }
+
+    // the statement as sqlite_schema keeps it, quoted and without IF NOT EXISTS or a schema name like CREATE TABLE
+    pub fn to_sql(&self) -> String {
+        let mut sql = format!(
+            "CREATE {}INDEX {} ON {} {}",
+            if self.unique { "UNIQUE " } else { "" },
+            quote_identifier(&self.index_name),
+            quote_identifier(&self.table_name),
+            indexed_columns_sql(&self.columns)
+        );
+        if let Some(where_clause) = &self.where_clause {
+            sql.push_str(&format!(" WHERE {where_clause}"));
+        }
+        sql
+    }
+}
+
+impl CreateTableStmt {
+    // a CREATE TABLE statement, e.g. CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT NOT NULL)
+    pub fn parse(stmt: &str) -> Result<CreateTableStmt> {
+        parse_create_table_stmt(stmt)
+    }
+
+    // case insensitive position of a column, the same way sqlite resolves column names
+    pub fn column_index(&self, column_name: &str) -> Option<usize> {
+        self.columns
+            .iter()
+            .position(|column| column.name.eq_ignore_ascii_case(column_name))
+    }
+
+    /*
+     * A column declared as INTEGER PRIMARY KEY becomes an alias for the rowid, either through a column constraint or
+     * a single column PRIMARY KEY table constraint. The declared type has to be exactly INTEGER, "INT PRIMARY KEY" is
+     * an ordinary column, and a quirk kept for compatibility means "INTEGER PRIMARY KEY DESC" is not an alias either.
+     * The record stores NULL in the alias column, the actual value is the rowid key of the table btree.
+     */
+    pub fn rowid_alias_column(&self) -> Option<usize> {
+        if self.without_rowid {
+            return None;
+        }
+
+        let is_integer = |column: &SqlColumn| {
+            column
+                .declared_type
+                .as_deref()
+                .is_some_and(|t| t.eq_ignore_ascii_case("INTEGER"))
+        };
+
+        for (idx, column) in self.columns.iter().enumerate() {
+            for constraint in &column.constraints {
+                if let ColumnConstraint::PrimaryKey { order, .. } = constraint {
+                    if is_integer(column) && order.as_ref() != Some(&SortOrder::Desc) {
+                        return Some(idx);
+                    }
+                    return None;
+                }
+            }
+        }
+
+        for constraint in &self.constraints {
+            if let TableConstraint::PrimaryKey(columns, _) = constraint {
+                if let [indexed_column] = columns.as_slice() {
+                    return self
+                        .column_index(&indexed_column.name)
+                        .filter(|idx| is_integer(&self.columns[*idx]));
+                }
+                return None;
+            }
+        }
+
+        None
+    }
+
+    /*
+     * The columns of every index SQLite creates for the table's constraints, in the order it numbers them: column
+     * constraints column by column, then table constraints. A PRIMARY KEY that makes a column the rowid alias needs no
+     * index, neither does the PRIMARY KEY of a WITHOUT ROWID table which is the key of the table btree itself, and a
+     * constraint over the same columns as an earlier one shares its index.
+     */
+    pub(crate) fn autoindex_columns(&self) -> Vec<Vec<IndexedColumn>> {
+        let rowid_alias_column = self.rowid_alias_column();
+        let mut autoindices: Vec<Vec<IndexedColumn>> = Vec::new();
+        let mut add = |columns: Vec<IndexedColumn>| {
+            let names = |columns: &[IndexedColumn]| -> Vec<String> {
+                columns
+                    .iter()
+                    .map(|column| column.name.to_lowercase())
+                    .collect()
+            };
+            if !autoindices
+                .iter()
+                .any(|existing| names(existing) == names(&columns))
+            {
+                autoindices.push(columns);
+            }
+        };
+
+        for (idx, column) in self.columns.iter().enumerate() {
+            for constraint in &column.constraints {
+                let order = match constraint {
+                    ColumnConstraint::PrimaryKey { .. }
+                        if self.without_rowid || rowid_alias_column == Some(idx) =>
+                    {
+                        continue
+                    }
+                    ColumnConstraint::PrimaryKey { order, .. } => order.clone(),
+                    ColumnConstraint::Unique(_) => None,
+                    _ => continue,
+                };
+                add(vec![IndexedColumn {
+                    name: column.name.clone(),
+                    collation: None,
+                    order,
+                }]);
+            }
+        }
+
+        for constraint in &self.constraints {
+            match constraint {
+                TableConstraint::PrimaryKey(columns, _) => {
+                    let is_alias = matches!(columns.as_slice(), [column]
+                        if rowid_alias_column.is_some() && self.column_index(&column.name) == rowid_alias_column);
+                    if !self.without_rowid && !is_alias {
+                        add(columns.clone());
+                    }
+                }
+                TableConstraint::Unique(columns, _) => add(columns.clone()),
+                _ => {}
+            }
+        }
+
+        autoindices
+    }
+
+    /*
+     * The statement as the text sqlite_schema keeps for the table, which is what the table is read back from. Names are
+     * always quoted so any of them reads back the same. IF NOT EXISTS and the schema name only matter while creating
+     * the table and are left out, like SQLite does.
+     */
+    pub fn to_sql(&self) -> String {
+        let mut definitions = self
+            .columns
+            .iter()
+            .map(|column| {
+                let mut definition = quote_identifier(&column.name);
+                if let Some(declared_type) = &column.declared_type {
+                    definition.push(' ');
+                    definition.push_str(declared_type);
+                }
+                for constraint in &column.constraints {
+                    definition.push(' ');
+                    definition.push_str(&column_constraint_sql(constraint));
+                }
+                definition
+            })
+            .collect::<Vec<_>>();
+        // a constraint name shares a definition with the constraint it names
+        let mut named = false;
+        for constraint in &self.constraints {
+            let sql = table_constraint_sql(constraint);
+            match definitions.last_mut() {
+                Some(definition) if named => {
+                    definition.push(' ');
+                    definition.push_str(&sql);
+                }
+                _ => definitions.push(sql),
+            }
+            named = matches!(constraint, TableConstraint::Name(_));
+        }
+
+        let mut options = Vec::new();
+        if self.without_rowid {
+            options.push("WITHOUT ROWID");
+        }
+        if self.strict {
+            options.push("STRICT");
+        }
+
+        let mut sql = format!(
+            "CREATE {}TABLE {} ({})",
+            if self.temporary { "TEMP " } else { "" },
+            quote_identifier(&self.table_name),
+            definitions.join(", ")
+        );
+        if !options.is_empty() {
+            sql.push(' ');
+            sql.push_str(&options.join(", "));
+        }
+        sql
+    }
+}
+
+// a name as a quoted identifier, the quote character is escaped by doubling it
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn sort_order_sql(order: &Option<SortOrder>) -> &'static str {
+    match order {
+        Some(SortOrder::Asc) => " ASC",
+        Some(SortOrder::Desc) => " DESC",
+        None => "",
+    }
+}
+
+fn indexed_columns_sql(columns: &[IndexedColumn]) -> String {
+    let columns = columns
+        .iter()
+        .map(|column| {
+            let mut sql = quote_identifier(&column.name);
+            if let Some(collation) = &column.collation {
+                sql.push_str(&format!(" COLLATE {}", quote_identifier(collation)));
+            }
+            sql.push_str(sort_order_sql(&column.order));
+            sql
+        })
+        .collect::<Vec<_>>();
+    format!("({})", columns.join(", "))
+}
+
+fn conflict_clause_sql(conflict: &Option<ConflictResolution>) -> &'static str {
+    match conflict {
+        Some(ConflictResolution::Rollback) => " ON CONFLICT ROLLBACK",
+        Some(ConflictResolution::Abort) => " ON CONFLICT ABORT",
+        Some(ConflictResolution::Fail) => " ON CONFLICT FAIL",
+        Some(ConflictResolution::Ignore) => " ON CONFLICT IGNORE",
+        Some(ConflictResolution::Replace) => " ON CONFLICT REPLACE",
+        None => "",
+    }
+}
+
+fn foreign_key_action_sql(action: &ForeignKeyAction) -> &'static str {
+    match action {
+        ForeignKeyAction::SetNull => "SET NULL",
+        ForeignKeyAction::SetDefault => "SET DEFAULT",
+        ForeignKeyAction::Cascade => "CASCADE",
+        ForeignKeyAction::Restrict => "RESTRICT",
+        ForeignKeyAction::NoAction => "NO ACTION",
+    }
+}
+
+fn foreign_key_clause_sql(clause: &ForeignKeyClause) -> String {
+    let mut sql = format!("REFERENCES {}", quote_identifier(&clause.table));
+    if !clause.columns.is_empty() {
+        let columns = clause
+            .columns
+            .iter()
+            .map(|column| quote_identifier(column))
+            .collect::<Vec<_>>();
+        sql.push_str(&format!(" ({})", columns.join(", ")));
+    }
+    if let Some(action) = &clause.on_delete {
+        sql.push_str(&format!(" ON DELETE {}", foreign_key_action_sql(action)));
+    }
+    if let Some(action) = &clause.on_update {
+        sql.push_str(&format!(" ON UPDATE {}", foreign_key_action_sql(action)));
+    }
+    if let Some(match_name) = &clause.match_name {
+        sql.push_str(&format!(" MATCH {}", quote_identifier(match_name)));
+    }
+    if let Some(deferral) = &clause.deferral {
+        sql.push_str(if deferral.deferrable {
+            " DEFERRABLE"
+        } else {
+            " NOT DEFERRABLE"
+        });
+        match deferral.initially_deferred {
+            Some(true) => sql.push_str(" INITIALLY DEFERRED"),
+            Some(false) => sql.push_str(" INITIALLY IMMEDIATE"),
+            None => {}
+        }
+    }
+    sql
+}
+
+fn column_constraint_sql(constraint: &ColumnConstraint) -> String {
+    match constraint {
+        ColumnConstraint::Name(name) => format!("CONSTRAINT {}", quote_identifier(name)),
+        ColumnConstraint::PrimaryKey {
+            order,
+            conflict,
+            autoincrement,
+        } => format!(
+            "PRIMARY KEY{}{}{}",
+            sort_order_sql(order),
+            conflict_clause_sql(conflict),
+            if *autoincrement { " AUTOINCREMENT" } else { "" }
+        ),
+        ColumnConstraint::NotNull(conflict) => format!("NOT NULL{}", conflict_clause_sql(conflict)),
+        ColumnConstraint::Null(conflict) => format!("NULL{}", conflict_clause_sql(conflict)),
+        ColumnConstraint::Unique(conflict) => format!("UNIQUE{}", conflict_clause_sql(conflict)),
+        ColumnConstraint::Check(expr) => format!("CHECK ({expr})"),
+        ColumnConstraint::Default(value) => format!("DEFAULT {value}"),
+        ColumnConstraint::Collate(collation) => format!("COLLATE {}", quote_identifier(collation)),
+        ColumnConstraint::References(clause) => foreign_key_clause_sql(clause),
+        ColumnConstraint::Generated { expr, stored } => format!(
+            "GENERATED ALWAYS AS ({expr}) {}",
+            if *stored { "STORED" } else { "VIRTUAL" }
+        ),
+    }
+}
+
+fn table_constraint_sql(constraint: &TableConstraint) -> String {
+    match constraint {
+        TableConstraint::Name(name) => format!("CONSTRAINT {}", quote_identifier(name)),
+        TableConstraint::PrimaryKey(columns, conflict) => format!(
+            "PRIMARY KEY {}{}",
+            indexed_columns_sql(columns),
+            conflict_clause_sql(conflict)
+        ),
+        TableConstraint::Unique(columns, conflict) => format!(
+            "UNIQUE {}{}",
+            indexed_columns_sql(columns),
+            conflict_clause_sql(conflict)
+        ),
+        TableConstraint::Check(expr) => format!("CHECK ({expr})"),
+        TableConstraint::ForeignKey { columns, clause } => {
+            let columns = columns
+                .iter()
+                .map(|column| quote_identifier(column))
+                .collect::<Vec<_>>();
+            format!(
+                "FOREIGN KEY ({}) {}",
+                columns.join(", "),
+                foreign_key_clause_sql(clause)
+            )
+        }
+    }
 }
 
 /* Given a create table stmt, return the schema name and the serial types in vector
@@ -21,222 +476,1297 @@ pub struct SqlColumn {
 */
 
 pub fn find_schema_from_create_stmt(stmt: &str) -> Result<Vec<SqlColumn>> {
-    // TODO
-    // construct lexer
-    // call parser with lexer
-    // interpret the AST to get schema
-    todo!()
+    Ok(parse_create_table_stmt(stmt)?.columns)
+}
+
+pub fn parse_create_table_stmt(stmt: &str) -> Result<CreateTableStmt> {
+    let tokens = Lexer::new(stmt).tokenize()?;
+    let mut parser = Parser::new(stmt, tokens);
+    let create_table_stmt = parser.parse_create_table_stmt()?;
+
+    // a trailing semicolon is the only thing allowed after the statement
+    if parser.curr_token() == &Token::Semicolon {
+        parser.advance();
+    }
+    parser.expect(&Token::Eof)?;
+
+    Ok(create_table_stmt)
+}
+
+// Parses CREATE [UNIQUE] INDEX [IF NOT EXISTS] [schema.]name ON table (indexed-column, ...) [WHERE expr]
+pub fn parse_create_index_stmt(stmt: &str) -> Result<CreateIndexStmt> {
+    let tokens = Lexer::new(stmt).tokenize()?;
+    let mut parser = Parser::new(stmt, tokens);
+    let create_index_stmt = parser.parse_create_index_stmt()?;
+
+    if parser.curr_token() == &Token::Semicolon {
+        parser.advance();
+    }
+    parser.expect(&Token::Eof)?;
+
+    Ok(create_index_stmt)
+}
+
+// Parses the WHERE clause subset understood by Predicate, e.g. age >= 30 AND name LIKE 'A%'
+pub fn parse_predicate(expr: &str) -> Result<Predicate> {
+    let tokens = Lexer::new(expr).tokenize()?;
+    let mut parser = Parser::new(expr, tokens);
+    let predicate = parser.parse_or_expr()?;
+    parser.expect(&Token::Eof)?;
+
+    Ok(predicate)
+}
+
+// Parses a comma separated list of literal values, e.g. the 1, 'Alice', NULL of a VALUES clause
+pub fn parse_literal_list(input: &str) -> Result<Vec<SerialData>> {
+    let tokens = Lexer::new(input).tokenize()?;
+    let mut parser = Parser::new(input, tokens);
+    let mut values = vec![parser.parse_literal_value()?];
+    while parser.curr_token() == &Token::Comma {
+        parser.advance();
+        values.push(parser.parse_literal_value()?);
+    }
+    parser.expect(&Token::Eof)?;
+
+    Ok(values)
 }
 
-// What are the tokens the lexer needs to be able to create table via sql?
-#[derive(Clone)]
+/*
+ * Keywords are not tokens of their own. Most SQLite keywords can also be used as plain identifiers
+ * (a column named "key" or "action" is perfectly valid), so the lexer hands back bare words and the parser
+ * decides from context whether a word is acting as a keyword. Quoted identifiers are never keywords.
+ */
+#[derive(Debug, Clone, PartialEq)]
 pub enum Token {
-    Create,
-    Table,
-    Literal(String),
+    Word(String),
+    QuotedIdentifier(String),
     StringLiteral(String),
     BlobLiteral(String),
     NumericLiteral(String),
-    If,
-    Not,
-    Exists,
+    Operator(String),
     OpenParen,
     CloseParen,
     Comma,
     Period,
-    EOF, // indicates end of token stream
+    Semicolon,
+    Eof, // indicates end of token stream
+}
+
+// A token along with the byte range of the input it was read from
+#[derive(Debug, Clone)]
+struct SpannedToken {
+    token: Token,
+    start: usize,
+    end: usize,
 }
 
 struct Lexer {
-    input: String,
+    input: Vec<(usize, char)>,
+    input_len: usize,
     char_position: usize,
-    curr_token: Option<Token>,
-    next_token: Option<Token>,
 }
 
 impl Lexer {
-    fn new(input: String) -> Lexer {
+    fn new(input: &str) -> Lexer {
         Lexer {
-            input,
+            input: input.char_indices().collect(),
+            input_len: input.len(),
             char_position: 0,
-            curr_token: None,
-            next_token: None,
         }
     }
 
-    fn get_curr_token(&mut self) -> Result<Option<Token>> {
-        match &self.curr_token {
-            Some(token) => Ok(Some(token.clone())),
-            None => {
-                let read_curr_token = self.read_in_token()?;
-                self.curr_token = Some(read_curr_token.clone());
-                Ok(Some(read_curr_token))
+    fn tokenize(mut self) -> Result<Vec<SpannedToken>> {
+        let mut tokens = Vec::new();
+        loop {
+            self.skip_whitespace_and_comments()?;
+            let start = self.byte_offset();
+            let token = self.read_in_token()?;
+            let end = self.byte_offset();
+            let is_eof = token == Token::Eof;
+            tokens.push(SpannedToken { token, start, end });
+            if is_eof {
+                return Ok(tokens);
             }
         }
     }
 
-    fn peek(&mut self) -> Result<Option<Token>> {
-        match self.next_token {
-            Some(ref token) => Ok(Some(token.clone())),
-            None => {
-                let next_token = self.read_in_token()?;
-                self.next_token = Some(next_token.clone());
-                Ok(Some(next_token))
+    fn curr_char(&self) -> Option<char> {
+        self.char_at(self.char_position)
+    }
+
+    fn char_at(&self, position: usize) -> Option<char> {
+        self.input.get(position).map(|(_, ch)| *ch)
+    }
+
+    fn byte_offset(&self) -> usize {
+        self.input
+            .get(self.char_position)
+            .map(|(offset, _)| *offset)
+            .unwrap_or(self.input_len)
+    }
+
+    fn skip_whitespace_and_comments(&mut self) -> Result<()> {
+        loop {
+            match (self.curr_char(), self.char_at(self.char_position + 1)) {
+                (Some(ch), _) if ch.is_whitespace() => self.char_position += 1,
+                (Some('-'), Some('-')) => {
+                    while !matches!(self.curr_char(), None | Some('\n')) {
+                        self.char_position += 1;
+                    }
+                }
+                (Some('/'), Some('*')) => {
+                    self.char_position += 2;
+                    loop {
+                        match (self.curr_char(), self.char_at(self.char_position + 1)) {
+                            (Some('*'), Some('/')) => {
+                                self.char_position += 2;
+                                break;
+                            }
+                            (Some(_), _) => self.char_position += 1,
+                            (None, _) => fail!("Unterminated comment"),
+                        }
+                    }
+                }
+                _ => return Ok(()),
             }
         }
     }
 
+    fn read_in_token(&mut self) -> Result<Token> {
+        let curr_char = match self.curr_char() {
+            Some(ch) => ch,
+            None => return Ok(Token::Eof),
+        };
+
+        // https://www.sqlite.org/syntax/literal-value.html
+        let token = match curr_char {
+            '(' => self.single_char_token(Token::OpenParen),
+            ')' => self.single_char_token(Token::CloseParen),
+            ',' => self.single_char_token(Token::Comma),
+            ';' => self.single_char_token(Token::Semicolon),
+            '\'' => Token::StringLiteral(self.read_quoted('\'', '\'')?),
+            '"' => Token::QuotedIdentifier(self.read_quoted('"', '"')?),
+            '`' => Token::QuotedIdentifier(self.read_quoted('`', '`')?),
+            '[' => Token::QuotedIdentifier(self.read_quoted('[', ']')?),
+            'x' | 'X' if self.char_at(self.char_position + 1) == Some('\'') => {
+                // BLOB literal, skip the X
+                self.char_position += 1;
+                let literal = self.read_quoted('\'', '\'')?;
+                if literal.len() % 2 != 0 || !literal.chars().all(|c| c.is_ascii_hexdigit()) {
+                    fail!("Invalid hex string in blob literal: {literal}")
+                }
+                Token::BlobLiteral(literal)
+            }
+            '.' if !self
+                .char_at(self.char_position + 1)
+                .is_some_and(|c| c.is_ascii_digit()) =>
+            {
+                self.single_char_token(Token::Period)
+            }
+            c if c.is_ascii_digit() || c == '.' => Token::NumericLiteral(self.read_number()),
+            c if c.is_alphabetic() || c == '_' => Token::Word(self.read_word()),
+            _ => Token::Operator(self.read_operator()?),
+        };
+
+        Ok(token)
+    }
+
+    fn single_char_token(&mut self, token: Token) -> Token {
+        self.char_position += 1;
+        token
+    }
+
+    // reads a quoted string or identifier, a doubled closing quote stands for the quote itself
+    fn read_quoted(&mut self, open: char, close: char) -> Result<String> {
+        debug_assert_eq!(self.curr_char(), Some(open));
+        self.char_position += 1;
+
+        let mut literal = String::new();
+        loop {
+            match self.curr_char() {
+                Some(ch) if ch == close => {
+                    self.char_position += 1;
+                    if close != ']' && self.curr_char() == Some(close) {
+                        literal.push(close);
+                        self.char_position += 1;
+                    } else {
+                        return Ok(literal);
+                    }
+                }
+                Some(ch) => {
+                    literal.push(ch);
+                    self.char_position += 1;
+                }
+                None => fail!("Unexpected end of input, missing closing {close}"),
+            }
+        }
+    }
+
+    fn read_word(&mut self) -> String {
+        let mut word = String::new();
+        while let Some(ch) = self.curr_char() {
+            if !(ch.is_alphanumeric() || ch == '_' || ch == '$') {
+                break;
+            }
+            word.push(ch);
+            self.char_position += 1;
+        }
+        word
+    }
+
+    fn read_number(&mut self) -> String {
+        let mut number = String::new();
+        let is_hex = self.curr_char() == Some('0')
+            && matches!(self.char_at(self.char_position + 1), Some('x' | 'X'));
+        if is_hex {
+            number.push_str("0x");
+            self.char_position += 2;
+            while let Some(ch) = self.curr_char().filter(|c| c.is_ascii_hexdigit()) {
+                number.push(ch);
+                self.char_position += 1;
+            }
+            return number;
+        }
+
+        while let Some(ch) = self.curr_char() {
+            let is_exponent_sign = matches!(ch, '+' | '-') && number.ends_with(['e', 'E']);
+            if !(ch.is_ascii_digit() || matches!(ch, '.' | 'e' | 'E') || is_exponent_sign) {
+                break;
+            }
+            number.push(ch);
+            self.char_position += 1;
+        }
+        number
+    }
+
+    fn read_operator(&mut self) -> Result<String> {
+        let two_chars: String = [self.curr_char(), self.char_at(self.char_position + 1)]
+            .iter()
+            .flatten()
+            .collect();
+        if ["||", "<<", ">>", "<=", ">=", "==", "!=", "<>", "->"].contains(&two_chars.as_str()) {
+            self.char_position += 2;
+            return Ok(two_chars);
+        }
+
+        match self.curr_char() {
+            Some(ch @ ('*' | '/' | '%' | '+' | '-' | '&' | '|' | '<' | '>' | '=' | '~')) => {
+                self.char_position += 1;
+                Ok(ch.to_string())
+            }
+            Some(ch) => fail!("Unexpected character {ch} in sql"),
+            None => fail!("Unexpected end of input"),
+        }
+    }
+}
+
+/*
+ * Recursive descent parser over the CREATE TABLE grammar
+ * https://www.sqlite.org/lang_createtable.html
+ *
+ * create-table-stmt := CREATE [TEMP | TEMPORARY] TABLE [IF NOT EXISTS] [schema-name .] table-name
+ *                      ( column-def [, column-def]* [, table-constraint]* ) [table-options]
+ * column-def        := column-name [type-name] [column-constraint]*
+ * type-name         := name [name]* [( signed-number ) | ( signed-number , signed-number )]
+ * column-constraint := [CONSTRAINT name] ( PRIMARY KEY [ASC | DESC] conflict-clause [AUTOINCREMENT]
+ *                      | NOT NULL conflict-clause | NULL | UNIQUE conflict-clause | CHECK ( expr )
+ *                      | DEFAULT ( signed-number | literal-value | ( expr ) ) | COLLATE collation-name
+ *                      | foreign-key-clause | [GENERATED ALWAYS] AS ( expr ) [STORED | VIRTUAL] )
+ * table-constraint  := [CONSTRAINT name] ( PRIMARY KEY ( indexed-column [, indexed-column]* ) conflict-clause
+ *                      | UNIQUE ( indexed-column [, indexed-column]* ) conflict-clause | CHECK ( expr )
+ *                      | FOREIGN KEY ( column-name [, column-name]* ) foreign-key-clause )
+ * table-options     := ( WITHOUT ROWID | STRICT ) [, ( WITHOUT ROWID | STRICT )]*
+ */
+struct Parser<'a> {
+    input: &'a str,
+    tokens: Vec<SpannedToken>,
+    position: usize,
+}
+
+// words that end a type name because they start a column constraint
+const COLUMN_CONSTRAINT_KEYWORDS: [&str; 11] = [
+    "CONSTRAINT",
+    "PRIMARY",
+    "NOT",
+    "NULL",
+    "UNIQUE",
+    "CHECK",
+    "DEFAULT",
+    "COLLATE",
+    "REFERENCES",
+    "GENERATED",
+    "AS",
+];
+
+// words that start a table constraint rather than a column definition
+const TABLE_CONSTRAINT_KEYWORDS: [&str; 5] =
+    ["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"];
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str, tokens: Vec<SpannedToken>) -> Self {
+        Parser {
+            input,
+            tokens,
+            position: 0,
+        }
+    }
+
+    fn curr_token(&self) -> &Token {
+        // the token stream always ends with EOF, which is never advanced past
+        &self.tokens[self.position.min(self.tokens.len() - 1)].token
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[(self.position + 1).min(self.tokens.len() - 1)].token
+    }
+
     fn advance(&mut self) {
-        self.curr_token = self.next_token.take();
+        if self.position < self.tokens.len() - 1 {
+            self.position += 1;
+        }
     }
 
-    fn read_in_token(&mut self) -> Result<Token> {
-        if self.char_position == self.input.len() {
-            return Ok(Token::EOF);
+    fn curr_start(&self) -> usize {
+        self.tokens[self.position].start
+    }
+
+    fn prev_end(&self) -> usize {
+        self.tokens[self.position.saturating_sub(1)].end
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.curr_token(), Token::Word(word) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn is_any_keyword(&self, keywords: &[&str]) -> bool {
+        keywords.iter().any(|keyword| self.is_keyword(keyword))
+    }
+
+    // consumes the keyword if it is the current token
+    fn accept_keyword(&mut self, keyword: &str) -> bool {
+        if self.is_keyword(keyword) {
+            self.advance();
+            return true;
         }
+        false
+    }
 
-        let mut read_keyword_lookup = HashMap::new();
+    fn expect_keyword(&mut self, keyword: &str) -> Result<()> {
+        if !self.accept_keyword(keyword) {
+            fail!("Expected {keyword} but found {:?}", self.curr_token());
+        }
+        Ok(())
+    }
 
-        read_keyword_lookup.insert(
-            'c',
-            vec![
-                ("create", Token::Create),
-                ("current_time", Token::Literal("CURRENT_TIME".to_string())),
-                ("current_date", Token::Literal("CURRENT_DATE".to_string())),
-                (
-                    "current_timestamp",
-                    Token::Literal("CURRENT_TIMESTAMP".to_string()),
-                ),
-            ],
-        );
+    fn expect(&mut self, token: &Token) -> Result<()> {
+        if self.curr_token() != token {
+            fail!("Expected {:?} but found {:?}", token, self.curr_token());
+        }
+        self.advance();
+        Ok(())
+    }
 
-        read_keyword_lookup.insert('i', vec![("if", Token::If)]);
+    // identifiers can be bare words, quoted identifiers or (for legacy reasons) string literals
+    fn parse_name(&mut self) -> Result<String> {
+        let name = match self.curr_token() {
+            Token::Word(name) | Token::QuotedIdentifier(name) | Token::StringLiteral(name) => {
+                name.clone()
+            }
+            token => fail!("Expected a name but found {:?}", token),
+        };
+        self.advance();
+        Ok(name)
+    }
 
-        read_keyword_lookup.insert(
-            'n',
-            vec![
-                ("not", Token::Not),
-                ("null", Token::Literal("NULL".to_string())),
-            ],
-        );
+    fn parse_create_table_stmt(&mut self) -> Result<CreateTableStmt> {
+        self.expect_keyword("CREATE")?;
+        let temporary = self.accept_keyword("TEMP") || self.accept_keyword("TEMPORARY");
+        self.expect_keyword("TABLE")?;
 
-        read_keyword_lookup.insert('e', vec![("exists", Token::Exists)]);
-        read_keyword_lookup.insert(
-            't',
-            vec![
-                ("true", Token::Literal("TRUE".to_string())),
-                ("table", Token::Table),
-            ],
-        );
+        let if_not_exists = self.accept_keyword("IF");
+        if if_not_exists {
+            self.expect_keyword("NOT")?;
+            self.expect_keyword("EXISTS")?;
+        }
 
-        // get current character, and make a decision off of that
-        let curr_char = self.input.chars().nth(self.char_position);
-        match curr_char {
-            Some('(') => {
-                self.char_position += 1;
-                Ok(Token::OpenParen)
+        let mut schema_name = None;
+        let mut table_name = self.parse_name()?;
+        if self.curr_token() == &Token::Period {
+            self.advance();
+            schema_name = Some(table_name);
+            table_name = self.parse_name()?;
+        }
+
+        if self.is_keyword("AS") {
+            fail!("CREATE TABLE ... AS SELECT is not supported");
+        }
+
+        self.expect(&Token::OpenParen)?;
+        let mut columns = Vec::new();
+        let mut constraints = Vec::new();
+        loop {
+            // once table constraints start no more columns can follow
+            if !constraints.is_empty() || self.is_any_keyword(&TABLE_CONSTRAINT_KEYWORDS) {
+                constraints.push(self.parse_table_constraint()?);
+                // no comma between a constraint name and the constraint it names
+                if matches!(constraints.last(), Some(TableConstraint::Name(_)))
+                    && !matches!(self.curr_token(), Token::Comma | Token::CloseParen)
+                {
+                    continue;
+                }
+            } else {
+                columns.push(self.parse_column_def()?);
             }
-            Some(')') => {
-                self.char_position += 1;
-                Ok(Token::CloseParen)
+
+            match self.curr_token() {
+                Token::Comma => self.advance(),
+                Token::CloseParen => {
+                    self.advance();
+                    break;
+                }
+                token => fail!("Expected , or ) in table definition but found {:?}", token),
             }
-            Some(',') => {
-                self.char_position += 1;
-                Ok(Token::Comma)
+        }
+
+        if columns.is_empty() {
+            fail!("Table {table_name} must have at least one column");
+        }
+
+        let mut without_rowid = false;
+        let mut strict = false;
+        loop {
+            if self.accept_keyword("WITHOUT") {
+                self.expect_keyword("ROWID")?;
+                without_rowid = true;
+            } else if self.accept_keyword("STRICT") {
+                strict = true;
+            } else {
+                break;
             }
-            Some('.') => {
-                self.char_position += 1;
-                Ok(Token::Period)
+
+            if self.curr_token() != &Token::Comma {
+                break;
             }
-            Some(' ') => {
-                // skip whitespace, and recrusively call self
-                self.char_position += 1;
-                return self.read_in_token();
-            }
-            Some(c) => match read_keyword_lookup.get(&c) {
-                Some(potential_expected_paths) => {
-                    for (expected, token) in potential_expected_paths {
-                        match self.read_and_return(&expected, token.clone()) {
-                            Ok(token) => return Ok(token),
-                            Err(_) => continue,
-                        }
-                    }
-                    bail!("no corresponding token found for character")
-                }
-                None => {
-                    match c {
-                        '\'' => {
-                            // string
-                            // we need to find the closing single quote
-                            let mut string_literal = String::new();
-                            loop {
-                                let next_char = self.input.chars().nth(self.char_position);
-                                match next_char {
-                                    Some('\'') => {
-                                        self.char_position += 1;
-                                        break;
-                                    }
-                                    Some(ch) => {
-                                        string_literal.push(ch);
-                                        self.char_position += 1;
-                                    }
-                                    None => bail!("Unexpected end of input"),
-                                }
-                            }
-                            Ok(Token::StringLiteral(string_literal))
-                        }, 
-                        'x' | 'X' => {
-                            // BLOB literal
-                            // skip the X
-                            self.char_position += 1;
-                            match self.read_in_token()? {
-                                Token::StringLiteral(literal) => {
-                                    // check if the literal is a valid hex string
-                                    if literal.len() % 2 != 0 {
-                                        bail!("Invalid hex string")
-                                    }
-                                    for c in literal.chars() {
-                                        if !c.is_ascii_hexdigit() {
-                                            bail!("Invalid hex string")
-                                        }
-                                    }
-                                    Ok(Token::BlobLiteral(literal))
-                                },
-                                _ => bail!("Unexpected character")
-                            }
-                        },
-                        _ => {
-                            // numeric literal
-                            todo!()
-                        }
+            self.advance();
+        }
+
+        Ok(CreateTableStmt {
+            temporary,
+            if_not_exists,
+            schema_name,
+            table_name,
+            columns,
+            constraints,
+            without_rowid,
+            strict,
+        })
+    }
+
+    fn parse_create_index_stmt(&mut self) -> Result<CreateIndexStmt> {
+        self.expect_keyword("CREATE")?;
+        let unique = self.accept_keyword("UNIQUE");
+        self.expect_keyword("INDEX")?;
+
+        let if_not_exists = self.accept_keyword("IF");
+        if if_not_exists {
+            self.expect_keyword("NOT")?;
+            self.expect_keyword("EXISTS")?;
+        }
+
+        let mut schema_name = None;
+        let mut index_name = self.parse_name()?;
+        if self.curr_token() == &Token::Period {
+            self.advance();
+            schema_name = Some(index_name);
+            index_name = self.parse_name()?;
+        }
+
+        self.expect_keyword("ON")?;
+        let table_name = self.parse_name()?;
+        let columns = self.parse_indexed_columns()?;
+
+        // the expression runs to the end of the statement
+        let where_clause = if self.accept_keyword("WHERE") {
+            let start = self.curr_start();
+            while !matches!(self.curr_token(), Token::Semicolon | Token::Eof) {
+                self.advance();
+            }
+            let expr = self.input[start..self.prev_end()].trim();
+            if expr.is_empty() {
+                fail!("Index {index_name} has an empty WHERE clause");
+            }
+            Some(expr.to_string())
+        } else {
+            None
+        };
+
+        Ok(CreateIndexStmt {
+            unique,
+            if_not_exists,
+            schema_name,
+            index_name,
+            table_name,
+            columns,
+            where_clause,
+        })
+    }
+
+    fn parse_column_def(&mut self) -> Result<SqlColumn> {
+        let name = self.parse_name()?;
+        let declared_type = self.parse_type_name()?;
+
+        let mut constraints = Vec::new();
+        while !matches!(self.curr_token(), Token::Comma | Token::CloseParen) {
+            constraints.push(self.parse_column_constraint()?);
+        }
+
+        Ok(SqlColumn {
+            name,
+            affinity: Affinity::from_declared_type(declared_type.as_deref()),
+            declared_type,
+            constraints,
+        })
+    }
+
+    // returns the type name as it was written, including any size arguments
+    fn parse_type_name(&mut self) -> Result<Option<String>> {
+        let start = self.curr_start();
+        let mut has_name = false;
+        while matches!(
+            self.curr_token(),
+            Token::Word(_) | Token::QuotedIdentifier(_)
+        ) && !self.is_any_keyword(&COLUMN_CONSTRAINT_KEYWORDS)
+        {
+            has_name = true;
+            self.advance();
+        }
+
+        if !has_name {
+            return Ok(None);
+        }
+
+        if self.curr_token() == &Token::OpenParen {
+            self.advance();
+            self.parse_signed_number()?;
+            if self.curr_token() == &Token::Comma {
+                self.advance();
+                self.parse_signed_number()?;
+            }
+            self.expect(&Token::CloseParen)?;
+        }
+
+        Ok(Some(self.input[start..self.prev_end()].to_string()))
+    }
+
+    fn parse_signed_number(&mut self) -> Result<String> {
+        let start = self.curr_start();
+        if matches!(self.curr_token(), Token::Operator(op) if op == "+" || op == "-") {
+            self.advance();
+        }
+        match self.curr_token() {
+            Token::NumericLiteral(_) => self.advance(),
+            token => fail!("Expected a number but found {:?}", token),
+        }
+        Ok(self.input[start..self.prev_end()].to_string())
+    }
+
+    fn parse_column_constraint(&mut self) -> Result<ColumnConstraint> {
+        let constraint = if self.accept_keyword("CONSTRAINT") {
+            ColumnConstraint::Name(self.parse_name()?)
+        } else if self.accept_keyword("PRIMARY") {
+            self.expect_keyword("KEY")?;
+            let order = self.parse_sort_order();
+            let conflict = self.parse_conflict_clause()?;
+            let autoincrement = self.accept_keyword("AUTOINCREMENT");
+            ColumnConstraint::PrimaryKey {
+                order,
+                conflict,
+                autoincrement,
+            }
+        } else if self.accept_keyword("NOT") {
+            self.expect_keyword("NULL")?;
+            ColumnConstraint::NotNull(self.parse_conflict_clause()?)
+        } else if self.accept_keyword("NULL") {
+            ColumnConstraint::Null(self.parse_conflict_clause()?)
+        } else if self.accept_keyword("UNIQUE") {
+            ColumnConstraint::Unique(self.parse_conflict_clause()?)
+        } else if self.accept_keyword("CHECK") {
+            ColumnConstraint::Check(self.parse_parenthesized_expr()?)
+        } else if self.accept_keyword("DEFAULT") {
+            ColumnConstraint::Default(self.parse_default_value()?)
+        } else if self.accept_keyword("COLLATE") {
+            ColumnConstraint::Collate(self.parse_name()?)
+        } else if self.is_keyword("REFERENCES") {
+            ColumnConstraint::References(self.parse_foreign_key_clause()?)
+        } else if self.is_keyword("GENERATED") || self.is_keyword("AS") {
+            if self.accept_keyword("GENERATED") {
+                self.expect_keyword("ALWAYS")?;
+            }
+            self.expect_keyword("AS")?;
+            let expr = self.parse_parenthesized_expr()?;
+            let stored = self.accept_keyword("STORED");
+            if !stored {
+                self.accept_keyword("VIRTUAL");
+            }
+            ColumnConstraint::Generated { expr, stored }
+        } else {
+            fail!("Unexpected {:?} in column definition", self.curr_token());
+        };
+
+        Ok(constraint)
+    }
+
+    fn parse_table_constraint(&mut self) -> Result<TableConstraint> {
+        let constraint = if self.accept_keyword("CONSTRAINT") {
+            TableConstraint::Name(self.parse_name()?)
+        } else if self.accept_keyword("PRIMARY") {
+            self.expect_keyword("KEY")?;
+            let columns = self.parse_indexed_columns()?;
+            TableConstraint::PrimaryKey(columns, self.parse_conflict_clause()?)
+        } else if self.accept_keyword("UNIQUE") {
+            let columns = self.parse_indexed_columns()?;
+            TableConstraint::Unique(columns, self.parse_conflict_clause()?)
+        } else if self.accept_keyword("CHECK") {
+            TableConstraint::Check(self.parse_parenthesized_expr()?)
+        } else if self.accept_keyword("FOREIGN") {
+            self.expect_keyword("KEY")?;
+            let columns = self.parse_column_name_list()?;
+            let clause = self.parse_foreign_key_clause()?;
+            TableConstraint::ForeignKey { columns, clause }
+        } else {
+            fail!("Unexpected {:?} in table constraint", self.curr_token());
+        };
+
+        Ok(constraint)
+    }
+
+    fn parse_sort_order(&mut self) -> Option<SortOrder> {
+        if self.accept_keyword("ASC") {
+            Some(SortOrder::Asc)
+        } else if self.accept_keyword("DESC") {
+            Some(SortOrder::Desc)
+        } else {
+            None
+        }
+    }
+
+    // ON CONFLICT (ROLLBACK | ABORT | FAIL | IGNORE | REPLACE)
+    fn parse_conflict_clause(&mut self) -> Result<Option<ConflictResolution>> {
+        if !self.accept_keyword("ON") {
+            return Ok(None);
+        }
+        self.expect_keyword("CONFLICT")?;
+        let conflict = if self.accept_keyword("ROLLBACK") {
+            ConflictResolution::Rollback
+        } else if self.accept_keyword("ABORT") {
+            ConflictResolution::Abort
+        } else if self.accept_keyword("FAIL") {
+            ConflictResolution::Fail
+        } else if self.accept_keyword("IGNORE") {
+            ConflictResolution::Ignore
+        } else if self.accept_keyword("REPLACE") {
+            ConflictResolution::Replace
+        } else {
+            fail!("Unexpected conflict resolution {:?}", self.curr_token());
+        };
+        Ok(Some(conflict))
+    }
+
+    fn parse_indexed_columns(&mut self) -> Result<Vec<IndexedColumn>> {
+        self.expect(&Token::OpenParen)?;
+        let mut columns = Vec::new();
+        loop {
+            let name = self.parse_name()?;
+            let collation = if self.accept_keyword("COLLATE") {
+                Some(self.parse_name()?)
+            } else {
+                None
+            };
+            let order = self.parse_sort_order();
+            columns.push(IndexedColumn {
+                name,
+                collation,
+                order,
+            });
+
+            if self.curr_token() != &Token::Comma {
+                break;
+            }
+            self.advance();
+        }
+        self.expect(&Token::CloseParen)?;
+
+        Ok(columns)
+    }
+
+    fn parse_column_name_list(&mut self) -> Result<Vec<String>> {
+        self.expect(&Token::OpenParen)?;
+        let mut columns = vec![self.parse_name()?];
+        while self.curr_token() == &Token::Comma {
+            self.advance();
+            columns.push(self.parse_name()?);
+        }
+        self.expect(&Token::CloseParen)?;
+
+        Ok(columns)
+    }
+
+    /*
+     * foreign-key-clause := REFERENCES foreign-table [( column-name [, column-name]* )]
+     *                       [ON (DELETE | UPDATE) action | MATCH name]* [[NOT] DEFERRABLE [INITIALLY (DEFERRED | IMMEDIATE)]]
+     * A later ON DELETE or ON UPDATE replaces an earlier one, as in SQLite.
+     */
+    fn parse_foreign_key_clause(&mut self) -> Result<ForeignKeyClause> {
+        self.expect_keyword("REFERENCES")?;
+        let table = self.parse_name()?;
+        let columns = if self.curr_token() == &Token::OpenParen {
+            self.parse_column_name_list()?
+        } else {
+            Vec::new()
+        };
+
+        let (mut on_delete, mut on_update, mut match_name) = (None, None, None);
+        loop {
+            if self.accept_keyword("ON") {
+                let is_delete = if self.accept_keyword("DELETE") {
+                    true
+                } else if self.accept_keyword("UPDATE") {
+                    false
+                } else {
+                    fail!(
+                        "Expected DELETE or UPDATE but found {:?}",
+                        self.curr_token()
+                    );
+                };
+                let action = if self.accept_keyword("SET") {
+                    if self.accept_keyword("NULL") {
+                        ForeignKeyAction::SetNull
+                    } else if self.accept_keyword("DEFAULT") {
+                        ForeignKeyAction::SetDefault
+                    } else {
+                        fail!("Expected NULL or DEFAULT but found {:?}", self.curr_token());
                     }
-                } 
-                  // https://www.sqlite.org/syntax/literal-value.html
-            },
-            None => bail!("Unexpected character"),
+                } else if self.accept_keyword("NO") {
+                    self.expect_keyword("ACTION")?;
+                    ForeignKeyAction::NoAction
+                } else if self.accept_keyword("CASCADE") {
+                    ForeignKeyAction::Cascade
+                } else if self.accept_keyword("RESTRICT") {
+                    ForeignKeyAction::Restrict
+                } else {
+                    fail!("Unexpected foreign key action {:?}", self.curr_token());
+                };
+                if is_delete {
+                    on_delete = Some(action);
+                } else {
+                    on_update = Some(action);
+                }
+            } else if self.accept_keyword("MATCH") {
+                match_name = Some(self.parse_name()?);
+            } else {
+                break;
+            }
+        }
+
+        let not_deferrable = self.is_keyword("NOT")
+            && matches!(self.peek(), Token::Word(word) if word.eq_ignore_ascii_case("DEFERRABLE"));
+        if not_deferrable {
+            self.advance();
+        }
+        let mut deferral = None;
+        if not_deferrable || self.is_keyword("DEFERRABLE") {
+            self.expect_keyword("DEFERRABLE")?;
+            let initially_deferred = if !self.accept_keyword("INITIALLY") {
+                None
+            } else if self.accept_keyword("DEFERRED") {
+                Some(true)
+            } else if self.accept_keyword("IMMEDIATE") {
+                Some(false)
+            } else {
+                fail!(
+                    "Expected DEFERRED or IMMEDIATE but found {:?}",
+                    self.curr_token()
+                );
+            };
+            deferral = Some(Deferral {
+                deferrable: !not_deferrable,
+                initially_deferred,
+            });
+        }
+
+        Ok(ForeignKeyClause {
+            table,
+            columns,
+            on_delete,
+            on_update,
+            match_name,
+            deferral,
+        })
+    }
+
+    fn parse_default_value(&mut self) -> Result<String> {
+        let start = self.curr_start();
+        match self.curr_token() {
+            Token::OpenParen => {
+                self.skip_parenthesized()?;
+            }
+            Token::Operator(op) if op == "+" || op == "-" => {
+                self.parse_signed_number()?;
+            }
+            Token::NumericLiteral(_)
+            | Token::StringLiteral(_)
+            | Token::BlobLiteral(_)
+            | Token::Word(_) => self.advance(),
+            token => fail!("Unexpected {:?} in DEFAULT", token),
+        }
+
+        Ok(self.input[start..self.prev_end()].to_string())
+    }
+
+    /*
+     * Predicates, https://www.sqlite.org/lang_expr.html restricted to a column on the left and literals on the right
+     *
+     * or-expr       := and-expr [OR and-expr]*
+     * and-expr      := not-expr [AND not-expr]*
+     * not-expr      := NOT not-expr | ( or-expr ) | column-name column-test
+     * column-test   := comparison-op literal-value | IS [NOT] NULL | ISNULL | NOTNULL
+     *                  | [NOT] IN ( literal-value [, literal-value]* ) | [NOT] BETWEEN literal-value AND literal-value
+     *                  | [NOT] LIKE string-literal
+     * comparison-op := = | == | != | <> | < | <= | > | >=
+     * literal-value := [+ | -] numeric-literal | string-literal | blob-literal | NULL | TRUE | FALSE
+     */
+    fn parse_or_expr(&mut self) -> Result<Predicate> {
+        let mut predicate = self.parse_and_expr()?;
+        while self.accept_keyword("OR") {
+            predicate = Predicate::Or(Box::new(predicate), Box::new(self.parse_and_expr()?));
+        }
+        Ok(predicate)
+    }
+
+    fn parse_and_expr(&mut self) -> Result<Predicate> {
+        let mut predicate = self.parse_not_expr()?;
+        while self.accept_keyword("AND") {
+            predicate = Predicate::And(Box::new(predicate), Box::new(self.parse_not_expr()?));
+        }
+        Ok(predicate)
+    }
+
+    fn parse_not_expr(&mut self) -> Result<Predicate> {
+        if self.accept_keyword("NOT") {
+            return Ok(Predicate::Not(Box::new(self.parse_not_expr()?)));
+        }
+
+        if self.curr_token() == &Token::OpenParen {
+            self.advance();
+            let predicate = self.parse_or_expr()?;
+            self.expect(&Token::CloseParen)?;
+            return Ok(predicate);
+        }
+
+        let column = match self.curr_token() {
+            Token::Word(name) | Token::QuotedIdentifier(name) => name.clone(),
+            token => fail!("Expected a column name but found {:?}", token),
+        };
+        self.advance();
+        self.parse_column_test(column)
+    }
+
+    fn parse_column_test(&mut self, column: String) -> Result<Predicate> {
+        if let Token::Operator(op) = self.curr_token() {
+            let op = match op.as_str() {
+                "=" | "==" => ComparisonOp::Eq,
+                "!=" | "<>" => ComparisonOp::NotEq,
+                "<" => ComparisonOp::Lt,
+                "<=" => ComparisonOp::LtEq,
+                ">" => ComparisonOp::Gt,
+                ">=" => ComparisonOp::GtEq,
+                op => fail!("Unsupported operator {op} in predicate"),
+            };
+            self.advance();
+            let value = self.parse_literal_value()?;
+            return Ok(Predicate::Comparison { column, op, value });
+        }
+
+        if self.accept_keyword("ISNULL") {
+            return Ok(Predicate::IsNull {
+                column,
+                negated: false,
+            });
+        }
+        if self.accept_keyword("NOTNULL") {
+            return Ok(Predicate::IsNull {
+                column,
+                negated: true,
+            });
+        }
+
+        if self.accept_keyword("IS") {
+            let negated = self.accept_keyword("NOT");
+            self.expect_keyword("NULL")?;
+            return Ok(Predicate::IsNull { column, negated });
+        }
+
+        let negated = self.accept_keyword("NOT");
+        if self.accept_keyword("IN") {
+            self.expect(&Token::OpenParen)?;
+            let mut values = Vec::new();
+            if self.curr_token() != &Token::CloseParen {
+                values.push(self.parse_literal_value()?);
+                while self.curr_token() == &Token::Comma {
+                    self.advance();
+                    values.push(self.parse_literal_value()?);
+                }
+            }
+            self.expect(&Token::CloseParen)?;
+            Ok(Predicate::In {
+                column,
+                values,
+                negated,
+            })
+        } else if self.accept_keyword("BETWEEN") {
+            let low = self.parse_literal_value()?;
+            self.expect_keyword("AND")?;
+            let high = self.parse_literal_value()?;
+            Ok(Predicate::Between {
+                column,
+                low,
+                high,
+                negated,
+            })
+        } else if self.accept_keyword("LIKE") {
+            let pattern = match self.curr_token() {
+                Token::StringLiteral(pattern) => pattern.clone(),
+                token => fail!("Expected a string pattern after LIKE but found {:?}", token),
+            };
+            self.advance();
+            Ok(Predicate::Like {
+                column,
+                pattern,
+                negated,
+            })
+        } else {
+            fail!(
+                "Expected a comparison after column {column} but found {:?}",
+                self.curr_token()
+            )
         }
     }
 
-    fn read_and_return(&mut self, expected: &str, token: Token) -> Result<Token> {
-        let mut expected_chars = expected.chars();
-        let mut curr_char = self.input.chars().nth(self.char_position);
-        while let Some(expected_char) = expected_chars.next() {
-            match curr_char {
-                Some(c) => {
-                    if c.to_lowercase().next() != expected_char.to_lowercase().next() {
-                        bail!("Unexpected character")
+    fn parse_literal_value(&mut self) -> Result<SerialData> {
+        let negative = match self.curr_token() {
+            Token::Operator(op) if op == "+" || op == "-" => {
+                let negative = op == "-";
+                self.advance();
+                if !matches!(self.curr_token(), Token::NumericLiteral(_)) {
+                    fail!("Expected a number but found {:?}", self.curr_token());
+                }
+                negative
+            }
+            _ => false,
+        };
+
+        let value = match self.curr_token() {
+            Token::NumericLiteral(number) => parse_numeric_literal(number, negative)?,
+            Token::StringLiteral(text) => SerialData::Text(text.clone()),
+            // the lexer already checked the literal is an even number of hex digits
+            Token::BlobLiteral(hex) => SerialData::Blob(
+                (0..hex.len())
+                    .step_by(2)
+                    .filter_map(|idx| u8::from_str_radix(&hex[idx..idx + 2], 16).ok())
+                    .collect(),
+            ),
+            Token::Word(word) if word.eq_ignore_ascii_case("NULL") => SerialData::Null,
+            Token::Word(word) if word.eq_ignore_ascii_case("TRUE") => SerialData::I64(1),
+            Token::Word(word) if word.eq_ignore_ascii_case("FALSE") => SerialData::I64(0),
+            token => fail!("Expected a literal value but found {:?}", token),
+        };
+        self.advance();
+
+        Ok(value)
+    }
+
+    // returns the source text between a balanced pair of parens
+    fn parse_parenthesized_expr(&mut self) -> Result<String> {
+        let (start, end) = self.skip_parenthesized()?;
+        Ok(self.input[start..end].trim().to_string())
+    }
+
+    // skips a balanced pair of parens, returning the byte range of what is inside them
+    fn skip_parenthesized(&mut self) -> Result<(usize, usize)> {
+        self.expect(&Token::OpenParen)?;
+        let start = self.prev_end();
+        let mut depth = 1;
+        loop {
+            match self.curr_token() {
+                Token::OpenParen => depth += 1,
+                Token::CloseParen => {
+                    depth -= 1;
+                    if depth == 0 {
+                        let end = self.curr_start();
+                        self.advance();
+                        return Ok((start, end));
                     }
                 }
-                None => bail!("Length of expected string does not match the input string"),
+                Token::Eof => fail!("Unexpected end of input, missing closing )"),
+                _ => {}
             }
-            self.char_position += 1;
-            curr_char = self.input.chars().nth(self.char_position);
+            self.advance();
         }
-        Ok(token)
     }
 }
 
-// What is the BNF grammar for the create table statement
-fn parser(lexer: &mut Lexer) -> Result<()> {
-    todo!();
+// integer literals too large for an i64 are reals, hex literals are the 64 bit two's complement of their digits
+fn parse_numeric_literal(number: &str, negative: bool) -> Result<SerialData> {
+    if let Some(hex) = number.strip_prefix("0x") {
+        return match u64::from_str_radix(hex, 16) {
+            Ok(num) if negative => Ok(SerialData::I64((num as i64).wrapping_neg())),
+            Ok(num) => Ok(SerialData::I64(num as i64)),
+            Err(_) => fail!("Hex literal {number} is too big"),
+        };
+    }
+
+    let signed = if negative {
+        format!("-{number}")
+    } else {
+        number.to_string()
+    };
+    if let Ok(num) = signed.parse::<i64>() {
+        return Ok(SerialData::I64(num));
+    }
+    match signed.parse::<f64>() {
+        Ok(num) => Ok(SerialData::F64(num)),
+        Err(_) => fail!("Malformed number {number}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_simple_create_table() {
+        let columns = find_schema_from_create_stmt(
+            "CREATE TABLE RandomData (id INTEGER, name TEXT, age INTEGER)",
+        )
+        .unwrap();
+
+        let names = columns.iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["id", "name", "age"]);
+        assert_eq!(columns[1].declared_type.as_deref(), Some("TEXT"));
+        assert!(columns.iter().all(|c| c.constraints.is_empty()));
+    }
+
+    #[test]
+    fn test_parse_quoted_identifiers_and_sized_types() {
+        let stmt = r#"CREATE TABLE IF NOT EXISTS main."user ""data""" (
+            [first name] VARCHAR(255) NOT NULL,
+            `price` DECIMAL(10, -2) DEFAULT -1.5,
+            "no type",
+            payload UNSIGNED BIG INT
+        )"#;
+        let create = parse_create_table_stmt(stmt).unwrap();
+
+        assert!(create.if_not_exists);
+        assert_eq!(create.schema_name.as_deref(), Some("main"));
+        assert_eq!(create.table_name, "user \"data\"");
+        assert_eq!(create.columns[0].name, "first name");
+        assert_eq!(
+            create.columns[0].declared_type.as_deref(),
+            Some("VARCHAR(255)")
+        );
+        assert_eq!(
+            create.columns[0].constraints,
+            vec![ColumnConstraint::NotNull(None)]
+        );
+        assert_eq!(
+            create.columns[1].declared_type.as_deref(),
+            Some("DECIMAL(10, -2)")
+        );
+        assert_eq!(
+            create.columns[1].constraints,
+            vec![ColumnConstraint::Default("-1.5".to_string())]
+        );
+        assert_eq!(create.columns[2].declared_type, None);
+        assert_eq!(
+            create.columns[3].declared_type.as_deref(),
+            Some("UNSIGNED BIG INT")
+        );
+    }
+
+    #[test]
+    fn test_parse_constraints_and_table_options() {
+        let stmt = "CREATE TABLE orders (
+            id INTEGER PRIMARY KEY DESC AUTOINCREMENT,
+            customer_id INT CONSTRAINT fk REFERENCES customers(id) ON DELETE SET NULL DEFERRABLE INITIALLY DEFERRED,
+            note TEXT COLLATE NOCASE UNIQUE ON CONFLICT REPLACE CHECK (length(note) < 100),
+            total REAL GENERATED ALWAYS AS (price * (1 + tax)) STORED,
+            created TEXT DEFAULT (datetime('now')), -- trailing comment
+            PRIMARY KEY (id, customer_id DESC),
+            /* block comment */ FOREIGN KEY (customer_id) REFERENCES customers (id),
+            CHECK (total >= 0)
+        ) WITHOUT ROWID, STRICT;";
+        let create = parse_create_table_stmt(stmt).unwrap();
+
+        assert!(create.without_rowid);
+        assert!(create.strict);
+        assert_eq!(
+            create.columns[0].constraints,
+            vec![ColumnConstraint::PrimaryKey {
+                order: Some(SortOrder::Desc),
+                conflict: None,
+                autoincrement: true
+            }]
+        );
+        assert_eq!(
+            create.columns[1].constraints,
+            vec![
+                ColumnConstraint::Name("fk".to_string()),
+                ColumnConstraint::References(ForeignKeyClause {
+                    table: "customers".to_string(),
+                    columns: vec!["id".to_string()],
+                    on_delete: Some(ForeignKeyAction::SetNull),
+                    on_update: None,
+                    match_name: None,
+                    deferral: Some(Deferral {
+                        deferrable: true,
+                        initially_deferred: Some(true)
+                    }),
+                })
+            ]
+        );
+        assert_eq!(
+            create.columns[2].constraints,
+            vec![
+                ColumnConstraint::Collate("NOCASE".to_string()),
+                ColumnConstraint::Unique(Some(ConflictResolution::Replace)),
+                ColumnConstraint::Check("length(note) < 100".to_string()),
+            ]
+        );
+        assert_eq!(
+            create.columns[3].constraints,
+            vec![ColumnConstraint::Generated {
+                expr: "price * (1 + tax)".to_string(),
+                stored: true
+            }]
+        );
+        assert_eq!(
+            create.columns[4].constraints,
+            vec![ColumnConstraint::Default("(datetime('now'))".to_string())]
+        );
+        assert_eq!(create.constraints.len(), 3);
+        assert_eq!(
+            create.constraints[0],
+            TableConstraint::PrimaryKey(
+                vec![
+                    IndexedColumn {
+                        name: "id".to_string(),
+                        collation: None,
+                        order: None
+                    },
+                    IndexedColumn {
+                        name: "customer_id".to_string(),
+                        collation: None,
+                        order: Some(SortOrder::Desc)
+                    },
+                ],
+                None
+            )
+        );
+        assert_eq!(
+            create.constraints[2],
+            TableConstraint::Check("total >= 0".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_statements() {
+        assert!(parse_create_table_stmt("CREATE TABLE t").is_err());
+        assert!(parse_create_table_stmt("CREATE TABLE t ()").is_err());
+        assert!(parse_create_table_stmt("CREATE TABLE t (a INT,").is_err());
+        assert!(parse_create_table_stmt("CREATE TABLE t (a INT) garbage").is_err());
+        assert!(parse_create_table_stmt("CREATE TABLE t (a VARCHAR(abc))").is_err());
+        assert!(parse_create_table_stmt("CREATE TABLE t AS SELECT 1").is_err());
+    }
+
+    #[test]
+    fn test_parse_create_index_stmt() {
+        let create = parse_create_index_stmt(
+            "CREATE UNIQUE INDEX IF NOT EXISTS main.\"by name\" ON people (last COLLATE NOCASE, first DESC)",
+        )
+        .unwrap();
+        assert!(create.unique);
+        assert!(create.if_not_exists);
+        assert_eq!(create.schema_name.as_deref(), Some("main"));
+        assert_eq!(create.index_name, "by name");
+        assert_eq!(create.table_name, "people");
+        assert_eq!(
+            create.columns,
+            vec![
+                IndexedColumn {
+                    name: "last".to_string(),
+                    collation: Some("NOCASE".to_string()),
+                    order: None
+                },
+                IndexedColumn {
+                    name: "first".to_string(),
+                    collation: None,
+                    order: Some(SortOrder::Desc)
+                },
+            ]
+        );
+        assert_eq!(create.where_clause, None);
+
+        let partial =
+            parse_create_index_stmt("create index adults on people(age) where age >= 18;").unwrap();
+        assert!(!partial.unique);
+        assert_eq!(partial.where_clause.as_deref(), Some("age >= 18"));
+
+        assert!(parse_create_index_stmt("CREATE INDEX i ON t").is_err());
+        assert!(parse_create_index_stmt("CREATE INDEX i ON t (a) WHERE").is_err());
+        assert!(parse_create_index_stmt("CREATE INDEX i ON t (lower(a))").is_err());
+    }
+
+    #[test]
+    fn test_to_sql_reads_back_as_the_same_table() {
+        let stmt = r#"create table if not exists main."odd ""name""" (
+            id integer primary key desc,
+            [select] varchar(10) not null on conflict fail default 'x' collate nocase unique,
+            ref int constraint fk references other (id) on delete cascade on update set default match full
+                not deferrable initially immediate,
+            total real generated always as (id * 2) stored constraint unnamed,
+            constraint pk primary key (id, "select" desc) on conflict replace,
+            constraint positive check (total > 0),
+            foreign key (ref) references other deferrable
+        ) without rowid, strict"#;
+        let create = parse_create_table_stmt(stmt).unwrap();
+        let sql = create.to_sql();
+        assert!(sql.starts_with(
+            r#"CREATE TABLE "odd ""name""" ("id" integer PRIMARY KEY DESC, "select" "#
+        ));
+        assert!(sql.ends_with(") WITHOUT ROWID, STRICT"));
+        // nothing the statement says about its constraints is lost on the way
+        for clause in [
+            r#"NOT NULL ON CONFLICT FAIL"#,
+            r#"CONSTRAINT "fk" REFERENCES "other" ("id") ON DELETE CASCADE ON UPDATE SET DEFAULT MATCH "full" NOT DEFERRABLE INITIALLY IMMEDIATE"#,
+            r#"STORED CONSTRAINT "unnamed", CONSTRAINT "pk" PRIMARY KEY ("id", "select" DESC) ON CONFLICT REPLACE"#,
+            r#", CONSTRAINT "positive" CHECK (total > 0), "#,
+            r#"REFERENCES "other" DEFERRABLE)"#,
+        ] {
+            assert!(sql.contains(clause), "{clause} is missing from {sql}");
+        }
+
+        let read_back = parse_create_table_stmt(&sql).unwrap();
+        assert!(!read_back.if_not_exists);
+        assert_eq!(read_back.schema_name, None);
+        assert_eq!(read_back.table_name, create.table_name);
+        assert_eq!(read_back.constraints, create.constraints);
+        assert_eq!(read_back.without_rowid, create.without_rowid);
+        assert_eq!(read_back.strict, create.strict);
+        for (read_back, column) in read_back.columns.iter().zip(&create.columns) {
+            assert_eq!(read_back.name, column.name);
+            assert_eq!(read_back.declared_type, column.declared_type);
+            assert_eq!(read_back.constraints, column.constraints);
+        }
+        assert_eq!(read_back.to_sql(), sql);
+    }
 }
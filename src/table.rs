@@ -0,0 +1,1019 @@
+use std::cmp::Ordering;
+use std::ops::Bound;
+use std::rc::Rc;
+
+use crate::btree::Btree;
+use crate::database::TableInfo;
+use crate::error::{Result, StorageError};
+use crate::row::{default_value, is_virtual_column, Row, Rows};
+use crate::sorter::{Sorter, SORT_MEMORY_BUDGET};
+use crate::sql_data_types::{compare_records, SerialData};
+use crate::sql_parser::{
+    parse_create_index_stmt, ColumnConstraint, CreateIndexStmt, CreateTableStmt, IndexedColumn,
+    SortOrder, SqlColumn,
+};
+
+/*
+ * A table together with its parsed schema and the indices defined on it, the entry point for reading rows.
+ * Everything handed back is a Row bound to the schema, callers never have to deal with raw records or column order.
+ */
+pub struct Table {
+    info: TableInfo,
+    schema: Rc<CreateTableStmt>,
+    btree: Btree,
+    indices: Vec<(TableInfo, Btree)>,
+}
+
+// an index of the table as writes see it, the positions of the row values its entries are made of
+struct WritableIndex<'a> {
+    btree: &'a Btree,
+    columns: Vec<usize>,
+    unique: bool,
+}
+
+impl WritableIndex<'_> {
+    fn key(&self, row: &Row) -> Vec<SerialData> {
+        index_key(&self.columns, row)
+    }
+}
+
+impl Table {
+    pub(crate) fn new(
+        info: TableInfo,
+        schema: CreateTableStmt,
+        btree: Btree,
+        indices: Vec<(TableInfo, Btree)>,
+    ) -> Self {
+        Table {
+            info,
+            schema: Rc::new(schema),
+            btree,
+            indices,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.info.name
+    }
+
+    pub fn schema(&self) -> &CreateTableStmt {
+        &self.schema
+    }
+
+    // names of the indices defined on the table
+    pub fn index_names(&self) -> Vec<&str> {
+        self.indices
+            .iter()
+            .map(|(info, _)| info.name.as_str())
+            .collect()
+    }
+
+    // every row of the table in rowid order
+    pub fn scan(&self) -> Rows {
+        Rows::new(self.schema.clone(), self.btree.iter())
+    }
+
+    pub fn get(&self, rowid: i64) -> Result<Option<Row>> {
+        match self.btree.get_by_rowid(rowid)? {
+            Some(mut record) => Ok(Some(Row::new(
+                self.schema.clone(),
+                rowid,
+                record.read_record()?,
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    // the rows whose rowid falls within the bounds, in rowid order
+    pub fn scan_rowid_range(
+        &self,
+        lower_bound: Bound<i64>,
+        upper_bound: Bound<i64>,
+    ) -> Result<impl Iterator<Item = Result<Row>>> {
+        let schema = self.schema.clone();
+        let rows = self.btree.scan_rowid_range(lower_bound, upper_bound)?;
+        Ok(rows.map(move |row| {
+            let (rowid, mut record) = row?;
+            Ok(Row::new(schema.clone(), rowid, record.read_record()?))
+        }))
+    }
+
+    /*
+     * Resolves an equality filter on a column through an index of the table that leads with that column.
+     * The index hands back the rowids of the matching rows, which are then looked up one at a time in the table btree,
+     * so only the matching rows are ever read. Returns None when no index of the table leads with the column.
+     */
+    pub fn find_by_index(&self, column_name: &str, value: &SerialData) -> Result<Option<Vec<Row>>> {
+        // a partial index misses rows and one in another order than the table values cannot be searched by them
+        let index = self.indices.iter().find(|(info, _)| {
+            self.index_definition(info).is_ok_and(|definition| {
+                definition.where_clause.is_none()
+                    && definition.columns.first().is_some_and(|first| {
+                        first.name.eq_ignore_ascii_case(column_name)
+                            && self.indexed_column_index(&info.name, first).is_ok()
+                    })
+            })
+        });
+
+        let index = match index {
+            Some((_, index)) => index,
+            None => return Ok(None),
+        };
+
+        // the index stores values the way the column's affinity coerced them, so the filter value has to match that
+        let column = &self.schema.columns[self.column_index(column_name)?];
+        let value = column.affinity.apply(value.clone());
+
+        let mut rows = Vec::new();
+        for (_, rowid) in index.index_seek(&[value])? {
+            match self.get(rowid)? {
+                Some(row) => rows.push(row),
+                None => {
+                    return Err(StorageError::corrupt(format!(
+                        "index entry points at missing rowid {rowid} in {}",
+                        self.name()
+                    )))
+                }
+            }
+        }
+
+        Ok(Some(rows))
+    }
+
+    /*
+     * Insert(Table, Fields, Values): inserts a row given values for some of its columns, the others take their default.
+     * Values are converted to the affinity of their column first, like SQLite does before storing them.
+     * The row goes under the value of the INTEGER PRIMARY KEY column when one is given and otherwise under one past the
+     * largest rowid in the table, which is how SQLite picks rowids for tables without AUTOINCREMENT.
+     * When one of the indices cannot take the entry of the row, the entries already made and the row itself are
+     * deleted again, so a failed insert does not leave a row behind that its indices know nothing about.
+     * Returns the rowid of the new row.
+     */
+    pub fn insert(&self, columns: &[&str], values: Vec<SerialData>) -> Result<i64> {
+        let indices = self.writable_indices()?;
+        let given = self.given_values(columns, values)?;
+        let (rowid, record) = self.build_record(given)?;
+
+        let rowid = match rowid {
+            Some(rowid) => rowid,
+            None => match self.btree.max_rowid()? {
+                Some(max_rowid) => max_rowid.checked_add(1).ok_or_else(|| {
+                    StorageError::InvalidOperation(format!(
+                        "{} has used up every rowid",
+                        self.name()
+                    ))
+                })?,
+                None => 1,
+            },
+        };
+
+        let row = Row::new(self.schema.clone(), rowid, record.clone());
+        self.check_unique(&indices, &row, None)?;
+        self.btree.insert(rowid, &record)?;
+        for (n, index) in indices.iter().enumerate() {
+            if let Err(err) = index.btree.insert_index_entry(&index.key(&row)) {
+                // the index that failed may hold the entry too if it failed part way through a split
+                for index in &indices[..=n] {
+                    index.btree.delete_index_entry(&index.key(&row))?;
+                }
+                self.btree.delete(rowid)?;
+                return Err(err);
+            }
+        }
+        Ok(rowid)
+    }
+
+    /*
+     * Update(Table, Rowid, Fields, Values): sets some columns of a row to new values, the others keep the ones they had.
+     * Values go through the same affinity and constraint checks as on insert. Setting the INTEGER PRIMARY KEY column
+     * moves the row to the new rowid, which must not be taken. Returns false when there is no row with the rowid.
+     * Like an insert, an update whose index writes fail part way is undone, the index entries already moved go back
+     * and the row gets its old record and rowid again.
+     */
+    pub fn update(&self, rowid: i64, columns: &[&str], values: Vec<SerialData>) -> Result<bool> {
+        let indices = self.writable_indices()?;
+        let changes = self.given_values(columns, values)?;
+        let old_record = match self.btree.get_by_rowid(rowid)? {
+            Some(mut record) => record.read_record()?,
+            None => return Ok(false),
+        };
+        let row = Row::new(self.schema.clone(), rowid, old_record.clone());
+
+        let given = self
+            .schema
+            .columns
+            .iter()
+            .zip(row.values())
+            .zip(changes)
+            .map(|((column, current), change)| match change {
+                Some(value) => Some(value),
+                // generated columns are never given a value, they are rejected by build_record() like on insert
+                None if is_generated_column(column) => None,
+                None => Some(current.clone()),
+            })
+            .collect();
+        let (new_rowid, record) = self.build_record(given)?;
+        let new_rowid = match new_rowid {
+            Some(new_rowid) => new_rowid,
+            None if self.schema.rowid_alias_column().is_some() => {
+                return Err(StorageError::TypeMismatch(format!(
+                    "the rowid of {} cannot be set to NULL",
+                    self.name()
+                )))
+            }
+            None => rowid,
+        };
+
+        let new_row = Row::new(self.schema.clone(), new_rowid, record.clone());
+        self.check_unique(&indices, &new_row, Some(rowid))?;
+        if new_rowid != rowid {
+            // insert first, so a taken rowid leaves the row where it was
+            self.btree.insert(new_rowid, &record)?;
+            if let Err(err) = self.btree.delete(rowid) {
+                self.btree.delete(new_rowid)?;
+                return Err(err);
+            }
+        } else {
+            self.btree.update(rowid, &record)?;
+        }
+
+        if let Err(err) = self.move_index_entries(&indices, &row, &new_row) {
+            // the old row goes back in before the new one goes, so the row is never lost
+            if new_rowid != rowid {
+                self.btree.insert(rowid, &old_record)?;
+                self.btree.delete(new_rowid)?;
+            } else {
+                self.btree.update(rowid, &old_record)?;
+            }
+            return Err(err);
+        }
+        Ok(true)
+    }
+
+    // deletes the row with the given rowid, returns whether there was such a row
+    pub fn delete(&self, rowid: i64) -> Result<bool> {
+        let indices = self.writable_indices()?;
+        let row = match self.get(rowid)? {
+            Some(row) => row,
+            None => return Ok(false),
+        };
+
+        self.btree.delete(rowid)?;
+        for index in &indices {
+            self.delete_index_entry(index, &index.key(&row))?;
+        }
+        Ok(true)
+    }
+
+    // the values given for each column of the table, in column order, checking the columns named exist
+    fn given_values(
+        &self,
+        columns: &[&str],
+        values: Vec<SerialData>,
+    ) -> Result<Vec<Option<SerialData>>> {
+        if columns.len() != values.len() {
+            return Err(StorageError::InvalidArgument(format!(
+                "{} columns given for {} values",
+                columns.len(),
+                values.len()
+            )));
+        }
+
+        let mut given = vec![None; self.schema.columns.len()];
+        for (column_name, value) in columns.iter().zip(values) {
+            let idx = self.column_index(column_name)?;
+            if given[idx].is_some() {
+                return Err(StorageError::InvalidArgument(format!(
+                    "column {column_name} is given more than once"
+                )));
+            }
+            given[idx] = Some(value);
+        }
+
+        Ok(given)
+    }
+
+    /*
+     * Builds the record stored for a row from a value, or None for the column default, for every column. Returns the
+     * value of the INTEGER PRIMARY KEY column separately, it is stored as NULL since it is the key of the row.
+     */
+    fn build_record(
+        &self,
+        given: Vec<Option<SerialData>>,
+    ) -> Result<(Option<i64>, Vec<SerialData>)> {
+        let rowid_alias_column = self.schema.rowid_alias_column();
+        let mut rowid = None;
+        let mut record = Vec::new();
+        for (idx, (column, value)) in self.schema.columns.iter().zip(given).enumerate() {
+            if is_generated_column(column) {
+                if value.is_some() || !is_virtual_column(column) {
+                    return Err(StorageError::InvalidOperation(format!(
+                        "generated column {} cannot be written",
+                        column.name
+                    )));
+                }
+                continue;
+            }
+
+            let value = column
+                .affinity
+                .apply(value.unwrap_or_else(|| default_value(column)));
+
+            // the rowid alias is stored as NULL, its value is the key of the row
+            if rowid_alias_column == Some(idx) {
+                rowid = match (&value, value.as_integer()) {
+                    (SerialData::Null, _) => None,
+                    (_, Some(num)) => Some(num),
+                    (value, None) => {
+                        return Err(StorageError::TypeMismatch(format!(
+                            "{value:?} cannot be used as the rowid of {}",
+                            self.name()
+                        )))
+                    }
+                };
+                record.push(SerialData::Null);
+                continue;
+            }
+
+            let not_null = column
+                .constraints
+                .iter()
+                .any(|constraint| matches!(constraint, ColumnConstraint::NotNull(_)));
+            if not_null && matches!(value, SerialData::Null) {
+                return Err(StorageError::Constraint(format!(
+                    "NOT NULL constraint failed: {}.{}",
+                    self.name(),
+                    column.name
+                )));
+            }
+            record.push(value);
+        }
+
+        Ok((rowid, record))
+    }
+
+    /*
+     * The indices of the table, which every write has to keep up to date. Only indices over plain columns in BINARY
+     * ascending order can be maintained, writes to a table with any other kind of index are refused rather than
+     * leaving that index behind.
+     */
+    fn writable_indices(&self) -> Result<Vec<WritableIndex<'_>>> {
+        if self.schema.without_rowid {
+            return Err(StorageError::InvalidOperation(format!(
+                "{} is a WITHOUT ROWID table, which cannot be written yet",
+                self.name()
+            )));
+        }
+
+        let mut indices = Vec::new();
+        for (info, btree) in &self.indices {
+            let definition = self.index_definition(info)?;
+            if definition.where_clause.is_some() {
+                return Err(StorageError::InvalidOperation(format!(
+                    "{} is a partial index, which cannot be kept up to date yet",
+                    info.name
+                )));
+            }
+
+            let columns = definition
+                .columns
+                .iter()
+                .map(|column| self.indexed_column_index(&info.name, column))
+                .collect::<Result<_>>()?;
+            indices.push(WritableIndex {
+                btree,
+                columns,
+                unique: definition.unique,
+            });
+        }
+
+        Ok(indices)
+    }
+
+    /*
+     * What an index of the table is made of. The indices SQLite creates itself for UNIQUE and PRIMARY KEY constraints
+     * have no SQL of their own, their columns come from the constraint they were made for.
+     */
+    fn index_definition(&self, info: &TableInfo) -> Result<CreateIndexStmt> {
+        if !info.sql.is_empty() {
+            return parse_create_index_stmt(&info.sql)
+                .map_err(|err| StorageError::SchemaError(format!("{}: {err}", info.name)));
+        }
+
+        let columns = info
+            .name
+            .strip_prefix(&format!("sqlite_autoindex_{}_", self.name()))
+            .and_then(|n| n.parse::<usize>().ok())
+            .and_then(|n| {
+                self.schema
+                    .autoindex_columns()
+                    .get(n.wrapping_sub(1))
+                    .cloned()
+            })
+            .ok_or_else(|| {
+                StorageError::SchemaError(format!(
+                    "{} matches no constraint of {}",
+                    info.name,
+                    self.name()
+                ))
+            })?;
+        Ok(CreateIndexStmt {
+            unique: true,
+            if_not_exists: false,
+            schema_name: None,
+            index_name: info.name.clone(),
+            table_name: self.name().to_string(),
+            columns,
+            where_clause: None,
+        })
+    }
+
+    /*
+     * The entries of a new index over the table in key order, ready to build the index from. The rows are read once
+     * and sorted, in memory as long as they fit and through temporary files on disk once they do not. For a UNIQUE
+     * index the iteration fails when two rows share a key without a NULL in it.
+     */
+    pub(crate) fn sorted_index_entries(
+        &self,
+        definition: &CreateIndexStmt,
+    ) -> Result<impl Iterator<Item = Result<Vec<SerialData>>>> {
+        let index_name = &definition.index_name;
+        if self.schema.without_rowid {
+            return Err(StorageError::InvalidOperation(format!(
+                "{} is a WITHOUT ROWID table, which cannot be indexed yet",
+                self.name()
+            )));
+        }
+        if definition.where_clause.is_some() {
+            return Err(StorageError::InvalidOperation(format!(
+                "{index_name} is a partial index, which cannot be kept up to date yet"
+            )));
+        }
+        let columns = definition
+            .columns
+            .iter()
+            .map(|column| self.indexed_column_index(index_name, column))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut sorter = Sorter::new(SORT_MEMORY_BUDGET);
+        for row in self.scan() {
+            sorter.push(index_key(&columns, &row?))?;
+        }
+
+        let violation = definition.unique.then(|| self.unique_violation(&columns));
+        let mut previous_key: Option<Vec<SerialData>> = None;
+        Ok(sorter.finish()?.map(move |entry| {
+            let entry = entry?;
+            if let Some(violation) = &violation {
+                // equal keys are next to each other once sorted, the rowid at the end is what tells the rows apart
+                let key = &entry[..entry.len() - 1];
+                let collides = previous_key.as_ref().is_some_and(|previous| {
+                    !key.iter().any(|value| matches!(value, SerialData::Null))
+                        && compare_records(previous, key) == Ordering::Equal
+                });
+                if collides {
+                    return Err(StorageError::Constraint(violation.clone()));
+                }
+                previous_key = Some(key.to_vec());
+            }
+            Ok(entry)
+        }))
+    }
+
+    // the position of a column an index is made of, refusing what writes cannot keep in the order the index has
+    fn indexed_column_index(&self, index_name: &str, column: &IndexedColumn) -> Result<usize> {
+        let unsupported = |what: &str| {
+            Err(StorageError::InvalidOperation(format!(
+                "{index_name} {what}, which cannot be kept up to date yet"
+            )))
+        };
+
+        let idx = match self.schema.column_index(&column.name) {
+            Some(idx) => idx,
+            None => return unsupported(&format!("indexes the expression {}", column.name)),
+        };
+        let table_column = &self.schema.columns[idx];
+        let column_collation =
+            table_column
+                .constraints
+                .iter()
+                .find_map(|constraint| match constraint {
+                    ColumnConstraint::Collate(collation) => Some(collation),
+                    _ => None,
+                });
+        let collation = column.collation.as_ref().or(column_collation);
+
+        if is_generated_column(table_column) {
+            return unsupported(&format!(
+                "indexes the generated column {}",
+                table_column.name
+            ));
+        }
+        if collation.is_some_and(|collation| !collation.eq_ignore_ascii_case("BINARY")) {
+            return unsupported(&format!(
+                "orders {} by a collating sequence",
+                table_column.name
+            ));
+        }
+        if column.order == Some(SortOrder::Desc) {
+            return unsupported(&format!("orders {} descending", table_column.name));
+        }
+        Ok(idx)
+    }
+
+    /*
+     * Checks a row does not collide with another one in any UNIQUE index. Like in SQLite NULLs are distinct, an entry
+     * with a NULL among its key columns never collides. own_rowid is the rowid the row had before an update, the
+     * entries of the row itself are no collision.
+     */
+    fn check_unique(
+        &self,
+        indices: &[WritableIndex],
+        row: &Row,
+        own_rowid: Option<i64>,
+    ) -> Result<()> {
+        for index in indices.iter().filter(|index| index.unique) {
+            let mut key = index.key(row);
+            key.pop();
+            if key.iter().any(|value| matches!(value, SerialData::Null)) {
+                continue;
+            }
+
+            let collides = index
+                .btree
+                .index_seek(&key)?
+                .iter()
+                .any(|(_, rowid)| Some(*rowid) != own_rowid);
+            if collides {
+                return Err(StorageError::Constraint(
+                    self.unique_violation(&index.columns),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // how SQLite reports two rows sharing a key in a UNIQUE index over the columns
+    fn unique_violation(&self, columns: &[usize]) -> String {
+        let columns: Vec<String> = columns
+            .iter()
+            .map(|idx| format!("{}.{}", self.name(), self.schema.columns[*idx].name))
+            .collect();
+        format!("UNIQUE constraint failed: {}", columns.join(", "))
+    }
+
+    /*
+     * Moves the entries of a row from its old key to its new one in every index where the key changed. When one of
+     * the moves fails the ones done so far are reversed, the failed one included since it may have got half way.
+     */
+    fn move_index_entries(
+        &self,
+        indices: &[WritableIndex],
+        row: &Row,
+        new_row: &Row,
+    ) -> Result<()> {
+        let changed = indices
+            .iter()
+            .map(|index| (index, index.key(row), index.key(new_row)))
+            .filter(|(_, old_key, new_key)| compare_records(old_key, new_key) != Ordering::Equal)
+            .collect::<Vec<_>>();
+
+        for (n, (index, old_key, new_key)) in changed.iter().enumerate() {
+            let moved = self
+                .delete_index_entry(index, old_key)
+                .and_then(|_| index.btree.insert_index_entry(new_key));
+            if let Err(err) = moved {
+                for (index, old_key, new_key) in changed[..=n].iter().rev() {
+                    index.btree.delete_index_entry(new_key)?;
+                    // the old entry is still there when the move failed before deleting it
+                    match index.btree.insert_index_entry(old_key) {
+                        Ok(()) | Err(StorageError::Constraint(_)) => {}
+                        Err(err) => return Err(err),
+                    }
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    fn delete_index_entry(&self, index: &WritableIndex, key: &[SerialData]) -> Result<()> {
+        if !index.btree.delete_index_entry(key)? {
+            return Err(StorageError::corrupt(format!(
+                "row {:?} of {} is missing from one of its indices",
+                key.last(),
+                self.name()
+            )));
+        }
+        Ok(())
+    }
+
+    fn column_index(&self, column_name: &str) -> Result<usize> {
+        self.schema.column_index(column_name).ok_or_else(|| {
+            StorageError::NotFound(format!("Column {column_name} in table {}", self.name()))
+        })
+    }
+}
+
+// the entry a row has in an index over the columns at the given positions, its key values followed by its rowid
+fn index_key(columns: &[usize], row: &Row) -> Vec<SerialData> {
+    let mut key: Vec<SerialData> = columns
+        .iter()
+        .map(|idx| row.values()[*idx].clone())
+        .collect();
+    key.push(SerialData::I64(row.rowid()));
+    key
+}
+
+fn is_generated_column(column: &SqlColumn) -> bool {
+    column
+        .constraints
+        .iter()
+        .any(|constraint| matches!(constraint, ColumnConstraint::Generated { .. }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::btree::tests::write_database;
+    use crate::database::{CreateOptions, Database};
+
+    // a new database in the temp directory holding just the table the statement creates
+    fn create_table(name: &str, sql: &str) -> (String, Database, Table) {
+        let path = std::env::temp_dir().join(format!("{name}-{}.db", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let _ = std::fs::remove_file(&path);
+        let database = Database::create(&path, CreateOptions::default()).unwrap();
+        let table = database
+            .create_table(&CreateTableStmt::parse(sql).unwrap())
+            .unwrap();
+        (path, database, table)
+    }
+
+    fn rowids(rows: &[Row]) -> Vec<i64> {
+        rows.iter().map(|row| row.rowid()).collect()
+    }
+
+    // the message of the constraint violation a write failed with
+    fn violation<T: std::fmt::Debug>(result: Result<T>) -> String {
+        match result {
+            Err(StorageError::Constraint(message)) => message,
+            other => panic!("expected a constraint violation, got {other:?}"),
+        }
+    }
+
+    // checks every index of the table holds exactly one entry for each row, made of the values the row has now
+    fn check_indices(table: &Table) {
+        let rows = table.scan().collect::<Result<Vec<_>>>().unwrap();
+        for index in table.writable_indices().unwrap() {
+            let mut expected = rows.iter().map(|row| index.key(row)).collect::<Vec<_>>();
+            expected.sort_by(|a, b| compare_records(a, b));
+            let entries = index
+                .btree
+                .iter()
+                .map(|entry| entry.unwrap().read_record().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(entries.len(), expected.len());
+            assert!(entries
+                .iter()
+                .zip(&expected)
+                .all(|(a, b)| compare_records(a, b) == Ordering::Equal));
+        }
+    }
+
+    #[test]
+    fn test_find_by_index_resolves_equalities_through_an_index() {
+        let (path, database, table) = create_table(
+            "find-by-index",
+            "CREATE TABLE people (id INTEGER PRIMARY KEY, email TEXT UNIQUE, age INTEGER)",
+        );
+        for n in 1..=300 {
+            let values = vec![
+                SerialData::Text(format!("person{n}@example.com")),
+                SerialData::I64(n % 10),
+            ];
+            table.insert(&["email", "age"], values).unwrap();
+        }
+        let stmt = CreateIndexStmt::parse("CREATE INDEX by_age ON people (age)").unwrap();
+        database.create_index(&stmt).unwrap();
+        let table = database.table("people").unwrap();
+
+        // the text '5' is stored as the integer 5 in an INTEGER column, so it has to be looked up as one
+        let rows = table
+            .find_by_index("age", &SerialData::Text("5".to_string()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(rowids(&rows), (5..=300).step_by(10).collect::<Vec<_>>());
+        assert!(rows.iter().all(|row| row.get::<i64>("age").unwrap() == 5));
+        let rows = table.find_by_index("age", &SerialData::I64(42)).unwrap();
+        assert!(rows.unwrap().is_empty());
+
+        // the index of the UNIQUE constraint has no SQL, its columns come from the constraint
+        let autoindex = &table.indices[0].0;
+        assert_eq!(autoindex.name, "sqlite_autoindex_people_1");
+        assert!(autoindex.sql.is_empty());
+        let email = SerialData::Text("person7@example.com".to_string());
+        let rows = table.find_by_index("EMAIL", &email).unwrap().unwrap();
+        assert_eq!(rowids(&rows), vec![7]);
+        assert!(table
+            .find_by_index("id", &SerialData::I64(7))
+            .unwrap()
+            .is_none());
+
+        // a partial index misses the rows outside its WHERE clause, so it is never used
+        let (mut partial, btree) = table
+            .indices
+            .iter()
+            .find(|(info, _)| info.name == "by_age")
+            .cloned()
+            .unwrap();
+        partial.sql = "CREATE INDEX by_age ON people (age) WHERE age > 3".to_string();
+        let schema = table.schema().clone();
+        let table = Table::new(
+            table.info.clone(),
+            schema,
+            table.btree.clone(),
+            vec![(partial, btree)],
+        );
+        assert!(table
+            .find_by_index("age", &SerialData::I64(5))
+            .unwrap()
+            .is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_find_by_index_seeks_through_a_multi_level_index() {
+        // row r holds the text of r / 2 in the indexed column a, so every value but the first belongs to two rows
+        let path = write_database("find-by-index", &(1..=600).collect::<Vec<_>>());
+        let table = Database::from_file(&path).unwrap().table("t").unwrap();
+        for value in 0..=300 {
+            let text = SerialData::Text(format!("{value:05}"));
+            let rows = table.find_by_index("A", &text).unwrap().unwrap();
+            let expected = (value * 2..=value * 2 + 1)
+                .filter(|rowid| (1..=600).contains(rowid))
+                .collect::<Vec<i64>>();
+            assert_eq!(
+                rows.iter().map(|row| row.rowid()).collect::<Vec<_>>(),
+                expected
+            );
+            for row in rows {
+                assert_eq!(row.get::<i64>("b").unwrap(), row.rowid());
+            }
+        }
+
+        let missing = SerialData::Text("99999".to_string());
+        let rows = table.find_by_index("a", &missing).unwrap();
+        assert!(rows.unwrap().is_empty());
+        // no index of t leads with b, so the filter has to be resolved by a scan instead
+        assert!(table
+            .find_by_index("b", &SerialData::I64(1))
+            .unwrap()
+            .is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_update_keeps_secondary_indices_in_step() {
+        let (path, database, _) = create_table(
+            "update-indices",
+            "CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT, qty INT, note TEXT, extra)",
+        );
+        for sql in [
+            "CREATE INDEX by_name_qty ON items (name, qty)",
+            "CREATE INDEX by_note ON items (note)",
+        ] {
+            database
+                .create_index(&CreateIndexStmt::parse(sql).unwrap())
+                .unwrap();
+        }
+        let table = database.table("items").unwrap();
+        for n in 1..=200 {
+            let values = vec![
+                SerialData::Text(format!("item {}", n % 20)),
+                SerialData::I64(n),
+                SerialData::Text(format!("note {n}")),
+            ];
+            table.insert(&["name", "qty", "note"], values).unwrap();
+        }
+
+        // a key that changes, one that grows onto overflow pages and columns no index holds
+        for n in (1..=200).step_by(3) {
+            let values = vec![SerialData::I64(n * 10), SerialData::Null];
+            assert!(table.update(n, &["qty", "note"], values).unwrap());
+        }
+        for n in (2..=200).step_by(5) {
+            let note = SerialData::Text(format!("{n} ").repeat(1000));
+            assert!(table.update(n, &["note"], vec![note]).unwrap());
+        }
+        for n in (3..=200).step_by(7) {
+            let extra = SerialData::Blob(vec![n as u8; 20]);
+            assert!(table.update(n, &["extra"], vec![extra]).unwrap());
+        }
+        check_indices(&table);
+
+        // moving rows to new rowids takes their index entries along
+        for n in (4..=200).step_by(11) {
+            assert!(table
+                .update(n, &["id"], vec![SerialData::I64(n + 1000)])
+                .unwrap());
+        }
+        check_indices(&table);
+        let note = SerialData::Text("note 15".to_string());
+        let rows = table.find_by_index("note", &note).unwrap().unwrap();
+        assert_eq!(rowids(&rows), vec![1015]);
+        let rows = table
+            .find_by_index("name", &SerialData::Text("item 4".to_string()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(rowids(&rows).len(), 10);
+        assert!(!table.update(4, &["qty"], vec![SerialData::I64(1)]).unwrap());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_writes_keep_indices_in_step_with_the_table() {
+        let (path, database, _) = create_table(
+            "write-indices",
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT UNIQUE, team TEXT, bio TEXT)",
+        );
+        let stmt = CreateIndexStmt::parse("CREATE INDEX by_team_bio ON users (team, bio)").unwrap();
+        database.create_index(&stmt).unwrap();
+        let table = database.table("users").unwrap();
+        assert_eq!(table.index_names().len(), 2);
+
+        // every ninth bio spills its index entries onto overflow pages
+        for n in 1..=300 {
+            let len = if n % 9 == 0 { 2000 } else { 10 };
+            let values = vec![
+                SerialData::Text(format!("user{n}@example.com")),
+                SerialData::Text(format!("team {}", n % 7)),
+                SerialData::Text("b".repeat(len)),
+            ];
+            table.insert(&["email", "team", "bio"], values).unwrap();
+        }
+        check_indices(&table);
+
+        for n in (1..=300).step_by(4) {
+            let team = SerialData::Text(format!("team {}", n % 5));
+            assert!(table.update(n, &["team"], vec![team]).unwrap());
+        }
+        for n in (2..=300).step_by(6) {
+            let email = SerialData::Text(format!("user{n}@example.com"));
+            assert!(table.update(n, &["email"], vec![email]).unwrap());
+        }
+        for n in (3..=300).step_by(15) {
+            assert!(table.update(n, &["id"], vec![SerialData::I64(-n)]).unwrap());
+        }
+        check_indices(&table);
+
+        for n in (1..=300).filter(|n| n % 3 != 0) {
+            assert!(table.delete(n).unwrap());
+        }
+        assert!(!table.delete(1).unwrap());
+        check_indices(&table);
+        assert_eq!(table.scan().count(), 100);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_unique_violations_change_nothing() {
+        let (path, _, table) = create_table(
+            "unique",
+            "CREATE TABLE seats (id INTEGER PRIMARY KEY, email TEXT UNIQUE, row INT, seat INT, UNIQUE (row, seat))",
+        );
+        let insert = |email: SerialData, row: SerialData, seat: SerialData| {
+            table.insert(&["email", "row", "seat"], vec![email, row, seat])
+        };
+        let text = |text: &str| SerialData::Text(text.to_string());
+        insert(
+            text("a@example.com"),
+            SerialData::I64(1),
+            SerialData::I64(1),
+        )
+        .unwrap();
+        insert(
+            text("b@example.com"),
+            SerialData::I64(1),
+            SerialData::I64(2),
+        )
+        .unwrap();
+
+        let message = violation(insert(
+            text("a@example.com"),
+            SerialData::I64(2),
+            SerialData::I64(2),
+        ));
+        assert_eq!(message, "UNIQUE constraint failed: seats.email");
+        // the integer 1 and the text '1' in an INTEGER column are the same value
+        let message = violation(insert(text("c@example.com"), text("1"), SerialData::I64(2)));
+        assert_eq!(message, "UNIQUE constraint failed: seats.row, seats.seat");
+        violation(table.update(2, &["seat"], vec![SerialData::I64(1)]));
+        violation(table.update(2, &["email"], vec![text("a@example.com")]));
+        // a row colliding with itself is no collision
+        assert!(table
+            .update(2, &["email"], vec![text("b@example.com")])
+            .unwrap());
+
+        assert_eq!(table.scan().count(), 2);
+        let row = table.get(2).unwrap().unwrap();
+        assert_eq!(row.get::<String>("email").unwrap(), "b@example.com");
+        assert_eq!(row.get::<i64>("seat").unwrap(), 2);
+        check_indices(&table);
+
+        // NULLs are distinct from each other, even in one column of a key
+        for _ in 0..3 {
+            insert(SerialData::Null, SerialData::I64(5), SerialData::Null).unwrap();
+        }
+        insert(SerialData::Null, SerialData::Null, SerialData::I64(1)).unwrap();
+        assert!(table.update(1, &["email"], vec![SerialData::Null]).unwrap());
+        assert_eq!(table.scan().count(), 6);
+        check_indices(&table);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_failed_index_write_takes_the_row_back_out() {
+        let (path, database, _) = create_table("undo-insert", "CREATE TABLE tags (name TEXT)");
+        let stmt = CreateIndexStmt::parse("CREATE INDEX by_name ON tags (name)").unwrap();
+        database.create_index(&stmt).unwrap();
+        let table = database.table("tags").unwrap();
+        table
+            .insert(&["name"], vec![SerialData::Text("a".to_string())])
+            .unwrap();
+
+        // the same index listed twice, so the second entry of every insert collides with the first
+        let index = table.indices[0].clone();
+        let table = Table::new(
+            table.info.clone(),
+            table.schema().clone(),
+            table.btree.clone(),
+            vec![index.clone(), index],
+        );
+        let result = table.insert(&["name"], vec![SerialData::Text("b".to_string())]);
+        assert!(matches!(result, Err(StorageError::Constraint(_))));
+        assert!(table.get(2).unwrap().is_none());
+        assert_eq!(
+            rowids(&table.scan().collect::<Result<Vec<_>>>().unwrap()),
+            vec![1]
+        );
+        assert_eq!(table.indices[0].1.iter().count(), 1);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_failed_index_write_undoes_an_update() {
+        let (path, database, table) = create_table(
+            "undo-update",
+            "CREATE TABLE tags (id INTEGER PRIMARY KEY, name TEXT, color TEXT)",
+        );
+        for (n, name) in ["a", "b", "c"].iter().enumerate() {
+            let values = vec![
+                SerialData::Text(name.to_string()),
+                SerialData::I64(n as i64),
+            ];
+            table.insert(&["name", "color"], values).unwrap();
+        }
+        for sql in [
+            "CREATE INDEX by_name ON tags (name)",
+            "CREATE INDEX by_color ON tags (color)",
+        ] {
+            database
+                .create_index(&CreateIndexStmt::parse(sql).unwrap())
+                .unwrap();
+        }
+        let table = database.table("tags").unwrap();
+
+        // by_name listed twice, so moving the entry the second time finds it gone and fails after by_color moved
+        let mut indices = table.indices.clone();
+        indices.push(indices[0].clone());
+        let doubled = Table::new(
+            table.info.clone(),
+            table.schema().clone(),
+            table.btree.clone(),
+            indices,
+        );
+        let changes = || {
+            vec![
+                SerialData::Text("z".to_string()),
+                SerialData::Text("red".to_string()),
+            ]
+        };
+        let result = doubled.update(2, &["name", "color"], changes());
+        assert!(matches!(result, Err(StorageError::Corrupt { .. })));
+        let result = doubled.update(2, &["id", "name", "color"], {
+            let mut values = changes();
+            values.insert(0, SerialData::I64(20));
+            values
+        });
+        assert!(matches!(result, Err(StorageError::Corrupt { .. })));
+
+        assert!(table.get(20).unwrap().is_none());
+        let row = table.get(2).unwrap().unwrap();
+        assert_eq!(row.get::<String>("name").unwrap(), "b");
+        assert_eq!(row.get::<String>("color").unwrap(), "1");
+        assert_eq!(table.scan().count(), 3);
+        check_indices(&table);
+        std::fs::remove_file(path).unwrap();
+    }
+}